@@ -3,9 +3,9 @@ mod error;
 use jieto_web::job::ScheduledTask;
 use actix_web::{get, web};
 use deadpool_redis::redis::cmd;
-use jieto_web::{ApiResult, AppInitializing, AppState, Application, JietoResult};
+use jieto_web::{ApiResult, AppInitializing, Application, Db, JietoResult};
 use serde::Serialize;
-use sqlx::FromRow;
+use sqlx::{FromRow, MySqlPool};
 use jieto_macros::{scheduled, task};
 
 #[derive(FromRow, Debug, Serialize)]
@@ -17,19 +17,17 @@ pub struct User {
 }
 
 #[get("/")]
-async fn hello(data: web::Data<AppState>) -> JietoResult<User> {
-    let pool = data.db_manager.with_mysql_default()?;
+async fn hello(pool: Db<MySqlPool>) -> JietoResult<User> {
     let result = sqlx::query_as::<_, User>(r#"SELECT NAME,USER FROM USER"#)
-        .fetch_optional(&pool)
+        .fetch_optional(&*pool)
         .await?;
 
     ApiResult::ok_data(result)
 }
 
 #[get("/redis/{key}")]
-async fn redis_test(data: web::Data<AppState>, path: web::Path<String>) -> JietoResult<String> {
+async fn redis_test(pool: Db<deadpool_redis::Pool>, path: web::Path<String>) -> JietoResult<String> {
     let key = path.into_inner();
-    let pool = data.db_manager.with_redis_default()?;
     let mut conn = pool.get().await.unwrap();
     let result = cmd("GET")
         .arg(&[key])
@@ -59,7 +57,7 @@ impl AppInitializing for  ApplicationInit{
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     Application::new(| cfg|{
-        cfg.service(hello);
+        cfg.service(hello).service(redis_test);
     }).bind_init(ApplicationInit)
         .register_task(task!(health_check_task))
         .run().await?;
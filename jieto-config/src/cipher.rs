@@ -0,0 +1,98 @@
+use crate::error::ConfigError;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+
+/// Environment variable holding the base64 encoded 256-bit master key.
+pub const KEY_ENV: &str = "JIETO_CONFIG_KEY";
+/// Environment variable holding the path of a file containing the master key.
+pub const KEY_FILE_ENV: &str = "JIETO_CONFIG_KEY_FILE";
+
+const NONCE_LEN: usize = 12;
+
+/// Master key decrypting `ENC(...)` configuration values.
+#[derive(Clone)]
+pub struct MasterKey(Key<Aes256Gcm>);
+
+impl MasterKey {
+    /// A new random key.
+    pub fn generate() -> Self {
+        Self(Aes256Gcm::generate_key(OsRng))
+    }
+
+    /// Reads the key from `JIETO_CONFIG_KEY`, or from the file named by `JIETO_CONFIG_KEY_FILE`.
+    pub fn from_env() -> Result<Self, ConfigError> {
+        if let Ok(encoded) = std::env::var(KEY_ENV) {
+            return Self::from_base64(&encoded);
+        }
+        let path = std::env::var(KEY_FILE_ENV).map_err(|_| ConfigError::MissingKey)?;
+        let encoded = std::fs::read_to_string(&path).map_err(|source| ConfigError::SecretFile {
+            key: String::from(KEY_FILE_ENV),
+            path,
+            source,
+        })?;
+        Self::from_base64(&encoded)
+    }
+
+    pub fn from_base64(encoded: &str) -> Result<Self, ConfigError> {
+        let bytes = STANDARD
+            .decode(encoded.trim())
+            .map_err(|_| ConfigError::InvalidKey)?;
+        if bytes.len() != 32 {
+            return Err(ConfigError::InvalidKey);
+        }
+        Ok(Self(*Key::<Aes256Gcm>::from_slice(&bytes)))
+    }
+
+    pub fn to_base64(&self) -> String {
+        STANDARD.encode(self.0)
+    }
+
+    /// Encrypts `plaintext` into an `ENC(...)` value.
+    pub fn encrypt(&self, plaintext: &str) -> String {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        // unwrap: encryption only fails for inputs larger than the GCM limit
+        let ciphertext = Aes256Gcm::new(&self.0)
+            .encrypt(&nonce, plaintext.as_bytes())
+            .unwrap();
+        let mut payload = nonce.to_vec();
+        payload.extend_from_slice(&ciphertext);
+        format!("ENC({})", STANDARD.encode(payload))
+    }
+
+    /// Decrypts the base64 payload of an `ENC(...)` value.
+    fn decrypt(&self, payload: &str) -> Option<String> {
+        let bytes = STANDARD.decode(payload.trim()).ok()?;
+        if bytes.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+        let plaintext = Aes256Gcm::new(&self.0)
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .ok()?;
+        String::from_utf8(plaintext).ok()
+    }
+}
+
+/// The base64 payload when `value` is an `ENC(...)` value.
+pub(crate) fn encrypted_payload(value: &str) -> Option<&str> {
+    value.trim().strip_prefix("ENC(")?.strip_suffix(')')
+}
+
+/// Decrypts `ENC(...)` values, loading the master key on first use.
+#[derive(Default)]
+pub(crate) struct Decryptor {
+    key: Option<MasterKey>,
+}
+
+impl Decryptor {
+    pub(crate) fn decrypt(&mut self, payload: &str, path: &str) -> Result<String, ConfigError> {
+        let key = match &mut self.key {
+            Some(key) => key,
+            None => self.key.insert(MasterKey::from_env()?),
+        };
+        key.decrypt(payload)
+            .ok_or_else(|| ConfigError::Decrypt(path.to_string()))
+    }
+}
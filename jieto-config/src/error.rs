@@ -0,0 +1,38 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("Failed to read configuration file '{path}': {source}")]
+    Io {
+        path: String,
+        source: std::io::Error,
+    },
+
+    #[error("Invalid configuration: {0}")]
+    Parse(#[from] toml::de::Error),
+
+    #[error("Environment variable '{var}' referenced by '{key}' is not set")]
+    MissingVar { key: String, var: String },
+
+    #[error("Failed to read secret file '{path}' for '{key}': {source}")]
+    SecretFile {
+        key: String,
+        path: String,
+        source: std::io::Error,
+    },
+
+    #[error("'{0}' and '{0}_file' cannot both be set")]
+    SecretConflict(String),
+
+    #[error("Encrypted values need a master key in JIETO_CONFIG_KEY or JIETO_CONFIG_KEY_FILE")]
+    MissingKey,
+
+    #[error("Master key must be 32 bytes encoded as base64")]
+    InvalidKey,
+
+    #[error("Failed to decrypt '{0}', check the master key")]
+    Decrypt(String),
+
+    #[error("Configuration section '{0}' not found")]
+    MissingSection(String),
+}
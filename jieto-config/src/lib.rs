@@ -0,0 +1,429 @@
+//! Loads jieto configuration files.
+//!
+//! Every string value may reference environment variables as `${VAR}` or `${VAR:default}`
+//! (`$${` produces a literal `${`), and every credential field `<name>` may instead be given as
+//! `<name>_file` pointing to a file holding the value, e.g. `password_file = "/run/secrets/db"`.
+//!
+//! Values written as `ENC(...)` are decrypted with the AES-256-GCM master key from
+//! `JIETO_CONFIG_KEY` or `JIETO_CONFIG_KEY_FILE`, see [`MasterKey::encrypt`] and the
+//! `jieto-config` binary for producing them.
+//!
+//! When a profile is active, set by [`set_profile`] or `APP_PROFILE`, `application-<profile>.toml`
+//! next to `application.toml` is merged over it before values are resolved.
+
+mod cipher;
+pub mod error;
+mod secret;
+
+pub use cipher::{KEY_ENV, KEY_FILE_ENV, MasterKey};
+pub use secrecy::ExposeSecret;
+pub use secret::Secret;
+
+use crate::cipher::{Decryptor, encrypted_payload};
+use crate::error::ConfigError;
+use serde::de::DeserializeOwned;
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::sync::OnceLock;
+use toml::{Table, Value};
+
+/// Environment variable selecting the profile when none is set by [`set_profile`].
+pub const PROFILE_ENV: &str = "APP_PROFILE";
+
+static PROFILE: OnceLock<String> = OnceLock::new();
+
+/// Printed in place of credential values.
+pub const MASK: &str = "******";
+
+/// Key fragments marking a value as a credential.
+pub const CREDENTIAL_KEYS: [&str; 6] = [
+    "password",
+    "secret",
+    "token",
+    "credential",
+    "private_key",
+    "api_key",
+];
+
+/// Whether `key` names a credential field.
+pub fn is_credential(key: &str) -> bool {
+    let key = key.to_ascii_lowercase();
+    CREDENTIAL_KEYS
+        .iter()
+        .any(|fragment| key.contains(fragment))
+}
+
+/// Paths of the values that were decrypted from `ENC(...)` or read from secret files, written
+/// like `mysql[0].url`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SecretPaths(BTreeSet<String>);
+
+impl SecretPaths {
+    pub fn contains(&self, path: &str) -> bool {
+        self.0.contains(path)
+    }
+}
+
+/// Copy of `table` with every credential value and every value listed in `secrets` masked, safe
+/// to log or expose.
+pub fn redact(table: &Table, secrets: &SecretPaths) -> Table {
+    redact_table(table, "", secrets)
+}
+
+fn redact_table(table: &Table, path: &str, secrets: &SecretPaths) -> Table {
+    table
+        .iter()
+        .map(|(key, value)| {
+            let path = join(path, key);
+            let value = if is_credential(key) || secrets.contains(&path) {
+                Value::String(String::from(MASK))
+            } else {
+                redact_value(value, &path, secrets)
+            };
+            (key.clone(), value)
+        })
+        .collect()
+}
+
+fn redact_value(value: &Value, path: &str, secrets: &SecretPaths) -> Value {
+    match value {
+        Value::String(_) if secrets.contains(path) => Value::String(String::from(MASK)),
+        Value::Table(table) => Value::Table(redact_table(table, path, secrets)),
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .enumerate()
+                .map(|(idx, item)| redact_value(item, &format!("{path}[{idx}]"), secrets))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+/// Resolution state of one file.
+#[derive(Default)]
+struct Resolver {
+    decryptor: Decryptor,
+    secrets: SecretPaths,
+}
+
+/// Reads `path`, merges the active profile file over it and resolves environment references and
+/// secret files.
+pub async fn load(path: &str) -> Result<Table, ConfigError> {
+    Ok(load_with_secrets(path).await?.0)
+}
+
+/// Like [`load`], also returning which values are secrets, for [`redact`].
+pub async fn load_with_secrets(path: &str) -> Result<(Table, SecretPaths), ConfigError> {
+    let mut table = read_merged(path).await?;
+    let mut resolver = Resolver::default();
+    resolve_table(&mut table, "", &mut resolver)?;
+    Ok((table, resolver.secrets))
+}
+
+/// Activates `profile` for every later [`load`], overriding `APP_PROFILE`.
+///
+/// Only the first call has an effect.
+pub fn set_profile(profile: impl Into<String>) {
+    let _ = PROFILE.set(profile.into());
+}
+
+/// The active profile, if any.
+pub fn profile() -> Option<String> {
+    PROFILE
+        .get()
+        .cloned()
+        .or_else(|| std::env::var(PROFILE_ENV).ok())
+        .filter(|profile| !profile.is_empty())
+}
+
+/// Path of the `profile` file of `path`, e.g. `config/application-dev.toml`.
+pub fn profile_path(path: &str, profile: &str) -> String {
+    let path = Path::new(path);
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .unwrap_or_default();
+    let file = match path.extension() {
+        Some(ext) => format!("{}-{}.{}", stem, profile, ext.to_string_lossy()),
+        None => format!("{}-{}", stem, profile),
+    };
+    path.with_file_name(file).to_string_lossy().into_owned()
+}
+
+/// Reads `path` and merges the active profile file over it, without resolving values.
+async fn read_merged(path: &str) -> Result<Table, ConfigError> {
+    let mut table: Table = toml::from_str(&read(path).await?)?;
+    if let Some(profile) = profile() {
+        let profile_path = profile_path(path, &profile);
+        let overlay: Table = toml::from_str(&read(&profile_path).await?)?;
+        merge(&mut table, overlay);
+    }
+    Ok(table)
+}
+
+async fn read(path: &str) -> Result<String, ConfigError> {
+    tokio::fs::read_to_string(path)
+        .await
+        .map_err(|source| ConfigError::Io {
+            path: path.to_string(),
+            source,
+        })
+}
+
+/// Merges `overlay` into `base`: tables are merged key by key, other values (including arrays such
+/// as `[[mysql]]`) are replaced.
+fn merge(base: &mut Table, overlay: Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(overlay)) => merge(base, overlay),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Parses `contents` and resolves environment references and secret files.
+pub fn parse(contents: &str) -> Result<Table, ConfigError> {
+    let mut table: Table = toml::from_str(contents)?;
+    resolve_table(&mut table, "", &mut Resolver::default())?;
+    Ok(table)
+}
+
+/// Loads `path` and deserializes the table `name`, e.g. a custom `[sms]` section, or `web.cors`
+/// for a nested one. Only the values of that table are resolved.
+pub async fn section<T: DeserializeOwned>(path: &str, name: &str) -> Result<T, ConfigError> {
+    let missing = || ConfigError::MissingSection(name.to_string());
+    let mut value = Value::Table(read_merged(path).await?);
+    for key in name.split('.') {
+        value = match value {
+            Value::Table(mut table) => table.remove(key).ok_or_else(missing)?,
+            _ => return Err(missing()),
+        };
+    }
+    resolve_value(&mut value, name, &mut Resolver::default())?;
+    Ok(value.try_into()?)
+}
+
+fn resolve_table(
+    table: &mut Table,
+    path: &str,
+    resolver: &mut Resolver,
+) -> Result<(), ConfigError> {
+    for (key, value) in table.iter_mut() {
+        resolve_value(value, &join(path, key), resolver)?;
+    }
+
+    // 凭据字段支持从文件读取：password_file -> password
+    let secret_files: Vec<String> = table
+        .keys()
+        .filter(|key| key.strip_suffix("_file").is_some_and(is_credential))
+        .cloned()
+        .collect();
+    for file_key in secret_files {
+        // unwrap: filtered on the suffix above
+        let key = file_key.strip_suffix("_file").unwrap().to_string();
+        if table.contains_key(&key) {
+            return Err(ConfigError::SecretConflict(join(path, &key)));
+        }
+        let Some(Value::String(file)) = table.remove(&file_key) else {
+            continue;
+        };
+        let secret = std::fs::read_to_string(&file).map_err(|source| ConfigError::SecretFile {
+            key: join(path, &key),
+            path: file,
+            source,
+        })?;
+        let mut secret = secret.trim_end_matches(['\r', '\n']).to_string();
+        if let Some(payload) = encrypted_payload(&secret) {
+            secret = resolver.decryptor.decrypt(payload, &join(path, &key))?;
+        }
+        resolver.secrets.0.insert(join(path, &key));
+        table.insert(key, Value::String(secret));
+    }
+    Ok(())
+}
+
+fn resolve_value(
+    value: &mut Value,
+    path: &str,
+    resolver: &mut Resolver,
+) -> Result<(), ConfigError> {
+    match value {
+        Value::String(s) => {
+            if s.contains('$') {
+                *s = interpolate(s, path)?;
+            }
+            if let Some(payload) = encrypted_payload(s) {
+                *s = resolver.decryptor.decrypt(payload, path)?;
+                resolver.secrets.0.insert(path.to_string());
+            }
+        }
+        Value::Array(items) => {
+            for (idx, item) in items.iter_mut().enumerate() {
+                resolve_value(item, &format!("{path}[{idx}]"), resolver)?;
+            }
+        }
+        Value::Table(table) => resolve_table(table, path, resolver)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Replaces `${VAR}` and `${VAR:default}` in `input`, defaults may reference other variables.
+fn interpolate(input: &str, path: &str) -> Result<String, ConfigError> {
+    let mut out = String::with_capacity(input.len());
+    let mut rest = input;
+
+    while let Some(idx) = rest.find('$') {
+        out.push_str(&rest[..idx]);
+        rest = &rest[idx..];
+
+        if let Some(after) = rest.strip_prefix("$${") {
+            out.push_str("${");
+            rest = after;
+            continue;
+        }
+
+        let Some(end) = rest.strip_prefix("${").and_then(closing_brace) else {
+            out.push('$');
+            rest = &rest[1..];
+            continue;
+        };
+        let expr = &rest[2..2 + end];
+        let (var, default) = match expr.split_once(':') {
+            Some((var, default)) => (var, Some(default)),
+            None => (expr, None),
+        };
+        match (std::env::var(var.trim()), default) {
+            (Ok(value), _) => out.push_str(&value),
+            (Err(_), Some(default)) => out.push_str(&interpolate(default, path)?),
+            (Err(_), None) => {
+                return Err(ConfigError::MissingVar {
+                    key: path.to_string(),
+                    var: var.trim().to_string(),
+                });
+            }
+        }
+        rest = &rest[2 + end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Index of the `}` closing a reference, skipping the references nested in its default.
+fn closing_brace(expr: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (idx, c) in expr.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(idx),
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{path}.{key}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redact_masks_credentials_and_secret_paths() {
+        let table: Table = toml::from_str(
+            r#"
+            name = "app"
+            [[mysql]]
+            url = "mysql://user:pw@db/app"
+            password = "pw"
+            [[mysql]]
+            url = "mysql://db/other"
+            [sms]
+            keys = ["plain", "decrypted"]
+            "#,
+        )
+        .unwrap();
+        let secrets = SecretPaths(["mysql[0].url", "sms.keys[1]"].map(String::from).into());
+
+        let redacted = redact(&table, &secrets);
+        let mysql = redacted["mysql"].as_array().unwrap();
+        assert_eq!(mysql[0]["url"].as_str(), Some(MASK));
+        assert_eq!(mysql[0]["password"].as_str(), Some(MASK));
+        assert_eq!(mysql[1]["url"].as_str(), Some("mysql://db/other"));
+        let keys = redacted["sms"]["keys"].as_array().unwrap();
+        assert_eq!(keys[0].as_str(), Some("plain"));
+        assert_eq!(keys[1].as_str(), Some(MASK));
+        assert_eq!(redacted["name"].as_str(), Some("app"));
+    }
+
+    #[tokio::test]
+    async fn section_resolves_only_its_table() {
+        let path = std::env::temp_dir().join(format!("jieto-section-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"
+            [mysql]
+            password = "${JIETO_TEST_UNSET_VAR}"
+            [web.ip_filter]
+            enabled = true
+            "#,
+        )
+        .unwrap();
+        let path = path.to_string_lossy();
+
+        let filter: Table = section(&path, "web.ip_filter").await.unwrap();
+        assert_eq!(filter["enabled"].as_bool(), Some(true));
+        assert!(matches!(
+            section::<Table>(&path, "web.cors").await,
+            Err(ConfigError::MissingSection(_))
+        ));
+        assert!(matches!(
+            section::<Table>(&path, "mysql").await,
+            Err(ConfigError::MissingVar { .. })
+        ));
+        std::fs::remove_file(&*path).unwrap();
+    }
+
+    #[test]
+    fn interpolate_nested_defaults() {
+        // SAFETY: the variables are only used by this test
+        unsafe { std::env::set_var("JIETO_TEST_INNER", "inner") };
+        let value = |input| interpolate(input, "key").unwrap();
+        assert_eq!(value("${JIETO_TEST_INNER}"), "inner");
+        assert_eq!(value("${JIETO_TEST_UNSET:${JIETO_TEST_INNER}}"), "inner");
+        assert_eq!(
+            value("${JIETO_TEST_UNSET:${JIETO_TEST_UNSET2:deep}}"),
+            "deep"
+        );
+        assert_eq!(value("a-${JIETO_TEST_UNSET:}-b"), "a--b");
+        assert_eq!(value("$${JIETO_TEST_INNER}"), "${JIETO_TEST_INNER}");
+        assert_eq!(value("${JIETO_TEST_UNCLOSED"), "${JIETO_TEST_UNCLOSED");
+    }
+
+    #[test]
+    fn interpolate_missing_variables() {
+        assert!(matches!(
+            interpolate("${JIETO_TEST_UNSET}", "db.url"),
+            Err(ConfigError::MissingVar { key, var }) if key == "db.url" && var == "JIETO_TEST_UNSET"
+        ));
+        assert!(matches!(
+            interpolate("${JIETO_TEST_UNSET:${JIETO_TEST_UNSET2}}", "db.url"),
+            Err(ConfigError::MissingVar { var, .. }) if var == "JIETO_TEST_UNSET2"
+        ));
+        let err = parse("[a.b]\nitems = [\"x\", \"${JIETO_TEST_UNSET}\"]").unwrap_err();
+        assert!(
+            matches!(&err, ConfigError::MissingVar { key, .. } if key == "a.b.items[1]"),
+            "{err}"
+        );
+    }
+}
@@ -0,0 +1,70 @@
+//! Produces `ENC(...)` configuration values.
+//!
+//! ```text
+//! jieto-config keygen                 print a new base64 master key
+//! jieto-config encrypt [VALUE]        encrypt VALUE, or a line from stdin
+//! jieto-config decrypt 'ENC(...)'     decrypt a value to verify the key
+//! ```
+//!
+//! `encrypt` and `decrypt` read the master key from `JIETO_CONFIG_KEY` or `JIETO_CONFIG_KEY_FILE`.
+
+use jieto_config::MasterKey;
+use std::io::BufRead;
+use std::process::ExitCode;
+
+fn usage() -> ExitCode {
+    eprintln!("usage: jieto-config <keygen | encrypt [VALUE] | decrypt VALUE>");
+    ExitCode::FAILURE
+}
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    let Some(command) = args.next() else {
+        return usage();
+    };
+
+    let result = match command.as_str() {
+        "keygen" => Ok(MasterKey::generate().to_base64()),
+        "encrypt" => {
+            // 未传参数时从 stdin 读取，避免明文出现在 shell 历史中
+            let value = match args.next() {
+                Some(value) => value,
+                None => {
+                    let mut line = String::new();
+                    if let Err(e) = std::io::stdin().lock().read_line(&mut line) {
+                        eprintln!("{e}");
+                        return ExitCode::FAILURE;
+                    }
+                    line.trim_end_matches(['\r', '\n']).to_string()
+                }
+            };
+            MasterKey::from_env().map(|key| key.encrypt(&value))
+        }
+        "decrypt" => {
+            let Some(value) = args.next() else {
+                return usage();
+            };
+            // 按配置文件的方式解析，由 toml 负责转义
+            let mut table = toml::Table::new();
+            table.insert(String::from("value"), toml::Value::String(value));
+            jieto_config::parse(&table.to_string()).map(|mut table| {
+                match table.remove("value") {
+                    Some(toml::Value::String(value)) => value,
+                    _ => String::new(),
+                }
+            })
+        }
+        _ => return usage(),
+    };
+
+    match result {
+        Ok(output) => {
+            println!("{output}");
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -1,8 +1,6 @@
 use super::conn::DatabaseInit;
 use serde::Deserialize;
 use std::collections::HashMap;
-use tokio::fs::File;
-use tokio::io::AsyncReadExt;
 
 #[cfg(feature = "mysql")]
 use super::conn::mysql::MySqlSourceConfig;
@@ -15,6 +13,7 @@ use super::conn::sqlite::SqliteSourceConfig;
 
 use super::database::{DataSource, DbManager, DefaultKey};
 use super::error::DbError;
+use crate::logging;
 
 #[derive(Deserialize, Debug, Default)]
 pub(crate) struct MultiDataSourceConfig {
@@ -79,13 +78,47 @@ macro_rules! define_into_data_sources {
     };
 }
 
+macro_rules! define_migrate {
+    (
+        $self:expr,
+        $applied:ident,
+        $( $Variant:ident ),* $(,)?
+    ) => {
+        $(
+            paste::paste! {
+    #[cfg(feature = $Variant:lower)]
+    for config in &$self.[<$Variant:lower>] {
+        if let Some(dir) = &config.migrations {
+            let pool = config.init_datasource().await?.[<$Variant:lower _pool>]()?;
+            logging::info!("[db][{}] applying migrations from '{}'", config.name, dir);
+            sqlx::migrate::Migrator::new(std::path::Path::new(dir))
+                .await?
+                .run(&pool)
+                .await?;
+            $applied.push(config.name.clone());
+        }
+    }
+}
+        )*
+    };
+}
+
 impl MultiDataSourceConfig {
+    pub(crate) async fn load(path: &str) -> anyhow::Result<Self> {
+        Ok(jieto_config::load(path).await?.try_into()?)
+    }
+
     pub(crate) async fn from_toml(path: &str) -> anyhow::Result<DbManager> {
-        let mut file = File::open(path).await?;
-        let mut contents = String::new();
-        file.read_to_string(&mut contents).await?;
-        let config: MultiDataSourceConfig = toml::from_str(&contents)?;
-        config.into_data_sources().await
+        Self::load(path).await?.into_data_sources().await
+    }
+
+    /// Applies the migrations of every SQL data source with a `migrations` directory, returning
+    /// the names of the migrated data sources.
+    pub(crate) async fn migrate(&self) -> anyhow::Result<Vec<String>> {
+        #[allow(unused_mut)]
+        let mut applied = vec![];
+        define_migrate!(self, applied, Sqlite, Mysql, Postgres,);
+        Ok(applied)
     }
 
     pub(crate) async fn into_data_sources(self) -> anyhow::Result<DbManager> {
@@ -1,6 +1,7 @@
 use std::cmp::max;
 use std::time::Duration;
 use async_trait::async_trait;
+use jieto_config::Secret;
 use secrecy::{ExposeSecret, SecretBox};
 use serde::Deserialize;
 use sqlx::mysql::MySqlPoolOptions;
@@ -8,6 +9,7 @@ use urlencoding::encode;
 use super::super::conn::DatabaseInit;
 use super::super::error::DbError;
 use super::super::database::DataSource;
+use crate::logging;
 
 #[derive(Deserialize, Debug,Default)]
 pub struct MySqlSourceConfig {
@@ -16,8 +18,11 @@ pub struct MySqlSourceConfig {
     pub host: String,
     pub port: u16,
     pub username: String,
-    pub password: String,
+    pub password: Secret,
     pub database: String,
+    /// Directory of sqlx migrations applied by `jieto_db_migrate`.
+    #[serde(default)]
+    pub migrations: Option<String>,
 }
 
 impl MySqlSourceConfig{
@@ -25,7 +30,7 @@ impl MySqlSourceConfig{
         SecretBox::from(format!(
             "mysql://{}:{}@{}:{}/{}",
             encode(&self.username),
-            encode(&self.password),
+            encode(self.password.expose_secret()),
             self.host,
             self.port,
             encode(&self.database)
@@ -36,7 +41,7 @@ impl MySqlSourceConfig{
 #[async_trait]
 impl DatabaseInit for MySqlSourceConfig {
     async fn init_datasource(&self) -> anyhow::Result<DataSource, DbError> {
-        log::info!(
+        logging::info!(
         "[db] Connecting to Mysql database: {}", self.name
     );
         let uri = self.create_uri();
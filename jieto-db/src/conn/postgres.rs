@@ -1,7 +1,9 @@
 use super::super::conn::DatabaseInit;
 use super::super::database::DataSource;
 use super::super::error::DbError;
+use crate::logging;
 use async_trait::async_trait;
+use jieto_config::Secret;
 use secrecy::{ExposeSecret, SecretBox};
 use serde::Deserialize;
 use sqlx::PgPool;
@@ -14,7 +16,7 @@ pub struct PostgresSourceConfig {
     pub host: String,
     pub port: u16,
     pub username: String,
-    pub password: String,
+    pub password: Secret,
     pub database: String,
     #[serde(default)]
     pub ssl_mode: Option<String>,
@@ -22,6 +24,9 @@ pub struct PostgresSourceConfig {
     pub schema: Option<String>,
     #[serde(default)]
     pub application_name: Option<String>,
+    /// Directory of sqlx migrations applied by `jieto_db_migrate`.
+    #[serde(default)]
+    pub migrations: Option<String>,
 }
 
 impl PostgresSourceConfig {
@@ -29,7 +34,7 @@ impl PostgresSourceConfig {
         let mut u = format!(
             "postgres://{}:{}@{}:{}/{}",
             encode(&self.username),
-            encode(&self.password),
+            encode(self.password.expose_secret()),
             self.host,
             self.port,
             encode(&self.database)
@@ -61,7 +66,7 @@ impl PostgresSourceConfig {
 #[async_trait]
 impl DatabaseInit for PostgresSourceConfig {
     async fn init_datasource(&self) -> anyhow::Result<DataSource, DbError> {
-        log::info!("[db] Connecting to Postgres database: {}", self.name);
+        logging::info!("[db] Connecting to Postgres database: {}", self.name);
         let uri = self.create_uri();
         let pool = PgPool::connect_lazy(uri.expose_secret())?;
         Ok(DataSource::Postgres { pool })
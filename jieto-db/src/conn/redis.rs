@@ -1,8 +1,10 @@
 use super::super::conn::DatabaseInit;
 use super::super::database::DataSource;
 use super::super::error::DbError;
+use crate::logging;
 use async_trait::async_trait;
 use deadpool_redis::{Config, Runtime};
+use jieto_config::Secret;
 use secrecy::{ExposeSecret, SecretBox};
 use serde::Deserialize;
 use urlencoding::encode;
@@ -16,7 +18,7 @@ pub struct RedisSourceConfig {
     #[serde(default)]
     pub username: Option<String>,
     #[serde(default)]
-    pub password: Option<String>,
+    pub password: Option<Secret>,
     #[serde(default)]
     pub db: u8,
     #[serde(default)]
@@ -32,7 +34,7 @@ impl RedisSourceConfig {
         };
         if let Some(password) = self.password.as_ref() {
             use std::fmt::Write;
-            write!(url, ":{}", encode(password)).unwrap();
+            write!(url, ":{}", encode(password.expose_secret())).unwrap();
         }
         use std::fmt::Write;
         write!(url, "@{}:{}", self.host, self.port).unwrap();
@@ -48,7 +50,7 @@ impl RedisSourceConfig {
 #[async_trait]
 impl DatabaseInit for RedisSourceConfig {
     async fn init_datasource(&self) -> anyhow::Result<DataSource, DbError> {
-        log::info!("[db][{}] Connecting to Redis database", self.name);
+        logging::info!("[db][{}] Connecting to Redis database", self.name);
         let uri = self.create_uri();
         let cfg = Config::from_url(uri.expose_secret());
         let pool = cfg.create_pool(Some(Runtime::Tokio1))?;
@@ -1,6 +1,7 @@
 use super::super::conn::DatabaseInit;
 use super::super::database::DataSource;
 use super::super::error::DbError;
+use crate::logging;
 use async_trait::async_trait;
 use secrecy::{ExposeSecret, SecretBox};
 use serde::Deserialize;
@@ -11,6 +12,9 @@ pub struct SqliteSourceConfig {
     pub default: Option<bool>,
     pub name: String,
     pub url: String,
+    /// Directory of sqlx migrations applied by `jieto_db_migrate`.
+    #[serde(default)]
+    pub migrations: Option<String>,
 }
 
 impl SqliteSourceConfig {
@@ -22,7 +26,7 @@ impl SqliteSourceConfig {
 #[async_trait]
 impl DatabaseInit for SqliteSourceConfig {
     async fn init_datasource(&self) -> anyhow::Result<DataSource, DbError> {
-        log::info!("[db][{}] Connecting to Sqlite database", self.name);
+        logging::info!("[db][{}] Connecting to Sqlite database", self.name);
         let uri = self.create_uri();
         let pool = SqlitePool::connect_lazy(uri.expose_secret())?;
         Ok(DataSource::Sqlite {  pool })
@@ -33,6 +33,17 @@ macro_rules! define_data_sources {
         }
 
         impl DataSource {
+            /// Database type of this data source, e.g. `mysql`.
+            pub fn kind(&self) -> &'static str {
+                match self {
+                    DataSource::None => "none",
+                    $(
+                        #[cfg(feature = $feature)]
+                        DataSource::$variant { .. } => $feature,
+                    )*
+                }
+            }
+
             $(
                 #[cfg(feature = $feature)]
                 paste::paste! {
@@ -75,6 +86,30 @@ macro_rules! define_data_sources {
             }
         )*
 
+        /// A connection pool type held by a [`DataSource`].
+        pub trait DataSourcePool: Sized {
+            /// Pool of the data source named `name`.
+            fn from_named(manager: &DbManager, name: &str) -> Result<Self, DbError>;
+
+            /// Pool of the default data source of this type.
+            fn from_default(manager: &DbManager) -> Result<Self, DbError>;
+        }
+
+        $(
+            #[cfg(feature = $feature)]
+            paste::paste! {
+                impl DataSourcePool for $pool_type {
+                    fn from_named(manager: &DbManager, name: &str) -> Result<Self, DbError> {
+                        manager.[<with_ $variant:lower>](name)
+                    }
+
+                    fn from_default(manager: &DbManager) -> Result<Self, DbError> {
+                        manager.[<with_ $variant:lower _default>]()
+                    }
+                }
+            }
+        )*
+
         impl DbManager {
               pub fn get(&self, name:&str) -> Option<&DataSource> {
                 self.inner.get(name)
@@ -120,6 +155,15 @@ define_data_sources! {
     Redis, "redis", RedisPool,
 }
 
+/// A configured data source as reported by [`DbManager::datasources`].
+#[derive(Debug, Clone)]
+pub struct DataSourceInfo {
+    pub name: String,
+    pub kind: &'static str,
+    /// Whether this is the default data source of its type.
+    pub default: bool,
+}
+
 impl DbManager {
     pub fn new(
         inner: HashMap<String, DataSource>,
@@ -130,4 +174,19 @@ impl DbManager {
             default_name: Arc::new(default_name),
         }
     }
+
+    /// Lists configured data sources sorted by name.
+    pub fn datasources(&self) -> Vec<DataSourceInfo> {
+        let mut sources: Vec<DataSourceInfo> = self
+            .inner
+            .iter()
+            .map(|(name, ds)| DataSourceInfo {
+                name: name.clone(),
+                kind: ds.kind(),
+                default: self.default_name.values().any(|n| n == name),
+            })
+            .collect();
+        sources.sort_by(|a, b| a.name.cmp(&b.name));
+        sources
+    }
 }
@@ -6,7 +6,25 @@ pub mod conn;
 pub mod database;
 pub mod error;
 
+/// `tracing::info` with the `tracing` feature, `log::info` otherwise.
+mod logging {
+    #[cfg(not(feature = "tracing"))]
+    pub(crate) use log::info;
+    #[cfg(feature = "tracing")]
+    pub(crate) use tracing::info;
+}
 
 pub async fn jieto_db_init(path: &str) -> anyhow::Result<DbManager> {
     MultiDataSourceConfig::from_toml(path).await
 }
+
+/// Validates the data source configuration without connecting.
+pub async fn jieto_db_check(path: &str) -> anyhow::Result<()> {
+    MultiDataSourceConfig::load(path).await.map(|_| ())
+}
+
+/// Applies the sqlx migrations configured by `migrations = "<dir>"` on SQL data sources,
+/// returning the names of the migrated data sources.
+pub async fn jieto_db_migrate(path: &str) -> anyhow::Result<Vec<String>> {
+    MultiDataSourceConfig::load(path).await?.migrate().await
+}
@@ -1,5 +1,13 @@
 mod scheduler;
 mod task;
 
-pub use scheduler::TaskScheduler;
+/// Task logs go through `tracing` with the `tracing` feature so they land in the run span.
+mod logging {
+    #[cfg(not(feature = "tracing"))]
+    pub(crate) use log::{debug, info};
+    #[cfg(feature = "tracing")]
+    pub(crate) use tracing::{debug, info};
+}
+
+pub use scheduler::{TaskInfo, TaskScheduler};
 pub use task::ScheduledTask;
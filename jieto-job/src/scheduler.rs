@@ -1,14 +1,27 @@
+use crate::logging;
 use crate::task::ScheduledTask;
 use anyhow::Result;
 use std::fmt;
-use std::sync::Arc;
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 use tokio::sync::OnceCell;
 use tokio_cron_scheduler::{Job, JobScheduler};
+use uuid::Uuid;
+
+/// A registered task as reported by [`TaskScheduler::tasks`].
+#[derive(Debug, Clone)]
+pub struct TaskInfo {
+    pub name: &'static str,
+    pub cron: &'static str,
+    /// Next fire time, `None` before the scheduler is started.
+    pub next_tick: Option<SystemTime>,
+}
 
 pub struct TaskScheduler {
     scheduler: OnceCell<JobScheduler>,
     task_count: AtomicUsize,
+    registry: Mutex<Vec<(&'static str, &'static str, Uuid)>>,
 }
 
 impl Default for TaskScheduler {
@@ -16,6 +29,7 @@ impl Default for TaskScheduler {
         Self {
             scheduler: OnceCell::new(),
             task_count: AtomicUsize::new(0),
+            registry: Mutex::new(Vec::new()),
         }
     }
 }
@@ -39,6 +53,7 @@ impl TaskScheduler {
         Ok(Self {
             scheduler: job_scheduler_cell,
             task_count: AtomicUsize::new(0),
+            registry: Mutex::new(Vec::new()),
         })
     }
 
@@ -48,10 +63,10 @@ impl TaskScheduler {
             .get()
             .ok_or_else(|| anyhow::anyhow!("[job] job scheduler not initialized"))?;
 
-        let cron_expr = task.cron_expression().to_string();
-        let task_name = task.task_name().to_string();
+        let cron_expr = task.cron_expression();
+        let task_name = task.task_name();
 
-        log::info!(
+        logging::info!(
             "[job] registering task: {} with cron: {}",
             task_name,
             cron_expr
@@ -60,28 +75,41 @@ impl TaskScheduler {
         // Wrap task in Arc for sharing across async boundaries
         let task = Arc::new(task);
 
-        let job = Job::new_async(cron_expr.as_str(), move |_uuid, _lock| {
+        let job = Job::new_async(cron_expr, move |_uuid, _lock| {
             let task = Arc::clone(&task);
             Box::pin(async move {
-                log::debug!("️[job] [{}] starting execution...", task.task_name());
-                task.execute().await;
-                log::debug!("[job] [{}] completed execution", task.task_name());
+                logging::debug!("️[job] [{}] starting execution...", task.task_name());
+                let run = task.execute();
+                #[cfg(feature = "tracing")]
+                let run = tracing::Instrument::instrument(
+                    run,
+                    tracing::info_span!(
+                        "job",
+                        otel.name = %format_args!("job {}", task.task_name()),
+                        job.name = task.task_name(),
+                    ),
+                );
+                run.await;
+                logging::debug!("[job] [{}] completed execution", task.task_name());
             })
         })?;
 
-        scheduler.add(job).await?;
-
+        let job_id = scheduler.add(job).await?;
+        self.registry
+            .lock()
+            .map_err(|_| anyhow::anyhow!("[job] task registry poisoned"))?
+            .push((task_name, cron_expr, job_id));
 
         self.task_count.fetch_add(1, Ordering::SeqCst);
 
-        log::info!("[job] successfully registered task: {}", task_name);
+        logging::info!("[job] successfully registered task: {}", task_name);
         Ok(())
     }
 
     pub async fn start(&self) -> Result<()> {
         let count = self.get_task_count();
         if count == 0 {
-            log::info!(
+            logging::info!(
             "[job] The scheduler has no tasks");
             Ok(())
         }else{
@@ -92,7 +120,7 @@ impl TaskScheduler {
 
             scheduler.start().await?;
 
-            log::info!(
+            logging::info!(
             "[job] scheduler started with {count} tasks");
             Ok(())
         }
@@ -104,7 +132,7 @@ impl TaskScheduler {
     pub async fn shutdown(&mut self) -> Result<()> {
         if let Some(scheduler) = self.scheduler.get_mut() {
             scheduler.shutdown().await?;
-            log::info!("[job] scheduler shutdown successfully");
+            logging::info!("[job] scheduler shutdown successfully");
         }
         Ok(())
     }
@@ -112,4 +140,32 @@ impl TaskScheduler {
     pub fn get_task_count(&self) -> usize {
         self.task_count.load(Ordering::SeqCst)
     }
+
+    /// Lists registered tasks with their next fire time.
+    pub async fn tasks(&self) -> Vec<TaskInfo> {
+        let registered = match self.registry.lock() {
+            Ok(registry) => registry.clone(),
+            Err(_) => return vec![],
+        };
+
+        let mut tasks = Vec::with_capacity(registered.len());
+        for (name, cron, job_id) in registered {
+            let next_tick = match self.scheduler.get() {
+                Some(scheduler) => scheduler
+                    .clone()
+                    .next_tick_for_job(job_id)
+                    .await
+                    .ok()
+                    .flatten()
+                    .map(SystemTime::from),
+                None => None,
+            };
+            tasks.push(TaskInfo {
+                name,
+                cron,
+                next_tick,
+            });
+        }
+        tasks
+    }
 }
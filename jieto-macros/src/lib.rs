@@ -57,3 +57,176 @@ pub fn task(input: TokenStream) -> TokenStream {
 
     TokenStream::from(expanded)
 }
+
+struct TransactionalArgs {
+    db: syn::Path,
+    datasource: Option<LitStr>,
+}
+
+impl syn::parse::Parse for TransactionalArgs {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let db: syn::Path = input.parse()?;
+        let mut datasource = None;
+        if input.parse::<Option<syn::Token![,]>>()?.is_some() && !input.is_empty() {
+            let key: syn::Ident = input.parse()?;
+            if key != "datasource" {
+                return Err(syn::Error::new(key.span(), "expected `datasource = \"...\"`"));
+            }
+            input.parse::<syn::Token![=]>()?;
+            datasource = Some(input.parse()?);
+        }
+        Ok(Self { db, datasource })
+    }
+}
+
+/// Runs an async function returning `Result` inside a transaction bound to `tx`.
+///
+/// Commits when the body returns `Ok`, rolls back on `Err` and on panic.
+///
+/// ```ignore
+/// #[transactional(MySql)]
+/// async fn transfer(from: u64, to: u64, amount: i64) -> JietoResult<()> {
+///     sqlx::query("UPDATE account SET balance = balance - ? WHERE id = ?")
+///         .bind(amount).bind(from).execute(&mut *tx).await?;
+///     sqlx::query("UPDATE account SET balance = balance + ? WHERE id = ?")
+///         .bind(amount).bind(to).execute(&mut *tx).await?;
+///     ApiResult::ok(())
+/// }
+///
+/// #[transactional(Postgres, datasource = "reporting")]
+/// async fn archive() -> JietoResult<()> { ... }
+/// ```
+#[proc_macro_attribute]
+pub fn transactional(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(attr as TransactionalArgs);
+    let input_fn = parse_macro_input!(item as ItemFn);
+
+    if input_fn.sig.asyncness.is_none() {
+        return syn::Error::new_spanned(&input_fn.sig, "#[transactional] requires an async fn")
+            .to_compile_error()
+            .into();
+    }
+    let syn::ReturnType::Type(_, output) = &input_fn.sig.output else {
+        return syn::Error::new_spanned(&input_fn.sig, "#[transactional] requires a Result return type")
+            .to_compile_error()
+            .into();
+    };
+
+    let fn_attrs = &input_fn.attrs;
+    let fn_vis = &input_fn.vis;
+    let fn_sig = &input_fn.sig;
+    let fn_block = &input_fn.block;
+    let db = &args.db;
+    let datasource = match &args.datasource {
+        Some(name) => quote! { Some(#name) },
+        None => quote! { None },
+    };
+
+    let expanded = quote! {
+        #(#fn_attrs)*
+        #fn_vis #fn_sig {
+            #[allow(unused_mut)]
+            let mut tx = jieto_web::tx::begin::<#db>(#datasource).await?;
+            let result: #output = async #fn_block.await;
+            jieto_web::tx::finish(tx, result.is_ok()).await?;
+            result
+        }
+    };
+
+    TokenStream::from(expanded)
+}
+
+struct CacheableArgs {
+    key: LitStr,
+    ttl: Option<u64>,
+}
+
+impl syn::parse::Parse for CacheableArgs {
+    fn parse(input: syn::parse::ParseStream) -> syn::Result<Self> {
+        let mut key = None;
+        let mut ttl = None;
+        while !input.is_empty() {
+            let name: syn::Ident = input.parse()?;
+            input.parse::<syn::Token![=]>()?;
+            let value: LitStr = input.parse()?;
+            match name.to_string().as_str() {
+                "key" => key = Some(value),
+                "ttl" => ttl = Some(parse_ttl(&value)?),
+                _ => return Err(syn::Error::new(name.span(), "expected `key` or `ttl`")),
+            }
+            if input.parse::<Option<syn::Token![,]>>()?.is_none() {
+                break;
+            }
+        }
+        let key = key.ok_or_else(|| input.error("expected `key = \"...\"`"))?;
+        Ok(Self { key, ttl })
+    }
+}
+
+/// Seconds of a ttl such as `"90"`, `"30s"`, `"5m"`, `"2h"` or `"1d"`.
+fn parse_ttl(value: &LitStr) -> syn::Result<u64> {
+    let invalid = || syn::Error::new(value.span(), "expected a ttl such as \"30s\", \"5m\" or \"1h\"");
+    let text = value.value();
+    let text = text.trim();
+    let (number, unit) = match text.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => text.split_at(index),
+        None => (text, "s"),
+    };
+    let multiplier = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    let number: u64 = number.parse().map_err(|_| invalid())?;
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| syn::Error::new(value.span(), "ttl is too large"))
+}
+
+/// Caches the `Ok` value of an async function returning `Result` under `key`.
+///
+/// `key` is a `format!` string and may use the function arguments. Concurrent calls missing the
+/// same key run the body once; without `ttl` the value never expires.
+///
+/// ```ignore
+/// #[cacheable(key = "user:{id}", ttl = "5m")]
+/// async fn find_user(id: u64) -> Result<User, WebError> { ... }
+/// ```
+#[proc_macro_attribute]
+pub fn cacheable(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(attr as CacheableArgs);
+    let input_fn = parse_macro_input!(item as ItemFn);
+
+    if input_fn.sig.asyncness.is_none() {
+        return syn::Error::new_spanned(&input_fn.sig, "#[cacheable] requires an async fn")
+            .to_compile_error()
+            .into();
+    }
+    if let syn::ReturnType::Default = input_fn.sig.output {
+        return syn::Error::new_spanned(&input_fn.sig, "#[cacheable] requires a Result return type")
+            .to_compile_error()
+            .into();
+    }
+
+    let fn_attrs = &input_fn.attrs;
+    let fn_vis = &input_fn.vis;
+    let fn_sig = &input_fn.sig;
+    let fn_block = &input_fn.block;
+    let key = &args.key;
+    let ttl = match args.ttl {
+        Some(secs) => quote! { Some(::std::time::Duration::from_secs(#secs)) },
+        None => quote! { None },
+    };
+
+    let expanded = quote! {
+        #(#fn_attrs)*
+        #fn_vis #fn_sig {
+            let cache_key = format!(#key);
+            jieto_web::cache::cached(&cache_key, #ttl, async #fn_block).await
+        }
+    };
+
+    TokenStream::from(expanded)
+}
@@ -0,0 +1,123 @@
+use super::{Cache, CacheError};
+use async_trait::async_trait;
+use lru::LruCache;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+struct Entry {
+    value: Vec<u8>,
+    expires_at: Option<Instant>,
+}
+
+impl Entry {
+    fn expired(&self, now: Instant) -> bool {
+        self.expires_at.is_some_and(|at| at <= now)
+    }
+}
+
+/// In-process cache evicting the least recently used entry beyond `capacity` entries.
+pub struct MemoryCache {
+    entries: Mutex<LruCache<String, Entry>>,
+}
+
+impl MemoryCache {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN);
+        Self {
+            entries: Mutex::new(LruCache::new(capacity)),
+        }
+    }
+
+    fn entries(&self) -> std::sync::MutexGuard<'_, LruCache<String, Entry>> {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Applies `f` to the live entry of `key`, dropping it when expired.
+    fn with_entry<R>(&self, key: &str, f: impl FnOnce(&Entry, Instant) -> R) -> Option<R> {
+        let mut entries = self.entries();
+        let now = Instant::now();
+        match entries.get(key) {
+            Some(entry) if entry.expired(now) => {
+                entries.pop(key);
+                None
+            }
+            Some(entry) => Some(f(entry, now)),
+            None => None,
+        }
+    }
+}
+
+#[async_trait]
+impl Cache for MemoryCache {
+    async fn get_bytes(&self, key: &str) -> Result<Option<Vec<u8>>, CacheError> {
+        Ok(self.with_entry(key, |entry, _| entry.value.clone()))
+    }
+
+    async fn set_bytes(
+        &self,
+        key: &str,
+        value: Vec<u8>,
+        ttl: Option<Duration>,
+    ) -> Result<(), CacheError> {
+        let entry = Entry {
+            value,
+            expires_at: ttl.map(|ttl| Instant::now() + ttl),
+        };
+        self.entries().put(key.to_string(), entry);
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), CacheError> {
+        self.entries().pop(key);
+        Ok(())
+    }
+
+    async fn ttl(&self, key: &str) -> Result<Option<Duration>, CacheError> {
+        Ok(self
+            .with_entry(key, |entry, now| entry.expires_at.map(|at| at - now))
+            .flatten())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn entries_expire_after_their_ttl() {
+        let cache = MemoryCache::new(16);
+        cache
+            .set_bytes("short", b"a".to_vec(), Some(Duration::from_millis(20)))
+            .await
+            .unwrap();
+        cache
+            .set_bytes("forever", b"b".to_vec(), None)
+            .await
+            .unwrap();
+        assert!(cache.ttl("short").await.unwrap().unwrap() <= Duration::from_millis(20));
+        assert_eq!(cache.ttl("forever").await.unwrap(), None);
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(cache.get_bytes("short").await.unwrap(), None);
+        assert_eq!(cache.ttl("short").await.unwrap(), None);
+        assert_eq!(
+            cache.get_bytes("forever").await.unwrap(),
+            Some(b"b".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn least_recently_used_entry_is_evicted_at_capacity() {
+        let cache = MemoryCache::new(2);
+        cache.set_bytes("a", b"a".to_vec(), None).await.unwrap();
+        cache.set_bytes("b", b"b".to_vec(), None).await.unwrap();
+        // 读取 a 后 b 成为最久未使用的条目
+        assert!(cache.get_bytes("a").await.unwrap().is_some());
+        cache.set_bytes("c", b"c".to_vec(), None).await.unwrap();
+
+        assert_eq!(cache.get_bytes("b").await.unwrap(), None);
+        assert_eq!(cache.get_bytes("a").await.unwrap(), Some(b"a".to_vec()));
+        assert_eq!(cache.get_bytes("c").await.unwrap(), Some(b"c".to_vec()));
+    }
+}
@@ -0,0 +1,268 @@
+//! Caching of serde values.
+//!
+//! `[cache] mode` selects an in-process LRU, a redis datasource or both tiers. The configured
+//! cache is available as `AppState::cache` and as [`GLOBAL_CACHE`](crate::GLOBAL_CACHE), and is
+//! used by [`cacheable`]:
+//!
+//! ```ignore
+//! #[cacheable(key = "user:{id}", ttl = "5m")]
+//! async fn find_user(id: u64) -> Result<User, WebError> {
+//!     ...
+//! }
+//!
+//! state.cache.set("greeting", &"hello", Some(Duration::from_secs(60))).await?;
+//! let greeting: Option<String> = state.cache.get("greeting").await?;
+//! ```
+
+mod memory;
+#[cfg(feature = "redis")]
+mod redis;
+mod tiered;
+
+pub use jieto_macros::cacheable;
+pub use memory::MemoryCache;
+#[cfg(feature = "redis")]
+pub use redis::RedisCache;
+pub use tiered::TieredCache;
+
+use crate::config::{self, CacheMode};
+use crate::logging;
+use async_trait::async_trait;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::ops::Deref;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum CacheError {
+    #[error("cache value serialization failed: {0}")]
+    Serde(#[from] serde_json::Error),
+    #[cfg(feature = "redis")]
+    #[error("cache redis error: {0}")]
+    Redis(#[from] deadpool_redis::redis::RedisError),
+    #[cfg(feature = "redis")]
+    #[error("cache redis pool error: {0}")]
+    Pool(#[from] deadpool_redis::PoolError),
+}
+
+/// A cache of serialized values, see the typed methods on `dyn Cache`.
+#[async_trait]
+pub trait Cache: Send + Sync + 'static {
+    async fn get_bytes(&self, key: &str) -> Result<Option<Vec<u8>>, CacheError>;
+
+    /// Stores `value`, without expiry when `ttl` is `None`.
+    async fn set_bytes(
+        &self,
+        key: &str,
+        value: Vec<u8>,
+        ttl: Option<Duration>,
+    ) -> Result<(), CacheError>;
+
+    async fn delete(&self, key: &str) -> Result<(), CacheError>;
+
+    /// Remaining time to live, `None` when the key is missing or never expires.
+    async fn ttl(&self, key: &str) -> Result<Option<Duration>, CacheError>;
+}
+
+// 同一 key 同时只有一个调用方执行加载
+static FLIGHTS: LazyLock<Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>> =
+    LazyLock::new(Default::default);
+
+/// A caller's share of the `FLIGHTS` entry of a key.
+///
+/// The entry is removed when the last caller drops its share, including callers whose future is
+/// dropped while waiting or loading.
+struct Flight<'a> {
+    key: &'a str,
+    lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl<'a> Flight<'a> {
+    fn join(key: &'a str) -> Self {
+        let lock = FLIGHTS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(key.to_string())
+            .or_default()
+            .clone();
+        Self { key, lock }
+    }
+}
+
+impl Drop for Flight<'_> {
+    fn drop(&mut self) {
+        let mut flights = FLIGHTS.lock().unwrap_or_else(|e| e.into_inner());
+        // 表中一份加上这里一份，说明没有其他等待者
+        if Arc::strong_count(&self.lock) == 2 {
+            flights.remove(self.key);
+        }
+    }
+}
+
+impl dyn Cache {
+    pub async fn get<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>, CacheError> {
+        match self.get_bytes(key).await? {
+            Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn set<T: Serialize + ?Sized>(
+        &self,
+        key: &str,
+        value: &T,
+        ttl: Option<Duration>,
+    ) -> Result<(), CacheError> {
+        self.set_bytes(key, serde_json::to_vec(value)?, ttl).await
+    }
+
+    /// Returns the cached value of `key`, or loads and caches it.
+    ///
+    /// Concurrent callers missing the same key wait for a single load instead of all hitting the
+    /// source. Cache failures are logged and fall back to `load`, errors of `load` are not cached.
+    pub async fn get_or_load<T, E, F, Fut>(
+        &self,
+        key: &str,
+        ttl: Option<Duration>,
+        load: F,
+    ) -> Result<T, E>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        if let Some(value) = self.cached(key).await {
+            return Ok(value);
+        }
+
+        let flight = Flight::join(key);
+        let _guard = flight.lock.lock().await;
+        // 等待期间可能已由其他调用方加载完成
+        match self.cached(key).await {
+            Some(value) => Ok(value),
+            None => match load().await {
+                Ok(value) => {
+                    if let Err(e) = self.set(key, &value, ttl).await {
+                        logging::warn!("[cache] failed to store '{}': {}", key, e);
+                    }
+                    Ok(value)
+                }
+                Err(e) => Err(e),
+            },
+        }
+    }
+
+    async fn cached<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        match self.get(key).await {
+            Ok(value) => value,
+            Err(e) => {
+                logging::warn!("[cache] failed to read '{}': {}", key, e);
+                None
+            }
+        }
+    }
+}
+
+/// Used by [`cacheable`], loads directly when no cache is configured.
+#[doc(hidden)]
+pub async fn cached<T, E, Fut>(key: &str, ttl: Option<Duration>, load: Fut) -> Result<T, E>
+where
+    T: Serialize + DeserializeOwned,
+    Fut: Future<Output = Result<T, E>>,
+{
+    match crate::GLOBAL_CACHE.get() {
+        Some(cache) => cache.get_or_load(key, ttl, || load).await,
+        None => load.await,
+    }
+}
+
+/// The cache configured by `[cache]`, cheap to clone and dereferencing to `dyn Cache`.
+#[derive(Clone)]
+pub struct SharedCache(Arc<dyn Cache>);
+
+impl SharedCache {
+    pub fn new<C: Cache>(cache: C) -> Self {
+        Self(Arc::new(cache))
+    }
+
+    pub(crate) fn from_config(
+        config: &config::Cache,
+        #[cfg(feature = "redis")] db_manager: &crate::DbManager,
+    ) -> anyhow::Result<Self> {
+        let memory = || MemoryCache::new(config.capacity);
+        #[cfg(feature = "redis")]
+        let redis = || -> anyhow::Result<RedisCache> {
+            let pool = match &config.redis {
+                Some(name) => db_manager.with_redis(name)?,
+                None => db_manager.with_redis_default()?,
+            };
+            Ok(RedisCache::new(pool, &config.prefix))
+        };
+
+        let cache = match config.mode {
+            CacheMode::Memory => Self::new(memory()),
+            #[cfg(feature = "redis")]
+            CacheMode::Redis => Self::new(redis()?),
+            #[cfg(feature = "redis")]
+            CacheMode::Tiered => Self::new(TieredCache::new(
+                memory(),
+                redis()?,
+                Duration::from_secs(config.local_ttl_secs),
+            )),
+            #[cfg(not(feature = "redis"))]
+            mode => anyhow::bail!("[cache] {:?} mode requires the `redis` feature", mode),
+        };
+        Ok(cache)
+    }
+}
+
+impl Default for SharedCache {
+    fn default() -> Self {
+        Self::new(MemoryCache::new(config::Cache::default().capacity))
+    }
+}
+
+impl Deref for SharedCache {
+    type Target = dyn Cache;
+
+    fn deref(&self) -> &Self::Target {
+        &*self.0
+    }
+}
+
+impl fmt::Debug for SharedCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedCache").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn in_flight(key: &str) -> bool {
+        FLIGHTS.lock().unwrap().contains_key(key)
+    }
+
+    #[tokio::test]
+    async fn cancelled_loads_release_their_flight() {
+        let cache = SharedCache::new(MemoryCache::new(16));
+        let key = "test:cancelled";
+        let load = cache.get_or_load(key, None, std::future::pending::<Result<u64, ()>>);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(10), load)
+                .await
+                .is_err()
+        );
+        assert!(!in_flight(key));
+
+        let loaded: Result<u64, ()> = cache.get_or_load(key, None, || async { Ok(7) }).await;
+        assert_eq!(loaded, Ok(7));
+        assert!(!in_flight(key));
+    }
+}
@@ -0,0 +1,64 @@
+use super::{Cache, CacheError};
+use async_trait::async_trait;
+use deadpool_redis::redis::AsyncCommands;
+use std::time::Duration;
+
+/// Cache on a redis datasource, keys are prefixed with `[cache] prefix`.
+pub struct RedisCache {
+    pool: deadpool_redis::Pool,
+    prefix: String,
+}
+
+impl RedisCache {
+    pub fn new(pool: deadpool_redis::Pool, prefix: &str) -> Self {
+        Self {
+            pool,
+            prefix: prefix.to_string(),
+        }
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+}
+
+#[async_trait]
+impl Cache for RedisCache {
+    async fn get_bytes(&self, key: &str) -> Result<Option<Vec<u8>>, CacheError> {
+        let mut conn = self.pool.get().await?;
+        Ok(conn.get(self.key(key)).await?)
+    }
+
+    async fn set_bytes(
+        &self,
+        key: &str,
+        value: Vec<u8>,
+        ttl: Option<Duration>,
+    ) -> Result<(), CacheError> {
+        let mut conn = self.pool.get().await?;
+        match ttl {
+            // 不足 1 毫秒按 1 毫秒计，PX 0 会被 redis 拒绝
+            Some(ttl) => {
+                let millis = (ttl.as_millis() as u64).max(1);
+                let _: () = conn.pset_ex(self.key(key), value, millis).await?;
+            }
+            None => {
+                let _: () = conn.set(self.key(key), value).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), CacheError> {
+        let mut conn = self.pool.get().await?;
+        let _: () = conn.del(self.key(key)).await?;
+        Ok(())
+    }
+
+    async fn ttl(&self, key: &str) -> Result<Option<Duration>, CacheError> {
+        let mut conn = self.pool.get().await?;
+        // -2 表示不存在，-1 表示永不过期
+        let millis: i64 = conn.pttl(self.key(key)).await?;
+        Ok((millis >= 0).then(|| Duration::from_millis(millis as u64)))
+    }
+}
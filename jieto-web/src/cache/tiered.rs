@@ -0,0 +1,120 @@
+use super::{Cache, CacheError};
+use async_trait::async_trait;
+use std::time::Duration;
+
+/// A local cache in front of a shared one.
+///
+/// Values read from or written to the remote tier are kept locally for at most `local_ttl`, which
+/// bounds how long an instance may serve a value changed or deleted by another instance, and never
+/// longer than they live in the remote tier.
+pub struct TieredCache<L, R> {
+    local: L,
+    remote: R,
+    local_ttl: Duration,
+}
+
+impl<L: Cache, R: Cache> TieredCache<L, R> {
+    pub fn new(local: L, remote: R, local_ttl: Duration) -> Self {
+        Self {
+            local,
+            remote,
+            local_ttl,
+        }
+    }
+
+    fn local_ttl(&self, ttl: Option<Duration>) -> Duration {
+        ttl.map_or(self.local_ttl, |ttl| ttl.min(self.local_ttl))
+    }
+}
+
+#[async_trait]
+impl<L: Cache, R: Cache> Cache for TieredCache<L, R> {
+    async fn get_bytes(&self, key: &str) -> Result<Option<Vec<u8>>, CacheError> {
+        if let Some(value) = self.local.get_bytes(key).await? {
+            return Ok(Some(value));
+        }
+        let Some(value) = self.remote.get_bytes(key).await? else {
+            return Ok(None);
+        };
+        let ttl = self.remote.ttl(key).await?;
+        self.local
+            .set_bytes(key, value.clone(), Some(self.local_ttl(ttl)))
+            .await?;
+        Ok(Some(value))
+    }
+
+    async fn set_bytes(
+        &self,
+        key: &str,
+        value: Vec<u8>,
+        ttl: Option<Duration>,
+    ) -> Result<(), CacheError> {
+        self.remote.set_bytes(key, value.clone(), ttl).await?;
+        self.local
+            .set_bytes(key, value, Some(self.local_ttl(ttl)))
+            .await
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), CacheError> {
+        self.local.delete(key).await?;
+        self.remote.delete(key).await
+    }
+
+    async fn ttl(&self, key: &str) -> Result<Option<Duration>, CacheError> {
+        self.remote.ttl(key).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::MemoryCache;
+
+    fn tiered() -> TieredCache<MemoryCache, MemoryCache> {
+        TieredCache::new(
+            MemoryCache::new(16),
+            MemoryCache::new(16),
+            Duration::from_secs(60),
+        )
+    }
+
+    #[tokio::test]
+    async fn local_ttl_is_bounded_by_the_remote_ttl() {
+        let cache = tiered();
+        cache
+            .set_bytes("short", b"a".to_vec(), Some(Duration::from_secs(5)))
+            .await
+            .unwrap();
+        cache.set_bytes("long", b"b".to_vec(), None).await.unwrap();
+
+        let local_ttl = |key| cache.local.ttl(key);
+        assert!(local_ttl("short").await.unwrap().unwrap() <= Duration::from_secs(5));
+        let long = local_ttl("long").await.unwrap().unwrap();
+        assert!(long > Duration::from_secs(5) && long <= Duration::from_secs(60));
+        assert_eq!(cache.remote.ttl("long").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn remote_values_fill_the_local_tier() {
+        let cache = tiered();
+        cache
+            .remote
+            .set_bytes("key", b"v".to_vec(), Some(Duration::from_secs(5)))
+            .await
+            .unwrap();
+        assert_eq!(cache.local.get_bytes("key").await.unwrap(), None);
+
+        assert_eq!(cache.get_bytes("key").await.unwrap(), Some(b"v".to_vec()));
+        assert_eq!(
+            cache.local.get_bytes("key").await.unwrap(),
+            Some(b"v".to_vec())
+        );
+        assert!(cache.local.ttl("key").await.unwrap().unwrap() <= Duration::from_secs(5));
+
+        // 本地副本在过期前继续提供远端已删除的值
+        cache.remote.delete("key").await.unwrap();
+        assert_eq!(cache.get_bytes("key").await.unwrap(), Some(b"v".to_vec()));
+        cache.delete("key").await.unwrap();
+        assert_eq!(cache.get_bytes("key").await.unwrap(), None);
+    }
+}
@@ -0,0 +1,147 @@
+//! Command line of applications built with `Application::cli`.
+
+use crate::config::ApplicationConfig;
+use crate::routes::RouteInfo;
+use std::fmt;
+
+pub(crate) const USAGE: &str = "\
+usage: <app> [--config <path>] [--profile <name>] [command]
+
+commands:
+  serve               start the server (default)
+  check-config        validate the configuration and exit
+  print-config        print the effective configuration with credentials masked
+  routes              list the routes registered through `Application::routes`
+  jobs list           list the registered scheduled tasks
+  jobs run <name>     run a scheduled task once and exit
+  migrate             apply the migrations of the SQL datasources
+
+options:
+  -c, --config <path>     configuration file, overrides APP_CONFIG
+  -p, --profile <name>    merge application-<name>.toml over it, overrides APP_PROFILE
+  -h, --help              print this help";
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Command {
+    Serve,
+    Help,
+    CheckConfig,
+    PrintConfig,
+    Routes,
+    #[cfg(feature = "job")]
+    JobsList,
+    #[cfg(feature = "job")]
+    JobsRun(String),
+    #[cfg(any(feature = "mysql", feature = "postgres", feature = "sqlite"))]
+    Migrate,
+}
+
+#[derive(Debug)]
+pub(crate) struct Cli {
+    pub command: Command,
+    pub config: Option<String>,
+    pub profile: Option<String>,
+}
+
+impl Cli {
+    pub(crate) fn parse(args: impl IntoIterator<Item = String>) -> anyhow::Result<Self> {
+        let mut config = None;
+        let mut profile = None;
+        let mut words = vec![];
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .ok_or_else(|| invalid(format_args!("{} requires a value", name)))
+            };
+            match arg.as_str() {
+                "-c" | "--config" => config = Some(value("--config")?),
+                "-p" | "--profile" => profile = Some(value("--profile")?),
+                "-h" | "--help" => words = vec![String::from("help")],
+                _ => {
+                    if let Some(path) = arg.strip_prefix("--config=") {
+                        config = Some(path.to_string());
+                    } else if let Some(name) = arg.strip_prefix("--profile=") {
+                        profile = Some(name.to_string());
+                    } else if arg.starts_with('-') {
+                        return Err(invalid(format_args!("unknown option '{}'", arg)));
+                    } else {
+                        words.push(arg);
+                    }
+                }
+            }
+        }
+
+        let words: Vec<&str> = words.iter().map(String::as_str).collect();
+        let command = match words.as_slice() {
+            [] | ["serve"] => Command::Serve,
+            ["help"] => Command::Help,
+            ["check-config"] => Command::CheckConfig,
+            ["print-config"] => Command::PrintConfig,
+            ["routes"] => Command::Routes,
+            #[cfg(feature = "job")]
+            ["jobs", "list"] => Command::JobsList,
+            #[cfg(feature = "job")]
+            ["jobs", "run", name] => Command::JobsRun(name.to_string()),
+            #[cfg(not(feature = "job"))]
+            ["jobs", ..] => anyhow::bail!("`jobs` requires the `job` feature"),
+            #[cfg(any(feature = "mysql", feature = "postgres", feature = "sqlite"))]
+            ["migrate"] => Command::Migrate,
+            #[cfg(not(any(feature = "mysql", feature = "postgres", feature = "sqlite")))]
+            ["migrate"] => {
+                anyhow::bail!("`migrate` requires the `mysql`, `postgres` or `sqlite` feature")
+            }
+            _ => {
+                return Err(invalid(format_args!(
+                    "unknown command '{}'",
+                    words.join(" ")
+                )));
+            }
+        };
+
+        Ok(Self {
+            command,
+            config,
+            profile,
+        })
+    }
+}
+
+fn invalid(message: fmt::Arguments) -> anyhow::Error {
+    anyhow::anyhow!("{}\n\n{}", message, USAGE)
+}
+
+/// Routes mounted by the framework from the configuration, listed after the application's.
+pub(crate) fn framework_routes(config: &ApplicationConfig) -> Vec<RouteInfo> {
+    let route = |method: &str, path: String, handler: &str| RouteInfo {
+        method: method.to_string(),
+        path,
+        handler: handler.to_string(),
+    };
+    let mut routes = vec![];
+    if config.management.enabled {
+        let handler = match config.management.port {
+            Some(port) => format!("(management, port {})", port),
+            None => String::from("(management)"),
+        };
+        let path = format!("{}/**", config.management.prefix.trim_end_matches('/'));
+        routes.push(route("*", path, &handler));
+    }
+    #[cfg(feature = "upload")]
+    if let Some(path) = &config.web.upload.download_path {
+        let path = format!("{}/{{key:.*}}", path.trim_end_matches('/'));
+        routes.push(route("GET", path, "(upload download)"));
+    }
+    #[cfg(feature = "ws")]
+    routes.push(route(
+        "GET",
+        config.ws.path.clone().unwrap_or(String::from("/ws")),
+        "(websocket)",
+    ));
+    for conf in &config.web.statics {
+        let path = format!("{}/**", conf.path.trim_end_matches('/'));
+        routes.push(route("GET", path, &format!("(static {})", conf.directory)));
+    }
+    routes
+}
@@ -1,19 +1,365 @@
-use serde::Deserialize;
-use tokio::fs::File;
-use tokio::io::AsyncReadExt;
+use ipnet::IpNet;
+use jieto_config::{ExposeSecret, Secret};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer};
+use std::fmt;
+use std::net::IpAddr;
 
-#[derive(Deserialize, Debug, Default)]
+#[derive(Deserialize, Default)]
 pub(crate) struct ApplicationConfig {
     pub name: Option<String>,
     pub web: Web,
     pub log: Log,
+    #[serde(default)]
+    pub management: Management,
     #[cfg(feature = "ws")]
     pub ws: Ws,
+    #[cfg(feature = "tracing")]
+    #[serde(default)]
+    pub tracing: Tracing,
+    #[cfg(feature = "sse")]
+    #[serde(default)]
+    pub sse: Sse,
+    #[cfg(feature = "cache")]
+    #[serde(default)]
+    pub cache: Cache,
+    #[cfg(feature = "flags")]
+    #[serde(default)]
+    pub flags: Flags,
+    #[cfg(feature = "events")]
+    #[serde(default)]
+    pub events: Events,
+    /// The whole file, including sections owned by other crates and the application.
+    #[serde(skip)]
+    pub raw: toml::Table,
+    /// Values of `raw` that were encrypted or read from secret files.
+    #[serde(skip)]
+    pub secrets: jieto_config::SecretPaths,
+}
+
+/// Prints the redacted file only, `raw` holds resolved secrets.
+impl fmt::Debug for ApplicationConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ApplicationConfig")
+            .field("name", &self.name)
+            .field("raw", &self.redacted())
+            .finish_non_exhaustive()
+    }
 }
 
 #[derive(Deserialize, Debug, Default)]
 pub(crate) struct Web {
     pub port: u16,
+    #[serde(default, rename = "static")]
+    pub statics: Vec<StaticFiles>,
+    #[serde(default)]
+    pub rate_limit: RateLimit,
+    #[serde(default)]
+    pub compression: Compression,
+    #[serde(default)]
+    pub limits: Limits,
+    #[serde(default)]
+    pub access_log: AccessLog,
+    #[serde(default)]
+    pub timeout: Timeout,
+    #[serde(default)]
+    pub security: Security,
+    #[serde(default)]
+    pub proxy: Proxy,
+    #[serde(default)]
+    pub ip_filter: IpFilter,
+    #[cfg(feature = "upload")]
+    #[serde(default)]
+    pub upload: Upload,
+    #[cfg(feature = "idempotency")]
+    #[serde(default)]
+    pub idempotency: Idempotency,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct StaticFiles {
+    /// URL prefix the directory is mounted under, e.g. `/admin`.
+    pub path: String,
+    pub directory: String,
+    #[serde(default = "default_index")]
+    pub index: String,
+    /// Serve the index file for unknown paths without an extension (client-side routing).
+    #[serde(default)]
+    pub spa: bool,
+    #[serde(default)]
+    pub cache_control: Option<String>,
+    /// Cache-Control for the index file, which usually must not be cached as long as assets.
+    #[serde(default)]
+    pub index_cache_control: Option<String>,
+    /// Look for `<file>.br` / `<file>.gz` siblings matching the client's Accept-Encoding.
+    #[serde(default)]
+    pub precompressed: bool,
+}
+
+fn default_index() -> String {
+    String::from("index.html")
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub(crate) struct RateLimit {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub backend: RateLimitBackend,
+    /// Redis datasource name, the default redis datasource when omitted.
+    #[serde(default)]
+    pub redis: Option<String>,
+    /// Applies to every request not matched by a rule.
+    #[serde(default)]
+    pub global: Option<LimitPolicy>,
+    #[serde(default)]
+    pub rules: Vec<RateLimitRule>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum RateLimitBackend {
+    #[default]
+    Memory,
+    Redis,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum RateLimitAlgorithm {
+    #[default]
+    TokenBucket,
+    SlidingWindow,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct LimitPolicy {
+    #[serde(default)]
+    pub algorithm: RateLimitAlgorithm,
+    /// Bucket size for token bucket, max requests per window for sliding window.
+    pub capacity: u64,
+    #[serde(default = "default_period_secs")]
+    pub period_secs: u64,
+    /// `ip`, `user` or `header:<name>`.
+    #[serde(default = "default_limit_key")]
+    pub key: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct RateLimitRule {
+    /// Path pattern, `*` matches within a segment and `**` matches any suffix.
+    pub pattern: String,
+    /// Restrict the rule to these methods, all methods when empty.
+    #[serde(default)]
+    pub methods: Vec<String>,
+    #[serde(flatten)]
+    pub policy: LimitPolicy,
+}
+
+fn default_period_secs() -> u64 {
+    1
+}
+
+fn default_limit_key() -> String {
+    String::from("ip")
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct Compression {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Content codings offered to clients: `br`, `gzip`, `deflate`, `zstd`.
+    #[serde(default = "default_algorithms")]
+    pub algorithms: Vec<String>,
+    /// Responses with a known body size below this are sent uncompressed.
+    #[serde(default = "default_min_size", deserialize_with = "deserialize_size")]
+    pub min_size: usize,
+    /// Content type prefixes that are never compressed, e.g. `image/` or `application/zip`.
+    #[serde(default = "default_excluded_types")]
+    pub exclude_content_types: Vec<String>,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            algorithms: default_algorithms(),
+            min_size: default_min_size(),
+            exclude_content_types: default_excluded_types(),
+        }
+    }
+}
+
+fn default_algorithms() -> Vec<String> {
+    vec![
+        String::from("br"),
+        String::from("gzip"),
+        String::from("zstd"),
+    ]
+}
+
+fn default_min_size() -> usize {
+    1024
+}
+
+fn default_excluded_types() -> Vec<String> {
+    vec![
+        String::from("image/"),
+        String::from("video/"),
+        String::from("audio/"),
+        String::from("application/zip"),
+        String::from("application/gzip"),
+    ]
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct Limits {
+    #[serde(default = "default_json_limit", deserialize_with = "deserialize_size")]
+    pub json: usize,
+    #[serde(default = "default_form_limit", deserialize_with = "deserialize_size")]
+    pub form: usize,
+    /// Raw bodies read through `Bytes` or `String`. `web::Payload` streams are not capped by this
+    /// limit, handlers reading them enforce their own.
+    #[serde(
+        default = "default_payload_limit",
+        deserialize_with = "deserialize_size"
+    )]
+    pub payload: usize,
+    #[serde(default)]
+    pub routes: Vec<RouteLimits>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            json: default_json_limit(),
+            form: default_form_limit(),
+            payload: default_payload_limit(),
+            routes: vec![],
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct RouteLimits {
+    pub pattern: String,
+    #[serde(default, deserialize_with = "deserialize_opt_size")]
+    pub json: Option<usize>,
+    #[serde(default, deserialize_with = "deserialize_opt_size")]
+    pub form: Option<usize>,
+    #[serde(default, deserialize_with = "deserialize_opt_size")]
+    pub payload: Option<usize>,
+}
+
+fn default_json_limit() -> usize {
+    2 * 1024 * 1024
+}
+
+fn default_form_limit() -> usize {
+    16 * 1024
+}
+
+fn default_payload_limit() -> usize {
+    256 * 1024
+}
+
+/// Sizes are either a byte count or a string with a `B`/`KB`/`MB`/`GB` unit, e.g. `"10MB"`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Size {
+    Bytes(usize),
+    Text(String),
+}
+
+impl Size {
+    fn into_bytes<E: serde::de::Error>(self) -> Result<usize, E> {
+        let text = match self {
+            Size::Bytes(bytes) => return Ok(bytes),
+            Size::Text(text) => text,
+        };
+        let upper = text.trim().to_uppercase();
+        let split = upper
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(upper.len());
+        let (number, unit) = upper.split_at(split);
+        let number: usize = number
+            .parse()
+            .map_err(|_| E::custom(format!("invalid size '{text}'")))?;
+        let multiplier = match unit.trim() {
+            "" | "B" => 1,
+            "K" | "KB" => 1024,
+            "M" | "MB" => 1024 * 1024,
+            "G" | "GB" => 1024 * 1024 * 1024,
+            _ => return Err(E::custom(format!("invalid size unit in '{text}'"))),
+        };
+        number
+            .checked_mul(multiplier)
+            .ok_or_else(|| E::custom(format!("size '{text}' is too large")))
+    }
+}
+
+fn deserialize_size<'de, D: Deserializer<'de>>(deserializer: D) -> Result<usize, D::Error> {
+    Size::deserialize(deserializer)?.into_bytes()
+}
+
+fn deserialize_opt_size<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<usize>, D::Error> {
+    Option::<Size>::deserialize(deserializer)?
+        .map(Size::into_bytes)
+        .transpose()
+}
+
+/// Networks are written as CIDRs or single addresses, e.g. `"10.0.0.0/8"` or `"127.0.0.1"`.
+pub(crate) fn deserialize_networks<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<IpNet>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|network| {
+            let network = network.trim();
+            network
+                .parse::<IpNet>()
+                .or_else(|_| network.parse::<IpAddr>().map(IpNet::from))
+                .map_err(|_| serde::de::Error::custom(format!("invalid network '{network}'")))
+        })
+        .collect()
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub(crate) struct Proxy {
+    /// Proxies whose `Forwarded` / `X-Forwarded-For` / `X-Real-IP` headers are honoured.
+    #[serde(default, deserialize_with = "deserialize_networks")]
+    pub trusted: Vec<IpNet>,
+}
+
+/// Client IP allow and deny lists, re-read from the configuration file while running.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub(crate) struct IpFilter {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Only these networks may connect, any network when empty.
+    #[serde(default, deserialize_with = "deserialize_networks")]
+    pub allow: Vec<IpNet>,
+    /// Rejected even when allowed.
+    #[serde(default, deserialize_with = "deserialize_networks")]
+    pub deny: Vec<IpNet>,
+    /// Applied in addition to the global lists on matching paths.
+    #[serde(default)]
+    pub routes: Vec<RouteIpFilter>,
+    /// How often the configuration file is checked for changes, every 10 seconds when the filter
+    /// is enabled. 0 disables reloading.
+    #[serde(default)]
+    pub reload_secs: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct RouteIpFilter {
+    pub pattern: String,
+    #[serde(default, deserialize_with = "deserialize_networks")]
+    pub allow: Vec<IpNet>,
+    #[serde(default, deserialize_with = "deserialize_networks")]
+    pub deny: Vec<IpNet>,
 }
 
 #[derive(Deserialize, Debug, Default)]
@@ -23,9 +369,438 @@ pub(crate) struct Log {
     #[serde(default)]
     pub filename_prefix: Option<String>,
     pub max_size_mb: Option<u64>,
+    /// `day` rotates when the local date changes, i.e. at local midnight.
     pub age: Option<String>,
-    pub keep_files: usize,
+    /// Rotated files kept uncompressed, rotated files are never removed when no retention is set.
+    #[serde(default)]
+    pub keep_files: Option<usize>,
+    /// Rotated files kept gzip-compressed after the `keep_files` plain ones.
+    #[serde(default)]
+    pub keep_compressed_files: Option<usize>,
+    /// Remove rotated files older than this many days, instead of counting files.
+    #[serde(default)]
+    pub keep_days: Option<usize>,
+    #[serde(default)]
+    pub naming: LogNaming,
+    pub level: Option<String>,
+    /// Where log records go, a rotating file plus stderr when empty.
+    #[serde(default)]
+    pub sinks: Vec<LogSink>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum LogNaming {
+    /// `<prefix>_r2024-06-09_13-24-35.log`
+    #[default]
+    Timestamps,
+    /// `<prefix>_rCURRENT.log`, rotated to `<prefix>_r00000.log`, `<prefix>_r00001.log`, ...
+    Numbers,
+    /// `<prefix>_2024-06-09.log`, meant for `age = "day"`.
+    Date,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct LogSink {
+    pub kind: SinkKind,
+    /// Minimum level written by this sink, on top of `[log] level`.
+    #[serde(default)]
     pub level: Option<String>,
+    /// `detailed` for files and `default` for the console when omitted.
+    #[serde(default)]
+    pub format: Option<LogFormat>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum SinkKind {
+    Stdout,
+    Stderr,
+    File,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum LogFormat {
+    /// `LEVEL [module] message`
+    Default,
+    /// `[time] LEVEL [target] message`
+    Detailed,
+    Json,
+}
+
+#[cfg(feature = "upload")]
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct Upload {
+    /// Root directory of the local storage.
+    #[serde(default = "default_upload_directory")]
+    pub directory: String,
+    #[serde(
+        default = "default_max_file_size",
+        deserialize_with = "deserialize_size"
+    )]
+    pub max_file_size: usize,
+    /// Limit of all files and text fields of one request.
+    #[serde(
+        default = "default_max_total_size",
+        deserialize_with = "deserialize_size"
+    )]
+    pub max_total_size: usize,
+    #[serde(default = "default_max_files")]
+    pub max_files: usize,
+    /// Sniffed content types accepted, e.g. `image/*` or `application/pdf`, any type when empty.
+    /// Raster images and PDF by default.
+    #[serde(default = "default_allowed_types")]
+    pub allowed_types: Vec<String>,
+    /// Serve stored files under this path, e.g. `/files`.
+    #[serde(default)]
+    pub download_path: Option<String>,
+}
+
+#[cfg(feature = "upload")]
+impl Default for Upload {
+    fn default() -> Self {
+        Self {
+            directory: default_upload_directory(),
+            max_file_size: default_max_file_size(),
+            max_total_size: default_max_total_size(),
+            max_files: default_max_files(),
+            allowed_types: default_allowed_types(),
+            download_path: None,
+        }
+    }
+}
+
+#[cfg(feature = "upload")]
+fn default_allowed_types() -> Vec<String> {
+    ["image/png", "image/jpeg", "image/gif", "image/webp", "application/pdf"]
+        .map(String::from)
+        .to_vec()
+}
+
+#[cfg(feature = "upload")]
+fn default_upload_directory() -> String {
+    String::from("uploads")
+}
+
+#[cfg(feature = "upload")]
+fn default_max_file_size() -> usize {
+    10 * 1024 * 1024
+}
+
+#[cfg(feature = "upload")]
+fn default_max_total_size() -> usize {
+    50 * 1024 * 1024
+}
+
+#[cfg(feature = "upload")]
+fn default_max_files() -> usize {
+    10
+}
+
+#[cfg(feature = "idempotency")]
+#[derive(Deserialize, Debug)]
+pub(crate) struct Idempotency {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub backend: RateLimitBackend,
+    /// Redis datasource name, the default redis datasource when omitted.
+    #[serde(default)]
+    pub redis: Option<String>,
+    #[serde(default = "default_idempotency_header")]
+    pub header: String,
+    #[serde(default = "default_idempotency_methods")]
+    pub methods: Vec<String>,
+    /// Path patterns the keys are honoured on, every path when empty.
+    #[serde(default)]
+    pub paths: Vec<String>,
+    /// How long completed responses are replayed.
+    #[serde(default = "default_idempotency_ttl_secs")]
+    pub ttl_secs: u64,
+    /// Expiry of the in-progress marker, in case the instance handling the request dies.
+    #[serde(default = "default_idempotency_lock_secs")]
+    pub lock_secs: u64,
+    /// Largest request body hashed, larger requests carrying a key are rejected with 413.
+    #[serde(
+        default = "default_idempotency_max_body_size",
+        deserialize_with = "deserialize_size"
+    )]
+    pub max_body_size: usize,
+    /// Largest response stored for replay. Larger and streamed responses are passed through and
+    /// the key is released.
+    #[serde(
+        default = "default_idempotency_max_response_size",
+        deserialize_with = "deserialize_size"
+    )]
+    pub max_response_size: usize,
+}
+
+#[cfg(feature = "idempotency")]
+impl Default for Idempotency {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            backend: RateLimitBackend::default(),
+            redis: None,
+            header: default_idempotency_header(),
+            methods: default_idempotency_methods(),
+            paths: vec![],
+            ttl_secs: default_idempotency_ttl_secs(),
+            lock_secs: default_idempotency_lock_secs(),
+            max_body_size: default_idempotency_max_body_size(),
+            max_response_size: default_idempotency_max_response_size(),
+        }
+    }
+}
+
+#[cfg(feature = "idempotency")]
+fn default_idempotency_header() -> String {
+    String::from("Idempotency-Key")
+}
+
+#[cfg(feature = "idempotency")]
+fn default_idempotency_methods() -> Vec<String> {
+    vec![String::from("POST"), String::from("PATCH")]
+}
+
+#[cfg(feature = "idempotency")]
+fn default_idempotency_ttl_secs() -> u64 {
+    24 * 60 * 60
+}
+
+#[cfg(feature = "idempotency")]
+fn default_idempotency_lock_secs() -> u64 {
+    60
+}
+
+#[cfg(feature = "idempotency")]
+fn default_idempotency_max_body_size() -> usize {
+    1024 * 1024
+}
+
+#[cfg(feature = "idempotency")]
+fn default_idempotency_max_response_size() -> usize {
+    1024 * 1024
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct Timeout {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Applies to routes without an override.
+    #[serde(default = "default_timeout_secs")]
+    pub secs: u64,
+    /// 503 or 504.
+    #[serde(default = "default_timeout_status")]
+    pub status: u16,
+    /// `ApiResult` code of the timeout response.
+    #[serde(default = "default_timeout_code")]
+    pub code: u16,
+    #[serde(default)]
+    pub routes: Vec<RouteTimeout>,
+}
+
+impl Default for Timeout {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            secs: default_timeout_secs(),
+            status: default_timeout_status(),
+            code: default_timeout_code(),
+            routes: vec![],
+        }
+    }
+}
+
+impl Timeout {
+    fn validate(&self) -> anyhow::Result<()> {
+        if !matches!(self.status, 503 | 504) {
+            anyhow::bail!("[timeout] status must be 503 or 504, got {}", self.status);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct RouteTimeout {
+    pub pattern: String,
+    /// 0 disables the timeout, e.g. for uploads.
+    pub secs: u64,
+}
+
+fn default_timeout_secs() -> u64 {
+    30
+}
+
+fn default_timeout_status() -> u16 {
+    504
+}
+
+fn default_timeout_code() -> u16 {
+    5040
+}
+
+/// Response headers added by `middleware::security_headers`, an empty value omits the header.
+#[derive(Deserialize, Debug)]
+pub(crate) struct Security {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_hsts")]
+    pub hsts: String,
+    /// `{nonce}` is replaced with a fresh nonce per request, see `extract::CspNonce`.
+    #[serde(default)]
+    pub content_security_policy: String,
+    #[serde(default = "default_content_type_options")]
+    pub content_type_options: String,
+    #[serde(default = "default_frame_options")]
+    pub frame_options: String,
+    #[serde(default = "default_referrer_policy")]
+    pub referrer_policy: String,
+    #[serde(default)]
+    pub permissions_policy: String,
+    #[serde(default)]
+    pub routes: Vec<RouteSecurity>,
+}
+
+impl Default for Security {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            hsts: default_hsts(),
+            content_security_policy: String::new(),
+            content_type_options: default_content_type_options(),
+            frame_options: default_frame_options(),
+            referrer_policy: default_referrer_policy(),
+            permissions_policy: String::new(),
+            routes: vec![],
+        }
+    }
+}
+
+/// Overrides of the global headers for matching paths, unset fields are inherited.
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct RouteSecurity {
+    pub pattern: String,
+    #[serde(default)]
+    pub hsts: Option<String>,
+    #[serde(default)]
+    pub content_security_policy: Option<String>,
+    #[serde(default)]
+    pub content_type_options: Option<String>,
+    #[serde(default)]
+    pub frame_options: Option<String>,
+    #[serde(default)]
+    pub referrer_policy: Option<String>,
+    #[serde(default)]
+    pub permissions_policy: Option<String>,
+}
+
+fn default_hsts() -> String {
+    String::from("max-age=31536000; includeSubDomains")
+}
+
+fn default_content_type_options() -> String {
+    String::from("nosniff")
+}
+
+fn default_frame_options() -> String {
+    String::from("DENY")
+}
+
+fn default_referrer_policy() -> String {
+    String::from("strict-origin-when-cross-origin")
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct AccessLog {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Apache style format, see `middleware::access_log` for the supported directives.
+    #[serde(default = "default_access_format")]
+    pub format: String,
+    /// Path patterns that are not logged, e.g. health checks.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// Requests slower than this are logged at warn level and marked `[SLOW]`.
+    #[serde(default)]
+    pub slow_threshold_ms: Option<u64>,
+    /// Write access logs to their own rotating file instead of the application log.
+    #[serde(default)]
+    pub file: bool,
+    /// File name prefix of the access log file, `<app>_access` by default.
+    #[serde(default)]
+    pub filename_prefix: Option<String>,
+}
+
+impl Default for AccessLog {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            format: default_access_format(),
+            exclude: vec![],
+            slow_threshold_ms: None,
+            file: false,
+            filename_prefix: None,
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_access_format() -> String {
+    String::from(r#"%a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T"#)
+}
+
+#[derive(Deserialize, Debug)]
+pub(crate) struct Management {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_management_prefix")]
+    pub prefix: String,
+    /// Serve the endpoints on their own port instead of the application port.
+    #[serde(default)]
+    pub port: Option<u16>,
+    /// Required as `Authorization: Bearer <token>`, the endpoints cannot be enabled without it.
+    #[serde(default)]
+    pub token: Option<Secret>,
+}
+
+impl Management {
+    fn validate(&self) -> anyhow::Result<()> {
+        let has_token = self
+            .token
+            .as_ref()
+            .is_some_and(|token| !token.expose_secret().is_empty());
+        if self.enabled && !has_token {
+            anyhow::bail!("[management] enabled requires a token");
+        }
+        // 挂在根路径会覆盖应用的路由
+        if self.enabled && self.prefix.trim_matches('/').is_empty() {
+            anyhow::bail!(
+                "[management] prefix must not be empty or '/', got {:?}",
+                self.prefix
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Default for Management {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            prefix: default_management_prefix(),
+            port: None,
+            token: None,
+        }
+    }
+}
+
+fn default_management_prefix() -> String {
+    String::from("/management")
 }
 
 #[cfg(feature = "ws")]
@@ -36,12 +811,321 @@ pub(crate) struct Ws {
     pub heartbeat_interval: Option<u64>,
 }
 
+#[cfg(feature = "sse")]
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct Sse {
+    /// Interval of the keep-alive comments sent to idle streams.
+    #[serde(default = "default_keep_alive_secs")]
+    pub keep_alive_secs: u64,
+    /// Events kept per channel for clients resuming with `Last-Event-ID`.
+    #[serde(default = "default_sse_buffer_size")]
+    pub buffer_size: usize,
+    /// Events queued per client, clients falling further behind are disconnected.
+    #[serde(default = "default_sse_client_queue")]
+    pub client_queue: usize,
+    /// Channels without clients are dropped, with their buffered events, after being idle this
+    /// long.
+    #[serde(default = "default_sse_replay_secs")]
+    pub replay_secs: u64,
+}
+
+#[cfg(feature = "sse")]
+impl Default for Sse {
+    fn default() -> Self {
+        Self {
+            keep_alive_secs: default_keep_alive_secs(),
+            buffer_size: default_sse_buffer_size(),
+            client_queue: default_sse_client_queue(),
+            replay_secs: default_sse_replay_secs(),
+        }
+    }
+}
+
+#[cfg(feature = "sse")]
+fn default_keep_alive_secs() -> u64 {
+    15
+}
+
+#[cfg(feature = "sse")]
+fn default_sse_buffer_size() -> usize {
+    100
+}
+
+#[cfg(feature = "sse")]
+fn default_sse_client_queue() -> usize {
+    64
+}
+
+#[cfg(feature = "sse")]
+fn default_sse_replay_secs() -> u64 {
+    300
+}
+
+#[cfg(feature = "tracing")]
+#[derive(Deserialize, Debug)]
+pub(crate) struct Tracing {
+    #[serde(default)]
+    pub enabled: bool,
+    /// `service.name` of exported spans, the application name when omitted.
+    #[serde(default)]
+    pub service_name: Option<String>,
+    #[serde(default)]
+    pub exporter: TraceExporter,
+    /// OTLP/HTTP traces endpoint, used by the `otlp` exporter.
+    #[serde(default = "default_otlp_endpoint")]
+    pub endpoint: String,
+    /// Fraction of new traces that are sampled, requests carrying a `traceparent` follow the caller.
+    #[serde(default = "default_sample_ratio")]
+    pub sample_ratio: f64,
+}
+
+#[cfg(feature = "tracing")]
+impl Default for Tracing {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            service_name: None,
+            exporter: TraceExporter::default(),
+            endpoint: default_otlp_endpoint(),
+            sample_ratio: default_sample_ratio(),
+        }
+    }
+}
+
+#[cfg(feature = "tracing")]
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum TraceExporter {
+    /// OTLP over HTTP/protobuf, e.g. to an OpenTelemetry collector or Jaeger.
+    #[default]
+    Otlp,
+    /// Finished spans written to the application log.
+    Log,
+}
+
+#[cfg(feature = "tracing")]
+fn default_otlp_endpoint() -> String {
+    String::from("http://localhost:4318/v1/traces")
+}
+
+#[cfg(feature = "tracing")]
+fn default_sample_ratio() -> f64 {
+    1.0
+}
+
+static CONFIG_PATH: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+
+/// Uses `path` instead of `APP_CONFIG`, e.g. from `--config`. Only the first call has an effect.
+pub(crate) fn set_config_path(path: &str) {
+    let _ = CONFIG_PATH.set(path.to_string());
+}
+
+/// Path of the application configuration file.
+pub(crate) fn config_path() -> String {
+    if let Some(path) = CONFIG_PATH.get() {
+        return path.clone();
+    }
+    std::env::var("APP_CONFIG")
+        .or_else(|_| std::env::var("CONFIG_PATH"))
+        .unwrap_or_else(|_| "application.toml".to_string()) // 默认路径
+}
+
+/// Reads a custom top-level section of the application configuration, e.g. `[sms]`.
+///
+/// Values are resolved like the rest of the file: `${VAR}` references, `*_file` credentials and
+/// `ENC(...)` encrypted values.
+pub async fn section<T: DeserializeOwned>(name: &str) -> anyhow::Result<T> {
+    Ok(jieto_config::section(&config_path(), name).await?)
+}
+
 impl ApplicationConfig {
     pub(crate) async fn from_toml(path: &str) -> anyhow::Result<Self> {
-        let mut file = File::open(path).await?;
-        let mut contents = String::new();
-        file.read_to_string(&mut contents).await?;
-        let config: ApplicationConfig = toml::from_str(&contents)?;
+        let (raw, secrets) = jieto_config::load_with_secrets(path).await?;
+        let mut config: ApplicationConfig = raw.clone().try_into()?;
+        config.management.validate()?;
+        config.web.timeout.validate()?;
+        config.raw = raw;
+        config.secrets = secrets;
         Ok(config)
     }
+
+    /// The effective configuration with every credential and decrypted value masked.
+    pub(crate) fn redacted(&self) -> toml::Table {
+        jieto_config::redact(&self.raw, &self.secrets)
+    }
+}
+
+#[cfg(feature = "cache")]
+#[derive(Deserialize, Debug)]
+pub(crate) struct Cache {
+    #[serde(default)]
+    pub mode: CacheMode,
+    /// Entries kept by the in-process cache.
+    #[serde(default = "default_cache_capacity")]
+    pub capacity: usize,
+    /// Name of the redis datasource, the default one when omitted.
+    #[cfg(feature = "redis")]
+    pub redis: Option<String>,
+    /// Prefix of the redis keys.
+    #[cfg(feature = "redis")]
+    #[serde(default)]
+    pub prefix: String,
+    /// How long the tiered mode keeps values in process.
+    #[cfg(feature = "redis")]
+    #[serde(default = "default_cache_local_ttl_secs")]
+    pub local_ttl_secs: u64,
+}
+
+#[cfg(feature = "cache")]
+impl Default for Cache {
+    fn default() -> Self {
+        Self {
+            mode: CacheMode::default(),
+            capacity: default_cache_capacity(),
+            #[cfg(feature = "redis")]
+            redis: None,
+            #[cfg(feature = "redis")]
+            prefix: String::new(),
+            #[cfg(feature = "redis")]
+            local_ttl_secs: default_cache_local_ttl_secs(),
+        }
+    }
+}
+
+#[cfg(feature = "cache")]
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum CacheMode {
+    #[default]
+    Memory,
+    Redis,
+    /// In-process cache in front of redis.
+    Tiered,
+}
+
+#[cfg(feature = "cache")]
+fn default_cache_capacity() -> usize {
+    10_000
+}
+
+#[cfg(all(feature = "cache", feature = "redis"))]
+fn default_cache_local_ttl_secs() -> u64 {
+    60
+}
+
+#[cfg(feature = "flags")]
+#[derive(Deserialize, Debug)]
+#[cfg_attr(not(feature = "redis"), derive(Default))]
+pub(crate) struct Flags {
+    /// Flag name to `true`, `false` or a rollout percentage.
+    #[serde(default)]
+    pub rules: std::collections::BTreeMap<String, crate::flags::Rollout>,
+    /// Name of the redis datasource, the default one when omitted.
+    #[cfg(feature = "redis")]
+    #[serde(default)]
+    pub redis: Option<String>,
+    /// Redis hash overriding the rules, no overrides are read when omitted.
+    #[cfg(feature = "redis")]
+    #[serde(default)]
+    pub hash: Option<String>,
+    #[cfg(feature = "redis")]
+    #[serde(default = "default_flags_refresh_secs")]
+    pub refresh_secs: u64,
+}
+
+#[cfg(all(feature = "flags", feature = "redis"))]
+impl Default for Flags {
+    fn default() -> Self {
+        Self {
+            rules: Default::default(),
+            redis: None,
+            hash: None,
+            refresh_secs: default_flags_refresh_secs(),
+        }
+    }
+}
+
+#[cfg(all(feature = "flags", feature = "redis"))]
+fn default_flags_refresh_secs() -> u64 {
+    30
+}
+
+#[cfg(feature = "events")]
+#[derive(Deserialize, Debug, Clone)]
+pub(crate) struct Events {
+    /// Events queued per subscriber lane unless the subscription sets its own capacity.
+    #[serde(default = "default_events_capacity")]
+    pub capacity: usize,
+    /// How long shutdown waits for queued events to be handled.
+    #[serde(default = "default_events_drain_secs")]
+    pub drain_secs: u64,
+}
+
+#[cfg(feature = "events")]
+impl Default for Events {
+    fn default() -> Self {
+        Self {
+            capacity: default_events_capacity(),
+            drain_secs: default_events_drain_secs(),
+        }
+    }
+}
+
+#[cfg(feature = "events")]
+fn default_events_capacity() -> usize {
+    1024
+}
+
+#[cfg(feature = "events")]
+fn default_events_drain_secs() -> u64 {
+    10
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_reject_overflow() {
+        let limits: Limits = toml::from_str(r#"json = "2MB""#).unwrap();
+        assert_eq!(limits.json, 2 * 1024 * 1024);
+        let err = toml::from_str::<Limits>(r#"json = "20000000000GB""#).unwrap_err();
+        assert!(err.to_string().contains("too large"), "{err}");
+    }
+
+    #[test]
+    fn management_prefix_must_not_be_the_root() {
+        let management = |prefix: &str| {
+            toml::from_str::<Management>(&format!(
+                "enabled = true\ntoken = \"secret\"\nprefix = {}",
+                toml::Value::String(prefix.to_string())
+            ))
+            .unwrap()
+        };
+        assert!(management("/management").validate().is_ok());
+        assert!(management("/ops/").validate().is_ok());
+        for prefix in ["", "/", "//"] {
+            let err = management(prefix).validate().unwrap_err();
+            assert!(err.to_string().contains("must not be empty"), "{err}");
+        }
+    }
+
+    #[tokio::test]
+    async fn timeout_status_is_checked_on_load() {
+        let path = std::env::temp_dir().join(format!("jieto-timeout-{}.toml", std::process::id()));
+        let write = |status: u16| {
+            let config =
+                format!("[web]\nport = 8080\n[web.timeout]\nstatus = {status}\n[log]\n[ws]\n");
+            std::fs::write(&path, config).unwrap();
+        };
+        let path_str = path.to_string_lossy().to_string();
+
+        write(503);
+        let config = ApplicationConfig::from_toml(&path_str).await.unwrap();
+        assert_eq!(config.web.timeout.status, 503);
+        write(500);
+        let err = ApplicationConfig::from_toml(&path_str).await.unwrap_err();
+        assert!(err.to_string().contains("503 or 504"), "{err}");
+        std::fs::remove_file(&path).unwrap();
+    }
 }
@@ -8,6 +8,11 @@ pub enum WebError {
     Business(u16, String),
     #[error("[WEB]:{0}")]
     Web(#[from] actix_web::Error),
+    #[error("[HTTP]:{1}")]
+    Http(actix_web::http::StatusCode, String),
+    /// Status, `ApiResult` code and the timeout that elapsed.
+    #[error("[TIMEOUT]:request timed out after {2:?}")]
+    Timeout(actix_web::http::StatusCode, u16, std::time::Duration),
     #[cfg(feature = "database")]
     #[error("[DB]:{0}")]
     DataSource(#[from] jieto_db::error::DbError),
@@ -17,18 +22,25 @@ pub enum WebError {
     #[error("[AU]:{0}")]
     #[cfg(feature = "auth")]
     Auth(#[from] jieto_auth::error::AuthError),
+    #[cfg(feature = "cache")]
+    #[error("[CACHE]:{0}")]
+    Cache(#[from] crate::cache::CacheError),
 }
 
 impl ResponseError for WebError {
     fn status_code(&self) -> actix_web::http::StatusCode {
         match self {
             WebError::Web(_) => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
+            WebError::Http(status, _) => *status,
+            WebError::Timeout(status, ..) => *status,
             #[cfg(feature = "database")]
             WebError::DataSource(_) => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
             #[cfg(any(feature = "mysql", feature = "postgres", feature = "sqlite"))]
             WebError::Execution(_) => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
             #[cfg(feature = "auth")]
             WebError::Auth(_) => actix_web::http::StatusCode::FORBIDDEN,
+            #[cfg(feature = "cache")]
+            WebError::Cache(_) => actix_web::http::StatusCode::INTERNAL_SERVER_ERROR,
             WebError::Business(..) => actix_web::http::StatusCode::default(),
         }
     }
@@ -36,6 +48,7 @@ impl ResponseError for WebError {
         let status = self.status_code();
         let (code, msg) = match self {
             WebError::Business(biz_code, msg) => (*biz_code, msg.clone()),
+            WebError::Timeout(_, code, _) => (*code, format!("{}", self)),
             _ => (status.as_u16(), format!("{}", self)),
         };
 
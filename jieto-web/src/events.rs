@@ -0,0 +1,524 @@
+//! In-process events.
+//!
+//! Handlers, scheduled tasks and websocket handlers publish typed events through the
+//! [`EventBus`] in `AppState`, or [`GLOBAL_EVENTS`](crate::GLOBAL_EVENTS) outside handlers.
+//! Subscribers are registered on the `Application` and handle the events in the background:
+//!
+//! ```ignore
+//! #[derive(Debug)]
+//! struct OrderPlaced {
+//!     order_id: u64,
+//!     email: String,
+//! }
+//!
+//! Application::new(configure)
+//!     .subscribe(Subscription::new("order_mail", |event: Arc<OrderPlaced>, _state| async move {
+//!         mailer::send_confirmation(&event.email, event.order_id).await
+//!     }))
+//!     .subscribe(
+//!         Subscription::new("order_cache", |event: Arc<OrderPlaced>, state| async move {
+//!             state.cache.delete(&format!("order:{}", event.order_id)).await?;
+//!             Ok(())
+//!         })
+//!         .ordered_by(4, |event| event.order_id),
+//!     )
+//!     .run()
+//!     .await
+//!
+//! #[post("/orders")]
+//! async fn place(state: web::Data<AppState>, order: web::Json<NewOrder>) -> JietoResult<u64> {
+//!     let order_id = ...;
+//!     state.events.publish(OrderPlaced { order_id, email: order.email.clone() }).await;
+//!     ApiResult::ok(order_id)
+//! }
+//! ```
+//!
+//! Every subscriber has its own bounded queues, so a slow or failing subscriber does not hold up
+//! the others. Errors and panics of a subscriber are logged and counted in
+//! `events_failed_total`, the event is not retried. [`EventBus::publish`] waits while a queue is
+//! full, [`EventBus::try_publish`] drops the event for that subscriber instead.
+
+use crate::AppState;
+use crate::config;
+use crate::logging;
+use crate::metrics;
+use crate::middleware::catch_panic::payload_message;
+use actix_web::web;
+use futures_util::FutureExt as _;
+use futures_util::future::BoxFuture;
+use std::any::{Any, TypeId, type_name};
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::time::Instant;
+
+type Payload = Arc<dyn Any + Send + Sync>;
+type Handler = Arc<
+    dyn Fn(Payload, web::Data<AppState>) -> BoxFuture<'static, anyhow::Result<()>> + Send + Sync,
+>;
+type KeyFn = Arc<dyn Fn(&(dyn Any + Send + Sync)) -> u64 + Send + Sync>;
+
+/// A subscriber of events of type `E`, registered with `Application::subscribe`.
+///
+/// By default events are handled one at a time in publication order.
+pub struct Subscription<E> {
+    registration: Registration,
+    _event: PhantomData<fn(E)>,
+}
+
+pub(crate) struct Registration {
+    event: TypeId,
+    event_name: &'static str,
+    name: &'static str,
+    handler: Handler,
+    lanes: usize,
+    capacity: Option<usize>,
+    key: Option<KeyFn>,
+}
+
+impl<E: Send + Sync + 'static> Subscription<E> {
+    /// `name` identifies the subscriber in logs and metrics.
+    pub fn new<F, Fut>(name: &'static str, handler: F) -> Self
+    where
+        F: Fn(Arc<E>, web::Data<AppState>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        let handler: Handler = Arc::new(move |event, state| {
+            // unwrap: events are only queued for subscribers of their type
+            let event = event.downcast::<E>().unwrap();
+            Box::pin(handler(event, state))
+        });
+        Self {
+            registration: Registration {
+                event: TypeId::of::<E>(),
+                event_name: type_name::<E>(),
+                name,
+                handler,
+                lanes: 1,
+                capacity: None,
+                key: None,
+            },
+            _event: PhantomData,
+        }
+    }
+
+    /// Handles up to `concurrency` events at a time, in no particular order.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.registration.lanes = concurrency.max(1);
+        self.registration.key = None;
+        self
+    }
+
+    /// Handles events with the same key one at a time in publication order, events with
+    /// different keys on up to `lanes` workers at a time.
+    pub fn ordered_by<K, F>(mut self, lanes: usize, key: F) -> Self
+    where
+        K: Hash,
+        F: Fn(&E) -> K + Send + Sync + 'static,
+    {
+        self.registration.lanes = lanes.max(1);
+        self.registration.key = Some(Arc::new(move |event| {
+            // unwrap: events are only queued for subscribers of their type
+            let event = event.downcast_ref::<E>().unwrap();
+            let mut hasher = DefaultHasher::new();
+            key(event).hash(&mut hasher);
+            hasher.finish()
+        }));
+        self
+    }
+
+    /// Events queued per worker, `[events] capacity` by default.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.registration.capacity = Some(capacity.max(1));
+        self
+    }
+
+    pub(crate) fn into_registration(self) -> Registration {
+        self.registration
+    }
+}
+
+/// The queues of one subscriber.
+struct Subscriber {
+    name: &'static str,
+    lanes: Vec<mpsc::Sender<Payload>>,
+    key: Option<KeyFn>,
+    next: AtomicUsize,
+}
+
+impl Subscriber {
+    fn lane(&self, event: &(dyn Any + Send + Sync)) -> &mpsc::Sender<Payload> {
+        let index = match &self.key {
+            Some(key) => key(event) as usize,
+            None => self.next.fetch_add(1, Ordering::Relaxed),
+        };
+        &self.lanes[index % self.lanes.len()]
+    }
+}
+
+/// A queue waiting for [`EventBus::start`].
+struct Worker {
+    name: &'static str,
+    event_name: &'static str,
+    handler: Handler,
+    receiver: mpsc::Receiver<Payload>,
+}
+
+/// Returned by [`EventBus::try_publish`] when queues were full.
+#[derive(Debug, thiserror::Error)]
+#[error("event dropped for {0:?}, their queues are full")]
+pub struct QueueFull(pub Vec<&'static str>);
+
+#[derive(Default)]
+struct Inner {
+    subscribers: HashMap<TypeId, Vec<Subscriber>>,
+    workers: Mutex<Vec<Worker>>,
+    /// Events queued or being handled.
+    pending: Arc<AtomicUsize>,
+}
+
+/// Publishes events to the subscribers registered on the `Application`, available as
+/// `AppState::events`.
+#[derive(Clone, Default)]
+pub struct EventBus {
+    inner: Arc<Inner>,
+}
+
+impl EventBus {
+    pub(crate) fn new(config: &config::Events, registrations: Vec<Registration>) -> Self {
+        let mut subscribers: HashMap<TypeId, Vec<Subscriber>> = HashMap::new();
+        let mut workers = vec![];
+        for registration in registrations {
+            let capacity = registration.capacity.unwrap_or(config.capacity.max(1));
+            let mut lanes = vec![];
+            for _ in 0..registration.lanes {
+                let (sender, receiver) = mpsc::channel(capacity);
+                lanes.push(sender);
+                workers.push(Worker {
+                    name: registration.name,
+                    event_name: registration.event_name,
+                    handler: registration.handler.clone(),
+                    receiver,
+                });
+            }
+            logging::info!(
+                "[events] {} subscribed to {} ({} workers)",
+                registration.name,
+                registration.event_name,
+                registration.lanes
+            );
+            subscribers
+                .entry(registration.event)
+                .or_default()
+                .push(Subscriber {
+                    name: registration.name,
+                    lanes,
+                    key: registration.key,
+                    next: AtomicUsize::new(0),
+                });
+        }
+        Self {
+            inner: Arc::new(Inner {
+                subscribers,
+                workers: Mutex::new(workers),
+                pending: Arc::default(),
+            }),
+        }
+    }
+
+    /// Spawns the workers, events published before are queued until then.
+    pub(crate) fn start(&self, state: web::Data<AppState>) {
+        // unwrap: the lock is never held across a panic
+        let workers = std::mem::take(&mut *self.inner.workers.lock().unwrap());
+        for worker in workers {
+            tokio::spawn(run(worker, state.clone(), self.inner.pending.clone()));
+        }
+    }
+
+    /// Queues `event` for every subscriber of `E`, waiting while one of their queues is full.
+    pub async fn publish<E: Send + Sync + 'static>(&self, event: E) {
+        let Some(subscribers) = self.inner.subscribers.get(&TypeId::of::<E>()) else {
+            return;
+        };
+        let event: Payload = Arc::new(event);
+        for subscriber in subscribers {
+            self.inner.pending.fetch_add(1, Ordering::AcqRel);
+            // 接收端由 worker 持有且不会提前退出
+            if subscriber.lane(&*event).send(event.clone()).await.is_err() {
+                self.inner.pending.fetch_sub(1, Ordering::AcqRel);
+            }
+        }
+    }
+
+    /// Queues `event` for every subscriber of `E` without waiting, dropping it for subscribers
+    /// whose queue is full. Usable outside async code, e.g. in actors.
+    pub fn try_publish<E: Send + Sync + 'static>(&self, event: E) -> Result<(), QueueFull> {
+        let Some(subscribers) = self.inner.subscribers.get(&TypeId::of::<E>()) else {
+            return Ok(());
+        };
+        let event: Payload = Arc::new(event);
+        let mut full = vec![];
+        for subscriber in subscribers {
+            self.inner.pending.fetch_add(1, Ordering::AcqRel);
+            match subscriber.lane(&*event).try_send(event.clone()) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    self.inner.pending.fetch_sub(1, Ordering::AcqRel);
+                    logging::warn!(
+                        "[events] queue of {} is full, dropping {}",
+                        subscriber.name,
+                        type_name::<E>()
+                    );
+                    metrics::increment(format!(
+                        "events_dropped_total{{subscriber=\"{}\"}}",
+                        subscriber.name
+                    ));
+                    full.push(subscriber.name);
+                }
+                Err(TrySendError::Closed(_)) => {
+                    self.inner.pending.fetch_sub(1, Ordering::AcqRel);
+                }
+            }
+        }
+        if full.is_empty() {
+            Ok(())
+        } else {
+            Err(QueueFull(full))
+        }
+    }
+
+    /// Waits up to `timeout` for the queued events to be handled.
+    pub(crate) async fn drain(&self, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+        while self.inner.pending.load(Ordering::Acquire) > 0 && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let pending = self.inner.pending.load(Ordering::Acquire);
+        if pending > 0 {
+            logging::warn!(
+                "[events] {} events were not handled before shutdown",
+                pending
+            );
+        }
+    }
+}
+
+async fn run(mut worker: Worker, state: web::Data<AppState>, pending: Arc<AtomicUsize>) {
+    while let Some(event) = worker.receiver.recv().await {
+        let handled = AssertUnwindSafe(async { (worker.handler)(event, state.clone()).await })
+            .catch_unwind()
+            .await;
+        let failed = match handled {
+            Ok(Ok(())) => false,
+            Ok(Err(e)) => {
+                logging::error!(
+                    "[events] {} failed to handle {}: {:#}",
+                    worker.name,
+                    worker.event_name,
+                    e
+                );
+                true
+            }
+            Err(payload) => {
+                logging::error!(
+                    "[events] {} panicked handling {}: '{}'",
+                    worker.name,
+                    worker.event_name,
+                    payload_message(payload.as_ref())
+                );
+                true
+            }
+        };
+        if failed {
+            metrics::increment(format!(
+                "events_failed_total{{subscriber=\"{}\"}}",
+                worker.name
+            ));
+        }
+        pending.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl fmt::Debug for EventBus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let subscribers: Vec<&str> = self
+            .inner
+            .subscribers
+            .values()
+            .flatten()
+            .map(|subscriber| subscriber.name)
+            .collect();
+        f.debug_struct("EventBus")
+            .field("subscribers", &subscribers)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Keyed {
+        key: u64,
+        seq: u64,
+    }
+
+    fn bus(subscriptions: Vec<Registration>) -> EventBus {
+        let bus = EventBus::new(&config::Events::default(), subscriptions);
+        bus.start(web::Data::new(AppState::default()));
+        bus
+    }
+
+    fn pending(bus: &EventBus) -> usize {
+        bus.inner.pending.load(Ordering::Acquire)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn events_with_the_same_key_are_handled_in_order() {
+        let seen = Arc::new(Mutex::new(vec![]));
+        let handled = seen.clone();
+        let subscription = Subscription::new("ordered", move |event: Arc<Keyed>, _| {
+            let handled = handled.clone();
+            async move {
+                // 不同 key 的事件交错完成
+                tokio::time::sleep(Duration::from_millis(event.seq * 7 % 5)).await;
+                handled.lock().unwrap().push((event.key, event.seq));
+                Ok(())
+            }
+        })
+        .ordered_by(4, |event: &Keyed| event.key);
+        let bus = bus(vec![subscription.into_registration()]);
+
+        for seq in 0..20 {
+            for key in 0..3 {
+                bus.publish(Keyed { key, seq }).await;
+            }
+        }
+        bus.drain(Duration::from_secs(10)).await;
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 60);
+        for key in 0..3 {
+            let order: Vec<u64> = seen
+                .iter()
+                .filter(|(k, _)| *k == key)
+                .map(|(_, seq)| *seq)
+                .collect();
+            assert_eq!(order, (0..20).collect::<Vec<_>>());
+        }
+    }
+
+    #[tokio::test]
+    async fn try_publish_drops_the_event_for_full_queues() {
+        let counter = |name, count: Arc<AtomicUsize>| {
+            Subscription::new(name, move |_: Arc<u32>, _| {
+                count.fetch_add(1, Ordering::SeqCst);
+                async { Ok(()) }
+            })
+        };
+        let small = Arc::new(AtomicUsize::new(0));
+        let large = Arc::new(AtomicUsize::new(0));
+        let bus = EventBus::new(
+            &config::Events::default(),
+            vec![
+                counter("small", small.clone())
+                    .capacity(1)
+                    .into_registration(),
+                counter("large", large.clone()).into_registration(),
+            ],
+        );
+
+        // 尚未启动，事件留在队列中
+        assert!(bus.try_publish(1u32).is_ok());
+        let full = bus.try_publish(2u32).unwrap_err();
+        assert_eq!(full.0, ["small"]);
+        assert_eq!(pending(&bus), 3);
+
+        bus.start(web::Data::new(AppState::default()));
+        bus.drain(Duration::from_secs(10)).await;
+        assert_eq!(small.load(Ordering::SeqCst), 1);
+        assert_eq!(large.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn failing_subscribers_do_not_stop_the_others() {
+        let panics = Arc::new(AtomicUsize::new(0));
+        let handled = Arc::new(AtomicUsize::new(0));
+        let panicking = {
+            let panics = panics.clone();
+            Subscription::new("panicking", move |_: Arc<u32>, _| {
+                let panics = panics.clone();
+                async move {
+                    panics.fetch_add(1, Ordering::SeqCst);
+                    panic!("subscriber panicked");
+                }
+            })
+        };
+        let failing = Subscription::new("failing", |_: Arc<u32>, _| async {
+            Err(anyhow::anyhow!("subscriber failed"))
+        });
+        let counting = {
+            let handled = handled.clone();
+            Subscription::new("counting", move |_: Arc<u32>, _| {
+                handled.fetch_add(1, Ordering::SeqCst);
+                async { Ok(()) }
+            })
+        };
+        let bus = bus(vec![
+            panicking.into_registration(),
+            failing.into_registration(),
+            counting.into_registration(),
+        ]);
+
+        for event in 0..3u32 {
+            bus.publish(event).await;
+        }
+        bus.drain(Duration::from_secs(10)).await;
+
+        assert_eq!(pending(&bus), 0);
+        assert_eq!(handled.load(Ordering::SeqCst), 3);
+        // 同一 worker 在 panic 后继续处理后续事件
+        assert_eq!(panics.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn drain_waits_for_pending_events() {
+        let done = Arc::new(AtomicUsize::new(0));
+        let slow = {
+            let done = done.clone();
+            Subscription::new("slow", move |event: Arc<u64>, _| {
+                let done = done.clone();
+                async move {
+                    tokio::time::sleep(Duration::from_secs(*event)).await;
+                    done.fetch_add(1, Ordering::SeqCst);
+                    Ok(())
+                }
+            })
+        };
+        let bus = bus(vec![slow.into_registration()]);
+
+        bus.publish(3u64).await;
+        bus.publish(3u64).await;
+        let start = Instant::now();
+        bus.drain(Duration::from_secs(60)).await;
+        assert!(start.elapsed() >= Duration::from_secs(6));
+        assert_eq!(pending(&bus), 0);
+        assert_eq!(done.load(Ordering::SeqCst), 2);
+
+        // 超时后不再等待
+        bus.publish(60u64).await;
+        let start = Instant::now();
+        bus.drain(Duration::from_secs(1)).await;
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert_eq!(pending(&bus), 1);
+    }
+}
@@ -0,0 +1,369 @@
+use crate::error::WebError;
+use actix_web::dev::Payload;
+use actix_web::http::StatusCode;
+use actix_web::http::header::{FORWARDED, HeaderName};
+use actix_web::{FromRequest, HttpMessage, HttpRequest};
+#[cfg(feature = "database")]
+use jieto_db::database::DataSourcePool;
+#[cfg(feature = "database")]
+use jieto_db::error::DbError;
+#[cfg(feature = "database")]
+use std::marker::PhantomData;
+use ipnet::IpNet;
+use std::future::{Ready, ready};
+use std::net::IpAddr;
+use std::sync::Arc;
+
+/// Resolves the id of the authenticated user from a request, see [`Application::identify`].
+///
+/// [`Application::identify`]: crate::Application::identify
+pub type IdentifyFn = dyn Fn(&HttpRequest) -> Option<String> + Send + Sync;
+
+#[derive(Clone)]
+pub(crate) struct UserResolver(pub(crate) Arc<IdentifyFn>);
+
+/// The authenticated user of the current request.
+///
+/// Taken from the request extensions when an authentication middleware inserted it, otherwise
+/// from the resolver registered with [`Application::identify`](crate::Application::identify).
+/// Extracting it from an anonymous request fails with 401.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CurrentUser(pub String);
+
+impl CurrentUser {
+    pub(crate) fn resolve(req: &HttpRequest) -> Option<CurrentUser> {
+        if let Some(user) = req.extensions().get::<CurrentUser>() {
+            return Some(user.clone());
+        }
+        let resolver = req.app_data::<UserResolver>()?;
+        let user = (resolver.0)(req).map(CurrentUser)?;
+        req.extensions_mut().insert(user.clone());
+        Some(user)
+    }
+}
+
+impl FromRequest for CurrentUser {
+    type Error = WebError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(CurrentUser::resolve(req).ok_or_else(|| {
+            WebError::Http(StatusCode::UNAUTHORIZED, String::from("unauthenticated"))
+        }))
+    }
+}
+
+/// The nonce of the `Content-Security-Policy` header sent with the current response.
+///
+/// Generated when the policy of the route in `[web.security]` contains `{nonce}`, extracting it
+/// on other routes fails with 500.
+///
+/// ```ignore
+/// #[get("/")]
+/// async fn index(nonce: CspNonce) -> HttpResponse {
+///     HttpResponse::Ok().body(format!("<script nonce=\"{}\">boot()</script>", nonce.0))
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CspNonce(pub String);
+
+impl FromRequest for CspNonce {
+    type Error = WebError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(req.extensions().get::<CspNonce>().cloned().ok_or_else(|| {
+            WebError::Http(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                String::from("no CSP nonce, add {nonce} to [web.security] content_security_policy"),
+            )
+        }))
+    }
+}
+
+/// The address of the client, resolved through the proxies of `[web.proxy] trusted`.
+///
+/// When the peer is a trusted proxy, the forwarded addresses of the first present of `Forwarded`,
+/// `X-Forwarded-For` and `X-Real-IP` are walked from the nearest hop and the first address that is
+/// not a trusted proxy is the client. Headers sent by other peers are ignored. Extracting it fails
+/// with 500 when the connection has no peer address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+impl FromRequest for ClientIp {
+    type Error = WebError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(client_ip(req).map(ClientIp).ok_or_else(|| {
+            WebError::Http(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                String::from("client address unavailable"),
+            )
+        }))
+    }
+}
+
+/// Networks of `[web.proxy] trusted`, registered as app data.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TrustedProxies(pub(crate) Arc<Vec<IpNet>>);
+
+impl TrustedProxies {
+    fn contains(&self, ip: &IpAddr) -> bool {
+        self.0.iter().any(|network| network.contains(ip))
+    }
+
+    fn resolve(&self, req: &HttpRequest, peer: IpAddr) -> IpAddr {
+        if !self.contains(&peer) {
+            return peer;
+        }
+        let headers = req.headers();
+        let hops: Vec<&str> = if headers.contains_key(FORWARDED) {
+            header_values(req, FORWARDED)
+                .flat_map(|element| element.split(';'))
+                .filter_map(|pair| pair.split_once('='))
+                .filter(|(name, _)| name.trim().eq_ignore_ascii_case("for"))
+                .map(|(_, value)| value.trim())
+                .collect()
+        } else if headers.contains_key(X_FORWARDED_FOR) {
+            header_values(req, X_FORWARDED_FOR).collect()
+        } else {
+            header_values(req, X_REAL_IP).take(1).collect()
+        };
+
+        let mut client = peer;
+        for hop in hops.iter().rev() {
+            // unknown 或混淆后的地址无法继续追溯，使用最近一跳
+            let Some(ip) = parse_hop(hop) else {
+                break;
+            };
+            client = ip;
+            if !self.contains(&ip) {
+                break;
+            }
+        }
+        client
+    }
+}
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+const X_REAL_IP: HeaderName = HeaderName::from_static("x-real-ip");
+
+/// Comma separated values of all lines of a header, in order.
+fn header_values(req: &HttpRequest, name: HeaderName) -> impl Iterator<Item = &str> {
+    req.headers()
+        .get_all(name)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+/// Parses `192.0.2.1`, `192.0.2.1:4711`, `2001:db8::1` and `"[2001:db8::1]:4711"`.
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    let hop = hop.trim_matches('"');
+    if let Ok(ip) = hop.parse() {
+        return Some(ip);
+    }
+    if let Some(rest) = hop.strip_prefix('[') {
+        return rest.split_once(']')?.0.parse().ok();
+    }
+    hop.rsplit_once(':')?.0.parse().ok()
+}
+
+/// Address of the client, see [`ClientIp`]; resolved once per request.
+pub(crate) fn client_ip(req: &HttpRequest) -> Option<IpAddr> {
+    if let Some(ClientIp(ip)) = req.extensions().get::<ClientIp>() {
+        return Some(*ip);
+    }
+    let peer = req.peer_addr()?.ip();
+    let ip = match req.app_data::<TrustedProxies>() {
+        Some(proxies) => proxies.resolve(req, peer),
+        None => peer,
+    };
+    req.extensions_mut().insert(ClientIp(ip));
+    Some(ip)
+}
+
+/// Names a data source for [`NamedDb`], usually declared with [`datasource!`](crate::datasource).
+#[cfg(feature = "database")]
+pub trait DataSourceName {
+    const NAME: &'static str;
+}
+
+/// Declares a data source name usable with [`NamedDb`].
+///
+/// ```ignore
+/// jieto_web::datasource!(Reporting = "reporting");
+///
+/// async fn report(db: NamedDb<Reporting, PgPool>) -> JietoResult<()> { ... }
+/// ```
+#[cfg(feature = "database")]
+#[macro_export]
+macro_rules! datasource {
+    ($(#[$meta:meta])* $vis:vis $name:ident = $value:literal) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy)]
+        $vis struct $name;
+
+        impl $crate::extract::DataSourceName for $name {
+            const NAME: &'static str = $value;
+        }
+    };
+}
+
+#[cfg(feature = "database")]
+fn db_manager(req: &HttpRequest) -> Option<&crate::DbManager> {
+    req.app_data::<actix_web::web::Data<crate::AppState>>()
+        .map(|state| state.db_manager.as_ref())
+}
+
+/// Pool of the default data source of type `P`, e.g. `Db<MySqlPool>`.
+#[cfg(feature = "database")]
+#[derive(Debug, Clone)]
+pub struct Db<P>(pub P);
+
+#[cfg(feature = "database")]
+impl<P> Db<P> {
+    pub fn into_inner(self) -> P {
+        self.0
+    }
+}
+
+#[cfg(feature = "database")]
+impl<P> std::ops::Deref for Db<P> {
+    type Target = P;
+
+    fn deref(&self) -> &P {
+        &self.0
+    }
+}
+
+#[cfg(feature = "database")]
+impl<P: DataSourcePool> FromRequest for Db<P> {
+    type Error = WebError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(
+            db_manager(req)
+                .ok_or_else(|| DbError::PoolNotConfigured(String::from("default")))
+                .and_then(P::from_default)
+                .map(Db)
+                .map_err(WebError::from),
+        )
+    }
+}
+
+/// Pool of the data source named by `N`, e.g. `NamedDb<Reporting, PgPool>`.
+#[cfg(feature = "database")]
+pub struct NamedDb<N, P> {
+    pool: P,
+    _name: PhantomData<N>,
+}
+
+#[cfg(feature = "database")]
+impl<N: DataSourceName, P> NamedDb<N, P> {
+    pub fn name(&self) -> &'static str {
+        N::NAME
+    }
+
+    pub fn into_inner(self) -> P {
+        self.pool
+    }
+}
+
+#[cfg(feature = "database")]
+impl<N, P> std::ops::Deref for NamedDb<N, P> {
+    type Target = P;
+
+    fn deref(&self) -> &P {
+        &self.pool
+    }
+}
+
+#[cfg(feature = "database")]
+impl<N: DataSourceName, P: DataSourcePool> FromRequest for NamedDb<N, P> {
+    type Error = WebError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(
+            db_manager(req)
+                .ok_or_else(|| DbError::PoolNotConfigured(N::NAME.to_string()))
+                .and_then(|manager| P::from_named(manager, N::NAME))
+                .map(|pool| NamedDb {
+                    pool,
+                    _name: PhantomData,
+                })
+                .map_err(WebError::from),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn proxies() -> TrustedProxies {
+        TrustedProxies(Arc::new(vec!["10.0.0.0/8".parse().unwrap()]))
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn untrusted_peers_cannot_spoof_the_client() {
+        let req = TestRequest::default()
+            .insert_header((X_FORWARDED_FOR, "1.2.3.4"))
+            .to_http_request();
+        assert_eq!(
+            proxies().resolve(&req, ip("203.0.113.9")),
+            ip("203.0.113.9")
+        );
+    }
+
+    #[test]
+    fn forwarded_for_stops_at_the_first_untrusted_hop() {
+        // 客户端伪造的 1.2.3.4 位于不可信地址之前，不会被采用
+        let req = TestRequest::default()
+            .insert_header((X_FORWARDED_FOR, "1.2.3.4, 203.0.113.7, 10.0.0.2"))
+            .to_http_request();
+        assert_eq!(proxies().resolve(&req, ip("10.0.0.1")), ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn forwarded_with_quoted_ipv6_and_ports() {
+        let req = TestRequest::default()
+            .insert_header((
+                FORWARDED,
+                r#"for="[2001:db8::1]:4711";proto=https, for=10.0.0.2:8080"#,
+            ))
+            .to_http_request();
+        assert_eq!(proxies().resolve(&req, ip("10.0.0.1")), ip("2001:db8::1"));
+    }
+
+    #[test]
+    fn unknown_hops_stop_at_the_nearest_address() {
+        let req = TestRequest::default()
+            .insert_header((FORWARDED, "for=unknown, for=10.0.0.2"))
+            .to_http_request();
+        assert_eq!(proxies().resolve(&req, ip("10.0.0.1")), ip("10.0.0.2"));
+    }
+
+    #[test]
+    fn hops() {
+        assert_eq!(parse_hop("192.0.2.1"), Some(ip("192.0.2.1")));
+        assert_eq!(parse_hop("192.0.2.1:4711"), Some(ip("192.0.2.1")));
+        assert_eq!(parse_hop("2001:db8::1"), Some(ip("2001:db8::1")));
+        assert_eq!(
+            parse_hop(r#""[2001:db8::1]:4711""#),
+            Some(ip("2001:db8::1"))
+        );
+        assert_eq!(parse_hop("[2001:db8::1]"), Some(ip("2001:db8::1")));
+        assert_eq!(parse_hop("unknown"), None);
+        assert_eq!(parse_hop("_hidden"), None);
+    }
+}
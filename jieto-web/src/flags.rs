@@ -0,0 +1,388 @@
+//! Feature flags.
+//!
+//! Flags are defined in `[flags.rules]` as `true`, `false` or a rollout percentage. With
+//! `[flags] hash` set, the fields of that redis hash override them and are re-read every
+//! `refresh_secs`, so a flag can be switched for all instances at once, e.g. with
+//! `HSET app:flags new_checkout 25`. The management endpoint `/flags` lists and sets them.
+//!
+//! ```ignore
+//! #[get("/checkout")]
+//! async fn checkout(state: web::Data<AppState>, ctx: FlagContext) -> JietoResult<Checkout> {
+//!     if state.flags().is_enabled("new_checkout", &ctx) {
+//!         ...
+//!     }
+//! }
+//! ```
+//!
+//! A percentage enables the flag for a stable share of users: the same user id always gets the
+//! same result for a flag, anonymous requests only see flags that are fully on.
+
+use crate::config;
+use crate::error::WebError;
+use crate::extract::CurrentUser;
+use crate::logging;
+use actix_web::dev::Payload;
+use actix_web::{FromRequest, HttpRequest};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future::{Ready, ready};
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+/// The state of a flag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rollout {
+    Off,
+    On,
+    /// Enabled for this share of users, between 1 and 99.
+    Percentage(u8),
+}
+
+impl Rollout {
+    /// `0` and `100` are normalized to [`Rollout::Off`] and [`Rollout::On`].
+    pub fn percentage(percentage: u8) -> Result<Self, String> {
+        match percentage {
+            0 => Ok(Rollout::Off),
+            100 => Ok(Rollout::On),
+            1..=99 => Ok(Rollout::Percentage(percentage)),
+            _ => Err(format!("percentage {} is larger than 100", percentage)),
+        }
+    }
+
+    fn applies(&self, flag: &str, ctx: &FlagContext) -> bool {
+        match self {
+            Rollout::Off => false,
+            Rollout::On => true,
+            Rollout::Percentage(percentage) => ctx
+                .user_id
+                .as_deref()
+                .is_some_and(|user| bucket(flag, user) < u64::from(*percentage)),
+        }
+    }
+}
+
+/// FNV-1a of `flag:user` mod 100, identical on every instance and release.
+fn bucket(flag: &str, user: &str) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in flag.bytes().chain([b':']).chain(user.bytes()) {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash % 100
+}
+
+/// Parses `true` / `on`, `false` / `off` and percentages like `25` or `25%`.
+impl FromStr for Rollout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        match s.to_ascii_lowercase().as_str() {
+            "true" | "on" => return Ok(Rollout::On),
+            "false" | "off" => return Ok(Rollout::Off),
+            _ => {}
+        }
+        s.trim_end_matches('%')
+            .parse::<u8>()
+            .map_err(|_| format!("invalid flag value '{}'", s))
+            .and_then(Rollout::percentage)
+    }
+}
+
+impl fmt::Display for Rollout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rollout::Off => write!(f, "false"),
+            Rollout::On => write!(f, "true"),
+            Rollout::Percentage(percentage) => write!(f, "{}%", percentage),
+        }
+    }
+}
+
+/// `true`, `false` or the percentage as a number.
+impl Serialize for Rollout {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Rollout::Off => serializer.serialize_bool(false),
+            Rollout::On => serializer.serialize_bool(true),
+            Rollout::Percentage(percentage) => serializer.serialize_u8(*percentage),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Rollout {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Value {
+            Bool(bool),
+            Number(u64),
+            Text(String),
+        }
+
+        match Value::deserialize(deserializer)? {
+            Value::Bool(true) => Ok(Rollout::On),
+            Value::Bool(false) => Ok(Rollout::Off),
+            Value::Number(n) => u8::try_from(n)
+                .map_err(|_| format!("percentage {} is larger than 100", n))
+                .and_then(Rollout::percentage)
+                .map_err(serde::de::Error::custom),
+            Value::Text(text) => text.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+/// Who a flag is evaluated for.
+///
+/// Extracted from a request it carries the [`CurrentUser`], if any.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FlagContext {
+    pub user_id: Option<String>,
+}
+
+impl FlagContext {
+    pub fn anonymous() -> Self {
+        Self::default()
+    }
+
+    pub fn user(user_id: impl Into<String>) -> Self {
+        Self {
+            user_id: Some(user_id.into()),
+        }
+    }
+}
+
+impl FromRequest for FlagContext {
+    type Error = WebError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Ok(Self {
+            user_id: CurrentUser::resolve(req).map(|user| user.0),
+        }))
+    }
+}
+
+/// A flag as listed by the management endpoint.
+#[derive(Debug, Clone, Serialize)]
+pub struct FlagView {
+    pub name: String,
+    pub value: Rollout,
+    /// `config` or `override`.
+    pub source: &'static str,
+}
+
+#[cfg(feature = "redis")]
+struct RedisSource {
+    pool: deadpool_redis::Pool,
+    hash: String,
+    refresh_secs: u64,
+}
+
+#[derive(Default)]
+struct Inner {
+    rules: BTreeMap<String, Rollout>,
+    overrides: RwLock<BTreeMap<String, Rollout>>,
+    #[cfg(feature = "redis")]
+    redis: Option<RedisSource>,
+}
+
+/// The flags of `[flags]`, available as `AppState::flags()`.
+#[derive(Clone, Default)]
+pub struct FeatureFlags {
+    inner: Arc<Inner>,
+}
+
+impl FeatureFlags {
+    pub(crate) fn from_config(
+        config: &config::Flags,
+        #[cfg(feature = "redis")] db_manager: &crate::DbManager,
+    ) -> anyhow::Result<Self> {
+        #[cfg(feature = "redis")]
+        let redis = match &config.hash {
+            Some(hash) => Some(RedisSource {
+                pool: match &config.redis {
+                    Some(name) => db_manager.with_redis(name)?,
+                    None => db_manager.with_redis_default()?,
+                },
+                hash: hash.clone(),
+                refresh_secs: config.refresh_secs,
+            }),
+            None => None,
+        };
+        Ok(Self {
+            inner: Arc::new(Inner {
+                rules: config.rules.clone(),
+                overrides: RwLock::default(),
+                #[cfg(feature = "redis")]
+                redis,
+            }),
+        })
+    }
+
+    /// Whether `name` is enabled for `ctx`, unknown flags are disabled.
+    pub fn is_enabled(&self, name: &str, ctx: &FlagContext) -> bool {
+        self.rollout(name)
+            .is_some_and(|rollout| rollout.applies(name, ctx))
+    }
+
+    /// The effective state of `name`, overrides before `[flags.rules]`.
+    pub fn rollout(&self, name: &str) -> Option<Rollout> {
+        // unwrap: the lock is never held across a panic
+        let overrides = self.inner.overrides.read().unwrap();
+        overrides
+            .get(name)
+            .or_else(|| self.inner.rules.get(name))
+            .copied()
+    }
+
+    pub fn list(&self) -> Vec<FlagView> {
+        let mut flags: BTreeMap<&str, FlagView> = self
+            .inner
+            .rules
+            .iter()
+            .map(|(name, value)| {
+                let view = FlagView {
+                    name: name.clone(),
+                    value: *value,
+                    source: "config",
+                };
+                (name.as_str(), view)
+            })
+            .collect();
+        // unwrap: the lock is never held across a panic
+        let overrides = self.inner.overrides.read().unwrap();
+        for (name, value) in overrides.iter() {
+            let view = FlagView {
+                name: name.clone(),
+                value: *value,
+                source: "override",
+            };
+            flags.insert(name, view);
+        }
+        flags.into_values().collect()
+    }
+
+    /// Overrides `name`. With a redis hash configured the value is written there, reaching the
+    /// other instances on their next refresh, otherwise it only lasts until this process exits.
+    pub async fn set(&self, name: &str, value: Rollout) -> anyhow::Result<()> {
+        #[cfg(feature = "redis")]
+        if let Some(redis) = &self.inner.redis {
+            use deadpool_redis::redis::AsyncCommands;
+            let mut conn = redis.pool.get().await?;
+            let _: () = conn.hset(&redis.hash, name, value.to_string()).await?;
+        }
+        logging::info!("[flags] {} set to {}", name, value);
+        // unwrap: the lock is never held across a panic
+        let mut overrides = self.inner.overrides.write().unwrap();
+        overrides.insert(name.to_string(), value);
+        Ok(())
+    }
+
+    /// Replaces the overrides with the fields of the redis hash, invalid fields are skipped.
+    #[cfg(feature = "redis")]
+    pub async fn refresh(&self) -> anyhow::Result<()> {
+        use deadpool_redis::redis::AsyncCommands;
+        let Some(redis) = &self.inner.redis else {
+            return Ok(());
+        };
+        let mut conn = redis.pool.get().await?;
+        let fields: BTreeMap<String, String> = conn.hgetall(&redis.hash).await?;
+        let overrides = fields
+            .into_iter()
+            .filter_map(|(name, value)| match value.parse::<Rollout>() {
+                Ok(rollout) => Some((name, rollout)),
+                Err(e) => {
+                    logging::warn!("[flags] ignoring {} in '{}': {}", name, redis.hash, e);
+                    None
+                }
+            })
+            .collect();
+        // unwrap: the lock is never held across a panic
+        *self.inner.overrides.write().unwrap() = overrides;
+        Ok(())
+    }
+
+    /// Refreshes the overrides every `refresh_secs` in the background.
+    #[cfg(feature = "redis")]
+    pub(crate) fn watch(&self) {
+        let Some(refresh_secs) = self.inner.redis.as_ref().map(|r| r.refresh_secs) else {
+            return;
+        };
+        if refresh_secs == 0 {
+            return;
+        }
+        let flags = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(refresh_secs));
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                if let Err(e) = flags.refresh().await {
+                    logging::warn!(
+                        "[flags] refresh failed, keeping the current overrides: {}",
+                        e
+                    );
+                }
+            }
+        });
+    }
+}
+
+impl fmt::Debug for FeatureFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FeatureFlags")
+            .field("rules", &self.inner.rules)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rollout_boundaries() {
+        assert_eq!("0".parse(), Ok(Rollout::Off));
+        assert_eq!("0%".parse(), Ok(Rollout::Off));
+        assert_eq!("1".parse(), Ok(Rollout::Percentage(1)));
+        assert_eq!("99%".parse(), Ok(Rollout::Percentage(99)));
+        assert_eq!("100".parse(), Ok(Rollout::On));
+        assert!("101".parse::<Rollout>().is_err());
+        assert!("-1".parse::<Rollout>().is_err());
+        assert_eq!(" On ".parse(), Ok(Rollout::On));
+        assert_eq!("false".parse(), Ok(Rollout::Off));
+    }
+
+    #[test]
+    fn buckets_are_stable_and_bounded() {
+        assert_eq!(bucket("beta", "42"), bucket("beta", "42"));
+        let users: Vec<String> = (0..1000).map(|id| id.to_string()).collect();
+        assert!(users.iter().all(|user| bucket("beta", user) < 100));
+
+        let user = |id: &str| FlagContext {
+            user_id: Some(id.to_string()),
+        };
+        assert!(
+            users
+                .iter()
+                .all(|id| !Rollout::Off.applies("beta", &user(id)))
+        );
+        assert!(
+            users
+                .iter()
+                .all(|id| Rollout::On.applies("beta", &user(id)))
+        );
+        assert!(!Rollout::Percentage(99).applies("beta", &FlagContext::anonymous()));
+        // 比例升高时已开启的用户保持开启
+        let enabled = |percentage| {
+            users
+                .iter()
+                .filter(|id| Rollout::Percentage(percentage).applies("beta", &user(id)))
+                .count()
+        };
+        assert!(enabled(1) <= enabled(50) && enabled(50) <= enabled(99));
+        assert!((400..600).contains(&enabled(50)), "{}", enabled(50));
+    }
+}
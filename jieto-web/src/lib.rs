@@ -1,24 +1,77 @@
 use crate::config::ApplicationConfig;
 use crate::error::WebError;
+use crate::extract::{TrustedProxies, UserResolver};
 use crate::log4r::init_logger;
+use crate::management::{ManagementState, configure_management};
+use crate::middleware::access_log::AccessLogger;
+use crate::middleware::body_limit::BodyLimit;
+use crate::middleware::catch_panic::CatchPanic;
+use crate::middleware::compression::{AcceptEncodingFilter, CompressionSkip};
+#[cfg(feature = "idempotency")]
+use crate::middleware::idempotency::IdempotencyGuard;
+use crate::middleware::ip_filter::IpAccessControl;
+use crate::middleware::rate_limit::RateLimiter;
+use crate::middleware::security_headers::SecurityHeaders;
+use crate::middleware::timeout::RequestTimeout;
+#[cfg(feature = "tracing")]
+use crate::middleware::trace::RequestTracing;
+#[cfg(any(feature = "mysql", feature = "postgres", feature = "sqlite"))]
+use crate::middleware::transaction::TransactionFinalizer;
 use actix_cors::Cors;
+use actix_web::middleware::{Compress, Condition};
 use actix_web::web::ServiceConfig;
-use actix_web::{App, HttpResponse, HttpServer, Responder, web};
+use actix_web::{App, HttpRequest, HttpResponse, HttpServer, Responder, web};
 use serde::Serialize;
-use std::env;
 use std::sync::Arc;
+#[cfg(feature = "events")]
+use std::time::Duration;
 
 pub mod config;
 pub mod error;
+#[cfg(feature = "events")]
+pub mod events;
+pub mod extract;
+#[cfg(feature = "flags")]
+pub mod flags;
 mod log4r;
+mod management;
+mod metrics;
+mod middleware;
 pub mod resp;
+pub mod routes;
+mod static_files;
+#[cfg(feature = "cache")]
+pub mod cache;
+mod cli;
+#[cfg(feature = "sse")]
+pub mod sse;
+#[cfg(feature = "upload")]
+pub mod upload;
+#[cfg(feature = "tracing")]
+mod telemetry;
+#[cfg(any(feature = "mysql", feature = "postgres", feature = "sqlite"))]
+pub mod tx;
 
 #[cfg(feature = "job")]
 pub mod job;
 #[cfg(feature = "ws")]
 mod ws;
 
+/// Logging macros of the framework: `tracing` with the `tracing` feature, `log` otherwise.
+mod logging {
+    #[cfg(not(feature = "tracing"))]
+    pub(crate) use log::{debug, error, info, warn};
+    #[cfg(feature = "tracing")]
+    pub(crate) use tracing::{debug, error, info, warn};
+}
+
+pub use extract::{ClientIp, CspNonce, CurrentUser};
+#[cfg(feature = "database")]
+pub use extract::{Db, NamedDb};
+#[cfg(any(feature = "mysql", feature = "postgres", feature = "sqlite"))]
+pub use tx::{NamedTx, Tx, transactional};
 pub use resp::ApiResult;
+pub use routes::Routes;
 
 #[cfg(feature = "job")]
 pub type TaskScheduler = jieto_job::TaskScheduler;
@@ -35,6 +88,18 @@ pub struct TaskScheduler {
 #[cfg(feature = "database")]
 pub static GLOBAL_DBMANAGER: std::sync::OnceLock<Arc<DbManager>> = std::sync::OnceLock::new();
 
+/// The broadcaster of `AppState::sse`, for publishing outside handlers such as scheduled tasks.
+#[cfg(feature = "sse")]
+pub static GLOBAL_SSE: std::sync::OnceLock<sse::SseBroadcaster> = std::sync::OnceLock::new();
+
+/// The cache of `AppState::cache`, also used by `#[cacheable]` functions.
+#[cfg(feature = "cache")]
+pub static GLOBAL_CACHE: std::sync::OnceLock<cache::SharedCache> = std::sync::OnceLock::new();
+
+/// The event bus of `AppState::events`, for publishing outside handlers such as scheduled tasks.
+#[cfg(feature = "events")]
+pub static GLOBAL_EVENTS: std::sync::OnceLock<events::EventBus> = std::sync::OnceLock::new();
+
 #[derive(Debug, Clone)]
 pub struct BusinessError {
     pub code: u16,
@@ -69,6 +134,14 @@ pub struct AppState {
     pub scheduler: Arc<jieto_job::TaskScheduler>,
     #[cfg(feature = "ws")]
     pub ws_server: Option<jieto_ws::WsServerHandle>,
+    #[cfg(feature = "sse")]
+    pub sse: sse::SseBroadcaster,
+    #[cfg(feature = "cache")]
+    pub cache: cache::SharedCache,
+    #[cfg(feature = "flags")]
+    flags: flags::FeatureFlags,
+    #[cfg(feature = "events")]
+    pub events: events::EventBus,
 }
 
 #[cfg(feature = "database")]
@@ -92,6 +165,97 @@ impl AppState {
     }
 }
 
+#[cfg(feature = "cache")]
+impl AppState {
+    fn with_cache(&mut self, cache: cache::SharedCache) {
+        self.cache = cache;
+    }
+}
+
+#[cfg(feature = "flags")]
+impl AppState {
+    fn with_flags(&mut self, flags: flags::FeatureFlags) {
+        self.flags = flags;
+    }
+
+    /// The feature flags of `[flags]`.
+    pub fn flags(&self) -> &flags::FeatureFlags {
+        &self.flags
+    }
+}
+
+#[cfg(feature = "events")]
+impl AppState {
+    fn with_events(&mut self, events: events::EventBus) {
+        self.events = events;
+    }
+}
+
+#[cfg(feature = "sse")]
+impl AppState {
+    fn with_sse(&mut self, broadcaster: sse::SseBroadcaster) {
+        self.sse = broadcaster;
+    }
+}
+
+/// Sets up the data sources and shared services of `state` and their globals.
+#[allow(unused_variables)]
+async fn init_services(
+    config: &ApplicationConfig,
+    config_path: &str,
+    state: &mut AppState,
+    #[cfg(feature = "events")] subscriptions: Vec<events::Registration>,
+) -> anyhow::Result<()> {
+    #[cfg(feature = "sse")]
+    {
+        let broadcaster = GLOBAL_SSE.get_or_init(|| sse::SseBroadcaster::new(config.sse.clone()));
+        state.with_sse(broadcaster.clone());
+    }
+
+    #[cfg(feature = "database")]
+    {
+        let db_manager = jieto_db::jieto_db_init(config_path).await?;
+        let db_manager = Arc::new(db_manager);
+        let db_manager = GLOBAL_DBMANAGER.get_or_init(|| db_manager);
+        state.with_db(db_manager.clone());
+    }
+
+    #[cfg(feature = "cache")]
+    {
+        let cache = cache::SharedCache::from_config(
+            &config.cache,
+            #[cfg(feature = "redis")]
+            &state.db_manager,
+        )?;
+        let cache = GLOBAL_CACHE.get_or_init(|| cache);
+        state.with_cache(cache.clone());
+    }
+
+    #[cfg(feature = "flags")]
+    {
+        let flags = flags::FeatureFlags::from_config(
+            &config.flags,
+            #[cfg(feature = "redis")]
+            &state.db_manager,
+        )?;
+        #[cfg(feature = "redis")]
+        if let Err(e) = flags.refresh().await {
+            logging::warn!("[flags] failed to read the overrides: {}", e);
+        }
+        state.with_flags(flags);
+    }
+
+    #[cfg(feature = "events")]
+    {
+        let events = events::EventBus::new(&config.events, subscriptions);
+        let events = GLOBAL_EVENTS.get_or_init(|| events);
+        state.with_events(events.clone());
+    }
+    Ok(())
+}
+
+type RoutesFn = Arc<dyn Fn(&mut Routes<'_>) + Send + Sync>;
+
 pub trait AppInitializing {
     fn initializing(&self);
 }
@@ -103,8 +267,16 @@ where
 {
     cfg: F,
     init: Vec<I>,
+    identify: Option<UserResolver>,
+    version: &'static str,
+    routes: Option<RoutesFn>,
+    cli: bool,
     #[cfg(feature = "job")]
     tasks: Vec<Box<dyn jieto_job::ScheduledTask>>,
+    #[cfg(feature = "upload")]
+    storage: Option<Arc<dyn upload::FileStorage>>,
+    #[cfg(feature = "events")]
+    subscriptions: Vec<events::Registration>,
 }
 
 impl<I, F> Application<I, F>
@@ -116,29 +288,215 @@ where
         Self {
             cfg,
             init: vec![],
+            identify: None,
+            version: "unknown",
+            routes: None,
+            cli: false,
             #[cfg(feature = "job")]
             tasks: vec![],
+            #[cfg(feature = "upload")]
+            storage: None,
+            #[cfg(feature = "events")]
+            subscriptions: vec![],
         }
     }
 
+    /// Hooks run after the services are initialized, the last bound first.
     pub fn bind_init(mut self, init: I) -> Self {
         self.init.push(init);
         self
     }
 
+    /// Application version reported by the management `/info` endpoint, usually
+    /// `env!("CARGO_PKG_VERSION")`.
+    pub fn version(mut self, version: &'static str) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Registers how the authenticated user is resolved from a request, used by
+    /// [`CurrentUser`] and user keyed rate limits.
+    pub fn identify<U>(mut self, resolver: U) -> Self
+    where
+        U: Fn(&HttpRequest) -> Option<String> + Send + Sync + 'static,
+    {
+        self.identify = Some(UserResolver(Arc::new(resolver)));
+        self
+    }
+
+    /// Registers routes through [`Routes`], which records them for the `routes` command.
+    /// They are mounted after those of the `ServiceConfig` closure.
+    pub fn routes<R>(mut self, routes: R) -> Self
+    where
+        R: Fn(&mut Routes<'_>) + Send + Sync + 'static,
+    {
+        self.routes = Some(Arc::new(routes));
+        self
+    }
+
     #[cfg(feature = "job")]
     pub fn register_task(mut self, task: Box<dyn jieto_job::ScheduledTask>) -> Self {
         self.tasks.push(task);
         self
     }
 
-    pub async fn run(mut self) -> anyhow::Result<()> {
-        let config_path = env::var("APP_CONFIG")
-            .or_else(|_| env::var("CONFIG_PATH"))
-            .unwrap_or_else(|_| "application.toml".to_string()); // 默认路径
+    /// Handles events of type `E` published through `AppState::events`, see [`events`].
+    #[cfg(feature = "events")]
+    pub fn subscribe<E: Send + Sync + 'static>(
+        mut self,
+        subscription: events::Subscription<E>,
+    ) -> Self {
+        self.subscriptions.push(subscription.into_registration());
+        self
+    }
+
+    /// Stores uploaded files in `storage` instead of the local `[web.upload] directory`.
+    #[cfg(feature = "upload")]
+    pub fn storage<S: upload::FileStorage>(mut self, storage: S) -> Self {
+        self.storage = Some(Arc::new(storage));
+        self
+    }
+
+    /// Parses the command line in [`run`](Self::run) instead of always starting the server:
+    ///
+    /// ```text
+    /// app [--config <path>] [--profile <name>] [command]
+    ///
+    /// serve               start the server (default)
+    /// check-config        validate the configuration and exit
+    /// print-config        print the effective configuration with credentials masked
+    /// routes              list the routes registered through `Application::routes`
+    /// jobs list           list the registered scheduled tasks
+    /// jobs run <name>     run a scheduled task once and exit
+    /// migrate             apply the migrations of the SQL datasources
+    /// ```
+    pub fn cli(mut self) -> Self {
+        self.cli = true;
+        self
+    }
+
+    pub async fn run(self) -> anyhow::Result<()> {
+        if !self.cli {
+            return self.serve().await;
+        }
+
+        let args = cli::Cli::parse(std::env::args().skip(1))?;
+        if let Some(path) = &args.config {
+            config::set_config_path(path);
+        }
+        if let Some(profile) = &args.profile {
+            jieto_config::set_profile(profile);
+        }
+        let config_path = config::config_path();
+
+        match args.command {
+            cli::Command::Serve => self.serve().await,
+            cli::Command::Help => {
+                println!("{}", cli::USAGE);
+                Ok(())
+            }
+            cli::Command::CheckConfig => {
+                ApplicationConfig::from_toml(&config_path).await?;
+                #[cfg(feature = "database")]
+                jieto_db::jieto_db_check(&config_path).await?;
+                match jieto_config::profile() {
+                    Some(profile) => {
+                        println!("configuration '{}' ({}) is valid", config_path, profile)
+                    }
+                    None => println!("configuration '{}' is valid", config_path),
+                }
+                Ok(())
+            }
+            cli::Command::PrintConfig => {
+                let config = ApplicationConfig::from_toml(&config_path).await?;
+                print!("{}", toml::to_string(&config.redacted())?);
+                Ok(())
+            }
+            cli::Command::Routes => {
+                let config = ApplicationConfig::from_toml(&config_path).await?;
+                let mut table = vec![];
+                if let Some(routes) = &self.routes {
+                    App::new().configure(|cfg| routes(&mut Routes::new(cfg, &mut table)));
+                }
+                table.extend(cli::framework_routes(&config));
+                for route in table {
+                    println!("{}", route);
+                }
+                Ok(())
+            }
+            #[cfg(feature = "job")]
+            cli::Command::JobsList => {
+                for task in &self.tasks {
+                    println!("{:<32} {}", task.task_name(), task.cron_expression());
+                }
+                Ok(())
+            }
+            #[cfg(feature = "job")]
+            cli::Command::JobsRun(name) => {
+                let Some(task) = self.tasks.iter().find(|task| task.task_name() == name) else {
+                    anyhow::bail!("no task named '{}', see `jobs list`", name);
+                };
+                let config = ApplicationConfig::from_toml(&config_path).await?;
+                let app_name = config.name.clone().unwrap_or(String::from("app"));
+                let logger = init_logger(&config.log, &config.web.access_log, &app_name)?;
+                let mut state = AppState::default();
+                init_services(
+                    &config,
+                    &config_path,
+                    &mut state,
+                    #[cfg(feature = "events")]
+                    self.subscriptions,
+                )
+                .await?;
+                #[cfg(feature = "events")]
+                let state = web::Data::new(state);
+                #[cfg(feature = "events")]
+                state.events.start(state.clone());
+                for init in self.init.iter().rev() {
+                    init.initializing();
+                }
+                logging::info!("[job] running task {} once", name);
+                task.execute().await;
+                #[cfg(feature = "events")]
+                state
+                    .events
+                    .drain(Duration::from_secs(config.events.drain_secs))
+                    .await;
+                logger.flush();
+                Ok(())
+            }
+            #[cfg(any(feature = "mysql", feature = "postgres", feature = "sqlite"))]
+            cli::Command::Migrate => {
+                let config = ApplicationConfig::from_toml(&config_path).await?;
+                let app_name = config.name.clone().unwrap_or(String::from("app"));
+                let logger = init_logger(&config.log, &config.web.access_log, &app_name)?;
+                let migrated = jieto_db::jieto_db_migrate(&config_path).await?;
+                if migrated.is_empty() {
+                    println!("no datasource has migrations configured");
+                }
+                for name in migrated {
+                    println!("migrated {}", name);
+                }
+                logger.flush();
+                Ok(())
+            }
+        }
+    }
+
+    async fn serve(mut self) -> anyhow::Result<()> {
+        let config_path = config::config_path();
         let config = ApplicationConfig::from_toml(&config_path).await?;
         let mut state = AppState::default();
-        init_logger(&config.log, &config.name.unwrap_or(String::from("app")))?;
+        let app_name = config.name.clone().unwrap_or(String::from("app"));
+        let logger = init_logger(&config.log, &config.web.access_log, &app_name)?;
+        #[cfg(feature = "tracing")]
+        let tracing = telemetry::init(&config.tracing, &app_name)?;
+        let env = config.redacted();
+        logging::debug!(
+            "[config] loaded '{}':\n{}",
+            config_path,
+            toml::to_string(&env).unwrap_or_default()
+        );
 
         #[cfg(feature = "ws")]
         let ws_handle = {
@@ -148,13 +506,16 @@ where
             ws_server_handle
         };
 
-        #[cfg(feature = "database")]
-        {
-            let db_manager = jieto_db::jieto_db_init(&config_path).await?;
-            let db_manager = Arc::new(db_manager);
-            let db_manager = GLOBAL_DBMANAGER.get_or_init(|| db_manager);
-            state.with_db(db_manager.clone());
-        }
+        init_services(
+            &config,
+            &config_path,
+            &mut state,
+            #[cfg(feature = "events")]
+            self.subscriptions,
+        )
+        .await?;
+        #[cfg(all(feature = "flags", feature = "redis"))]
+        state.flags().watch();
 
         while let Some(init) = self.init.pop() {
             init.initializing();
@@ -171,8 +532,91 @@ where
             state.with_job(scheduler);
         }
 
+        let rate_limiter = if config.web.rate_limit.enabled {
+            RateLimiter::from_config(
+                &config.web.rate_limit,
+                #[cfg(feature = "redis")]
+                &state.db_manager,
+            )?
+        } else {
+            RateLimiter::disabled()
+        };
+        #[cfg(feature = "idempotency")]
+        let idempotency = if config.web.idempotency.enabled {
+            IdempotencyGuard::from_config(
+                &config.web.idempotency,
+                #[cfg(feature = "redis")]
+                &state.db_manager,
+            )?
+        } else {
+            IdempotencyGuard::disabled()
+        };
+
+        let compression_enabled = config.web.compression.enabled;
+        let compression = Arc::new(config.web.compression);
+        let body_limit = BodyLimit::new(Arc::new(config.web.limits));
+        let request_timeout = RequestTimeout::from_config(config.web.timeout)?;
+        let security_headers = SecurityHeaders::from_config(&config.web.security)?;
+        let trusted_proxies = TrustedProxies(Arc::new(config.web.proxy.trusted));
+        let ip_filter = IpAccessControl::new(config.web.ip_filter);
+        ip_filter.watch(config_path.clone());
+        let access_log_enabled = config.web.access_log.enabled;
+        let access_logger = AccessLogger::new(config.web.access_log);
+        #[cfg(feature = "tracing")]
+        let tracing_enabled = config.tracing.enabled;
+        #[cfg(feature = "upload")]
+        let uploader = upload::Uploader::new(config.web.upload.clone(), self.storage.take());
+
         let app_state = web::Data::new(state);
+        #[cfg(feature = "events")]
+        app_state.events.start(app_state.clone());
+        #[cfg(feature = "events")]
+        let (events, drain_timeout) = (
+            app_state.events.clone(),
+            Duration::from_secs(config.events.drain_secs),
+        );
         let cfg_fn = self.cfg.clone();
+        let routes_fn = self.routes.clone();
+        let identify = self.identify.clone();
+
+        let management = config.management.enabled.then(|| {
+            web::Data::new(ManagementState::new(
+                &config.management,
+                app_name.clone(),
+                self.version,
+                env,
+                logger.clone(),
+            ))
+        });
+        let management_port = config.management.port;
+        let management_prefix = config.management.prefix.clone();
+
+        // 独立端口时管理端点不挂载到业务端口
+        let management_server = match (&management, management_port) {
+            (Some(management), Some(port)) => {
+                let management = management.clone();
+                let app_state = app_state.clone();
+                let prefix = management_prefix.clone();
+                let access_logger = access_logger.clone();
+                let trusted_proxies = trusted_proxies.clone();
+                let ip_filter = ip_filter.clone();
+                logging::info!("[management] listening on port {}", port);
+                let server = HttpServer::new(move || {
+                    App::new()
+                        .app_data(app_state.clone())
+                        .app_data(trusted_proxies.clone())
+                        .wrap(ip_filter.clone())
+                        .wrap(Condition::new(access_log_enabled, access_logger.clone()))
+                        .configure(|cfg| configure_management(cfg, &prefix, management.clone()))
+                })
+                .workers(1)
+                .bind(("0.0.0.0", port))?
+                .run();
+                Some(server)
+            }
+            _ => None,
+        };
+        let management = management.filter(|_| management_port.is_none());
 
         let server = HttpServer::new(move || {
             let cors = Cors::default()
@@ -182,33 +626,96 @@ where
                 .supports_credentials() // 如果需要携带 cookie
                 .max_age(3600);
 
-            App::new()
+            let app = App::new()
                 .app_data(app_state.clone())
+                .app_data(trusted_proxies.clone());
+            let app = match &identify {
+                Some(resolver) => app.app_data(resolver.clone()),
+                None => app,
+            };
+            #[cfg(feature = "upload")]
+            let app = app.app_data(uploader.clone());
+            // 最内层，处理函数 panic 时外层中间件照常处理 500 错误
+            let app = app.wrap(CatchPanic);
+            #[cfg(any(feature = "mysql", feature = "postgres", feature = "sqlite"))]
+            let app = app.wrap(TransactionFinalizer);
+            let app = app.wrap(request_timeout.clone());
+            #[cfg(feature = "idempotency")]
+            let app = app.wrap(idempotency.clone());
+
+            let app = app
+                .wrap(body_limit.clone())
+                .wrap(rate_limiter.clone())
+                .wrap(ip_filter.clone())
                 .wrap(cors)
-                .wrap(actix_web::middleware::Logger::default())
-                .configure(|cfg| {
-                    #[cfg(feature = "ws")]
-                    {
-                        use crate::ws::configure_ws;
-                        configure_ws(cfg, config.ws.path.as_deref());
-                    }
-
-                    cfg_fn(cfg)
-                })
+                .wrap(Condition::new(
+                    compression_enabled,
+                    CompressionSkip::new(compression.clone()),
+                ))
+                .wrap(Condition::new(compression_enabled, Compress::default()))
+                .wrap(Condition::new(
+                    compression_enabled,
+                    AcceptEncodingFilter::new(compression.clone()),
+                ))
+                .wrap(security_headers.clone())
+                .wrap(Condition::new(access_log_enabled, access_logger.clone()));
+            // 最外层，访问日志等记录在请求 span 内
+            #[cfg(feature = "tracing")]
+            let app = app.wrap(Condition::new(tracing_enabled, RequestTracing));
+
+            app.configure(|cfg| {
+                body_limit.configure(cfg);
+                #[cfg(feature = "upload")]
+                uploader.configure(cfg);
+
+                if let Some(management) = &management {
+                    configure_management(cfg, &management_prefix, management.clone());
+                }
+
+                #[cfg(feature = "ws")]
+                {
+                    use crate::ws::configure_ws;
+                    configure_ws(cfg, config.ws.path.as_deref());
+                }
+
+                cfg_fn(cfg);
+                if let Some(routes) = &routes_fn {
+                    routes(&mut Routes::new(cfg, &mut vec![]));
+                }
+                static_files::configure_static(cfg, &config.web.statics);
+            })
         })
         .bind(("0.0.0.0", config.web.port))?
         .run();
 
+        let management_server = async move {
+            match management_server {
+                Some(server) => server.await,
+                None => Ok(()),
+            }
+        };
+
         #[cfg(feature = "ws")]
         {
-            tokio::try_join!(server, async move { ws_handle.await.unwrap() })?;
+            tokio::try_join!(server, management_server, async move {
+                ws_handle.await.unwrap()
+            })?;
         }
 
         #[cfg(not(feature = "ws"))]
         {
-            server.await?;
+            tokio::try_join!(server, management_server)?;
         }
 
+        // 服务停止后等待已发布的事件处理完
+        #[cfg(feature = "events")]
+        events.drain(drain_timeout).await;
+
+        #[cfg(feature = "tracing")]
+        if let Some(tracing) = tracing {
+            tracing.shutdown();
+        }
+        logger.flush();
         Ok(())
     }
 }
@@ -1,11 +1,34 @@
-use crate::config::Log;
-use flexi_logger::{Age, Cleanup, Criterion, Duplicate, FileSpec, Logger, Naming, WriteMode};
+use crate::config::{AccessLog, Log, LogFormat, LogNaming, LogSink, SinkKind};
+use flexi_logger::writers::{FileLogWriter, LogWriter};
+use flexi_logger::{
+    Age, Cleanup, Criterion, DeferredNow, FileSpec, FormatFunction, Logger, LoggerHandle, Naming,
+    WriteMode,
+};
+use log::{LevelFilter, Record};
+use std::io::Write;
+
+/// Target of access log records written to the application log.
+pub(crate) const ACCESS_TARGET: &str = "jieto::access";
+/// Target routing access log records to the separate `access` writer.
+pub(crate) const ACCESS_WRITER_TARGET: &str = "{access}";
 
 fn jieto_detailed_format(
-    w: &mut dyn std::io::Write,
-    now: &mut flexi_logger::DeferredNow,
-    record: &log::Record,
+    w: &mut dyn Write,
+    now: &mut DeferredNow,
+    record: &Record,
 ) -> Result<(), std::io::Error> {
+    #[cfg(feature = "tracing")]
+    if let Some(trace_id) = crate::telemetry::current_trace_id() {
+        return write!(
+            w,
+            "[{}] {} [{}] [{}] {}",
+            now.format("%Y-%m-%d %H:%M:%S"),
+            record.level(),
+            record.target(),
+            trace_id,
+            &record.args()
+        );
+    }
     write!(
         w,
         "[{}] {} [{}] {}",
@@ -16,6 +39,20 @@ fn jieto_detailed_format(
     )
 }
 
+fn access_format(
+    w: &mut dyn Write,
+    now: &mut DeferredNow,
+    record: &Record,
+) -> Result<(), std::io::Error> {
+    write!(
+        w,
+        "[{}] {} {}",
+        now.format("%Y-%m-%d %H:%M:%S"),
+        record.level(),
+        &record.args()
+    )
+}
+
 fn parse_age(age_str: &str) -> Option<Age> {
     match age_str.to_lowercase().as_str() {
         "second" => Some(Age::Second),
@@ -26,22 +63,18 @@ fn parse_age(age_str: &str) -> Option<Age> {
     }
 }
 
-pub fn init_logger(config: &Log, app_name: &str) -> anyhow::Result<()> {
-    let mut filespec = FileSpec::default().suffix("log");
-
+fn file_spec(config: &Log, basename: &str) -> FileSpec {
     // 设置目录
-    if let Some(dir) = &config.directory {
-        filespec = filespec.directory(dir);
-    } else {
-        filespec = filespec.directory("logs");
-    }
-
-    // 设置 basename（即文件名前缀）
-    let basename = config.filename_prefix.as_deref().unwrap_or(app_name);
-    filespec = filespec.basename(basename);
+    let directory = config.directory.as_deref().unwrap_or("logs");
+    FileSpec::default()
+        .suffix("log")
+        .directory(directory)
+        .basename(basename)
+}
 
+fn criterion(config: &Log) -> Criterion {
     // 构建滚动策略
-    let criterion = match (&config.age, config.max_size_mb) {
+    match (&config.age, config.max_size_mb) {
         (Some(age_str), Some(size_mb)) => {
             if let Some(age) = parse_age(age_str) {
                 Criterion::AgeOrSize(age, size_mb * 1024 * 1024)
@@ -63,21 +96,518 @@ pub fn init_logger(config: &Log, app_name: &str) -> anyhow::Result<()> {
         (None, None) => {
             Criterion::Size(10 * 1024 * 1024 * 1024) // 10 GB
         }
+    }
+}
+
+fn naming(config: &Log) -> Naming {
+    match config.naming {
+        LogNaming::Timestamps => Naming::TimestampsDirect,
+        LogNaming::Numbers => Naming::Numbers,
+        LogNaming::Date => Naming::TimestampsCustomFormat {
+            current_infix: None,
+            format: "%Y-%m-%d",
+        },
+    }
+}
+
+fn cleanup(config: &Log) -> anyhow::Result<Cleanup> {
+    // 保留策略：按天数或按文件数（可压缩）二选一
+    let cleanup = match (
+        config.keep_days,
+        config.keep_files,
+        config.keep_compressed_files,
+    ) {
+        (Some(_), _, Some(_)) => {
+            anyhow::bail!("[log] keep_days cannot be combined with keep_compressed_files")
+        }
+        (Some(days), _, None) => Cleanup::KeepForDays(days),
+        (None, plain, Some(compressed)) => {
+            Cleanup::KeepLogAndCompressedFiles(plain.unwrap_or(0), compressed)
+        }
+        (None, Some(plain), None) => Cleanup::KeepLogFiles(plain),
+        (None, None, None) => Cleanup::Never,
     };
+    Ok(cleanup)
+}
+
+fn format_function(format: LogFormat) -> FormatFunction {
+    match format {
+        LogFormat::Default => flexi_logger::default_format,
+        LogFormat::Detailed => jieto_detailed_format,
+        LogFormat::Json => flexi_logger::json_format,
+    }
+}
+
+fn file_writer(
+    config: &Log,
+    basename: &str,
+    format: FormatFunction,
+) -> anyhow::Result<FileLogWriter> {
+    let writer = FileLogWriter::builder(file_spec(config, basename))
+        .rotate(criterion(config), naming(config), cleanup(config)?)
+        .write_mode(WriteMode::BufferAndFlush)
+        .format(format)
+        .try_build()?;
+    Ok(writer)
+}
+
+enum Output {
+    Stdout(FormatFunction),
+    Stderr(FormatFunction),
+    File(FileLogWriter),
+}
+
+struct Sink {
+    level: LevelFilter,
+    output: Output,
+}
+
+/// Dispatches every record to the `[log] sinks` whose level admits it.
+struct SinkWriter {
+    sinks: Vec<Sink>,
+}
+
+impl SinkWriter {
+    fn new(config: &Log, basename: &str) -> anyhow::Result<Self> {
+        // 未配置时保持原有行为：滚动文件 + stderr
+        let defaults = [
+            LogSink {
+                kind: SinkKind::File,
+                level: None,
+                format: None,
+            },
+            LogSink {
+                kind: SinkKind::Stderr,
+                level: None,
+                format: None,
+            },
+        ];
+        let configured = if config.sinks.is_empty() {
+            &defaults[..]
+        } else {
+            &config.sinks[..]
+        };
+
+        let mut sinks = Vec::with_capacity(configured.len());
+        for sink in configured {
+            let level = match &sink.level {
+                Some(level) => level
+                    .parse::<LevelFilter>()
+                    .map_err(|_| anyhow::anyhow!("invalid log sink level '{}'", level))?,
+                None => LevelFilter::Trace,
+            };
+            let output = match sink.kind {
+                SinkKind::Stdout => {
+                    Output::Stdout(format_function(sink.format.unwrap_or(LogFormat::Default)))
+                }
+                SinkKind::Stderr => {
+                    Output::Stderr(format_function(sink.format.unwrap_or(LogFormat::Default)))
+                }
+                SinkKind::File => Output::File(file_writer(
+                    config,
+                    basename,
+                    format_function(sink.format.unwrap_or(LogFormat::Detailed)),
+                )?),
+            };
+            sinks.push(Sink { level, output });
+        }
+        Ok(Self { sinks })
+    }
+}
+
+fn write_console(
+    w: &mut dyn Write,
+    format: FormatFunction,
+    now: &mut DeferredNow,
+    record: &Record,
+) -> std::io::Result<()> {
+    format(w, now, record)?;
+    writeln!(w)
+}
+
+impl LogWriter for SinkWriter {
+    fn write(&self, now: &mut DeferredNow, record: &Record) -> std::io::Result<()> {
+        #[cfg(feature = "tracing")]
+        crate::telemetry::record_log(record);
+        for sink in &self.sinks {
+            if record.level() > sink.level {
+                continue;
+            }
+            match &sink.output {
+                Output::Stdout(format) => {
+                    write_console(&mut std::io::stdout().lock(), *format, now, record)?
+                }
+                Output::Stderr(format) => {
+                    write_console(&mut std::io::stderr().lock(), *format, now, record)?
+                }
+                Output::File(writer) => writer.write(now, record)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> std::io::Result<()> {
+        for sink in &self.sinks {
+            match &sink.output {
+                Output::Stdout(_) => std::io::stdout().flush()?,
+                Output::Stderr(_) => std::io::stderr().flush()?,
+                Output::File(writer) => writer.flush()?,
+            }
+        }
+        Ok(())
+    }
+
+    fn max_log_level(&self) -> LevelFilter {
+        self.sinks
+            .iter()
+            .map(|sink| sink.level)
+            .max()
+            .unwrap_or(LevelFilter::Off)
+    }
+
+    fn shutdown(&self) {
+        for sink in &self.sinks {
+            if let Output::File(writer) = &sink.output {
+                writer.shutdown();
+            }
+        }
+    }
+}
+
+pub fn init_logger(
+    config: &Log,
+    access_log: &AccessLog,
+    app_name: &str,
+) -> anyhow::Result<LoggerHandle> {
+    let handle = logger(config, access_log, app_name)?.start()?;
+
+    Ok(handle)
+}
+
+fn logger(config: &Log, access_log: &AccessLog, app_name: &str) -> anyhow::Result<Logger> {
+    // 设置 basename（即文件名前缀）
+    let basename = config.filename_prefix.as_deref().unwrap_or(app_name);
 
     let default_level = String::from("info");
     let level = config.level.as_ref().unwrap_or(&default_level);
-    let _ = Logger::try_with_str(level)?
-        .log_to_file(filespec)
-        .rotate(
-            criterion,
-            Naming::TimestampsDirect,
-            Cleanup::KeepLogFiles(config.keep_files),
+    let mut logger =
+        Logger::try_with_str(level)?.log_to_writer(Box::new(SinkWriter::new(config, basename)?));
+
+    // 访问日志写入独立的滚动文件
+    if access_log.enabled && access_log.file {
+        let access_basename = access_log
+            .filename_prefix
+            .clone()
+            .unwrap_or_else(|| format!("{basename}_access"));
+        let writer = file_writer(config, &access_basename, access_format)?;
+        logger = logger.add_writer("access", Box::new(writer));
+    }
+
+    Ok(logger)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::{Path, PathBuf};
+
+    fn directory(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("jieto-log-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn config(dir: &Path, toml: &str) -> Log {
+        let mut config: Log = toml::from_str(toml).unwrap();
+        config.directory = Some(dir.to_string_lossy().into_owned());
+        config
+    }
+
+    fn files(dir: &Path) -> Vec<String> {
+        let mut files: Vec<String> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        files
+    }
+
+    fn log(writer: &dyn LogWriter, level: log::Level, target: &str, message: &str) {
+        writer
+            .write(
+                &mut DeferredNow::new(),
+                &Record::builder()
+                    .level(level)
+                    .target(target)
+                    .args(format_args!("{message}"))
+                    .build(),
+            )
+            .unwrap();
+    }
+
+    /// File names with every digit replaced by `#`.
+    fn shapes(dir: &Path) -> Vec<String> {
+        files(dir)
+            .iter()
+            .map(|name| name.replace(|c: char| c.is_ascii_digit(), "#"))
+            .collect()
+    }
+
+    /// Writes a line and rotates, `times` times, then waits for the cleanup.
+    fn rotate(config: &Log, times: usize) {
+        let writer = file_writer(config, "app", access_format).unwrap();
+        for i in 0..times {
+            log(&writer, log::Level::Info, "app", &format!("line {i}"));
+            writer.rotate().unwrap();
+        }
+        writer.shutdown();
+    }
+
+    fn content(dir: &Path, prefix: &str) -> String {
+        files(dir)
+            .iter()
+            .filter(|name| name.starts_with(prefix))
+            .map(|name| std::fs::read_to_string(dir.join(name)).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn sinks_are_built_from_the_config() {
+        let dir = directory("sinks");
+        let writer = SinkWriter::new(
+            &config(
+                &dir,
+                r#"
+                [[sinks]]
+                kind = "stdout"
+                level = "warn"
+
+                [[sinks]]
+                kind = "stderr"
+                level = "error"
+
+                [[sinks]]
+                kind = "file"
+                "#,
+            ),
+            "app",
         )
-        .write_mode(WriteMode::BufferAndFlush)
-        .duplicate_to_stderr(Duplicate::All)
-        .format_for_files(jieto_detailed_format)
-        .start()?;
+        .unwrap();
+        let sinks: Vec<_> = writer
+            .sinks
+            .iter()
+            .map(|sink| {
+                let kind = match sink.output {
+                    Output::Stdout(_) => "stdout",
+                    Output::Stderr(_) => "stderr",
+                    Output::File(_) => "file",
+                };
+                (kind, sink.level)
+            })
+            .collect();
+        assert_eq!(
+            sinks,
+            [
+                ("stdout", LevelFilter::Warn),
+                ("stderr", LevelFilter::Error),
+                ("file", LevelFilter::Trace),
+            ]
+        );
+        assert_eq!(writer.max_log_level(), LevelFilter::Trace);
+        writer.shutdown();
+
+        // 未配置时为滚动文件加 stderr
+        let writer = SinkWriter::new(&config(&dir, ""), "app").unwrap();
+        assert!(matches!(
+            writer.sinks[..],
+            [
+                Sink {
+                    output: Output::File(_),
+                    ..
+                },
+                Sink {
+                    output: Output::Stderr(_),
+                    ..
+                }
+            ]
+        ));
+        writer.shutdown();
+        let _ = std::fs::remove_dir_all(&dir);
+        assert!(
+            SinkWriter::new(
+                &config(&dir, "[[sinks]]\nkind = \"file\"\nlevel = \"loud\""),
+                "app"
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn records_go_to_the_sinks_admitting_their_level() {
+        let dir = directory("levels");
+        let writer = SinkWriter::new(
+            &config(
+                &dir,
+                r#"
+                [[sinks]]
+                kind = "file"
+                level = "info"
+
+                [[sinks]]
+                kind = "stderr"
+                level = "error"
+                "#,
+            ),
+            "app",
+        )
+        .unwrap();
+        assert_eq!(writer.max_log_level(), LevelFilter::Info);
+        log(&writer, log::Level::Debug, "app", "debug line");
+        log(&writer, log::Level::Info, "app", "info line");
+        writer.shutdown();
+
+        let content = content(&dir, "app");
+        assert!(content.contains("info line"), "{content}");
+        assert!(!content.contains("debug line"), "{content}");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn access_records_go_to_their_own_file() {
+        let dir = directory("access");
+        let access_log = AccessLog {
+            file: true,
+            ..AccessLog::default()
+        };
+        let config = config(&dir, "[[sinks]]\nkind = \"file\"");
+        let (logger, handle) = logger(&config, &access_log, "app")
+            .unwrap()
+            .build()
+            .unwrap();
+        for (target, message) in [(ACCESS_WRITER_TARGET, "GET /users"), ("app", "started")] {
+            logger.log(
+                &Record::builder()
+                    .level(log::Level::Info)
+                    .target(target)
+                    .args(format_args!("{message}"))
+                    .build(),
+            );
+        }
+        handle.shutdown();
+
+        let access = content(&dir, "app_access");
+        assert!(access.contains("GET /users"), "{access}");
+        assert!(!access.contains("started"), "{access}");
+        let app: String = files(&dir)
+            .iter()
+            .filter(|name| !name.starts_with("app_access"))
+            .map(|name| std::fs::read_to_string(dir.join(name)).unwrap())
+            .collect();
+        assert!(app.contains("started"), "{app}");
+        assert!(!app.contains("GET /users"), "{app}");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn naming_of_rotated_files() {
+        let dir = directory("numbers");
+        rotate(&config(&dir, "naming = \"numbers\""), 2);
+        assert_eq!(
+            files(&dir),
+            ["app_r00000.log", "app_r00001.log", "app_rCURRENT.log"]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let dir = directory("timestamps");
+        rotate(&config(&dir, "naming = \"timestamps\""), 1);
+        let names = shapes(&dir);
+        assert!(!names.is_empty());
+        assert!(
+            names
+                .iter()
+                .all(|name| name.starts_with("app_r####-##-##_##-##-##")),
+            "{names:?}"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let dir = directory("date");
+        rotate(&config(&dir, "naming = \"date\""), 1);
+        let names = shapes(&dir);
+        assert!(!names.is_empty());
+        assert!(
+            names
+                .iter()
+                .all(|name| name.starts_with("app_####-##-##.")),
+            "{names:?}"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 
-    Ok(())
+    #[test]
+    fn rotated_files_are_kept_by_count() {
+        let dir = directory("never");
+        rotate(&config(&dir, "naming = \"numbers\""), 4);
+        assert_eq!(files(&dir).len(), 5);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let dir = directory("keep-files");
+        rotate(&config(&dir, "naming = \"numbers\"\nkeep_files = 2"), 4);
+        assert_eq!(
+            files(&dir),
+            ["app_r00002.log", "app_r00003.log", "app_rCURRENT.log"]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let dir = directory("keep-compressed");
+        rotate(
+            &config(
+                &dir,
+                "naming = \"numbers\"\nkeep_files = 1\nkeep_compressed_files = 2",
+            ),
+            4,
+        );
+        assert_eq!(
+            files(&dir),
+            [
+                "app_r00001.log.gz",
+                "app_r00002.log.gz",
+                "app_r00003.log",
+                "app_rCURRENT.log"
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rotated_files_are_kept_by_age() {
+        let dir = directory("keep-days");
+        let config = config(&dir, "naming = \"numbers\"\nkeep_days = 1");
+        rotate(&config, 2);
+        // 第一个归档文件改为两天前修改
+        let old = std::time::SystemTime::now() - std::time::Duration::from_secs(2 * 24 * 3600);
+        std::fs::File::options()
+            .write(true)
+            .open(dir.join("app_r00000.log"))
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+
+        // 重新打开时已有的 rCURRENT 先归档
+        rotate(&config, 1);
+        assert_eq!(
+            files(&dir),
+            [
+                "app_r00001.log",
+                "app_r00002.log",
+                "app_r00003.log",
+                "app_rCURRENT.log"
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(cleanup(&config).is_ok());
+        let mut invalid = config;
+        invalid.keep_compressed_files = Some(1);
+        assert!(cleanup(&invalid).is_err());
+    }
 }
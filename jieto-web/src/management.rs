@@ -0,0 +1,292 @@
+use crate::config::Management;
+use crate::error::WebError;
+use crate::logging;
+use crate::{ApiResult, AppState, JietoResult};
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::StatusCode;
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::middleware::{Next, from_fn};
+use actix_web::{Error, web};
+use flexi_logger::LoggerHandle;
+use jieto_config::{ExposeSecret, Secret};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::SystemTime;
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+
+/// Shared by the management endpoints, built once in `Application::run`.
+pub(crate) struct ManagementState {
+    pub name: String,
+    pub version: &'static str,
+    pub started: SystemTime,
+    pub token: Option<Secret>,
+    /// Redacted copy of the effective configuration.
+    pub env: toml::Table,
+    pub logger: LoggerHandle,
+}
+
+impl ManagementState {
+    pub(crate) fn new(
+        config: &Management,
+        name: String,
+        version: &'static str,
+        env: toml::Table,
+        logger: LoggerHandle,
+    ) -> Self {
+        Self {
+            name,
+            version,
+            started: SystemTime::now(),
+            token: config.token.clone(),
+            env,
+            logger,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Info {
+    name: String,
+    version: &'static str,
+    build: BuildInfo,
+    started_at: Option<String>,
+    uptime_secs: u64,
+}
+
+#[derive(Serialize)]
+struct BuildInfo {
+    framework: &'static str,
+    framework_version: &'static str,
+    profile: &'static str,
+    os: &'static str,
+    arch: &'static str,
+}
+
+#[derive(Serialize)]
+struct DataSourceView {
+    name: String,
+    kind: &'static str,
+    default: bool,
+}
+
+#[derive(Serialize)]
+struct JobView {
+    name: &'static str,
+    cron: &'static str,
+    next_tick: Option<String>,
+}
+
+#[derive(Serialize, Default)]
+struct WsView {
+    enabled: bool,
+    connections: usize,
+    visitors: usize,
+    rooms: Vec<RoomView>,
+}
+
+#[derive(Serialize)]
+struct RoomView {
+    name: String,
+    connections: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LoggerSpec {
+    spec: String,
+}
+
+fn format_time(time: SystemTime) -> Option<String> {
+    OffsetDateTime::from(time).format(&Rfc3339).ok()
+}
+
+async fn info(management: web::Data<ManagementState>) -> JietoResult<Info> {
+    let uptime = management.started.elapsed().unwrap_or_default();
+    ApiResult::ok(Info {
+        name: management.name.clone(),
+        version: management.version,
+        build: BuildInfo {
+            framework: env!("CARGO_PKG_NAME"),
+            framework_version: env!("CARGO_PKG_VERSION"),
+            profile: if cfg!(debug_assertions) {
+                "debug"
+            } else {
+                "release"
+            },
+            os: std::env::consts::OS,
+            arch: std::env::consts::ARCH,
+        },
+        started_at: format_time(management.started),
+        uptime_secs: uptime.as_secs(),
+    })
+}
+
+async fn env(management: web::Data<ManagementState>) -> JietoResult<toml::Table> {
+    ApiResult::ok(management.env.clone())
+}
+
+#[allow(unused_variables)]
+async fn datasources(state: web::Data<AppState>) -> JietoResult<Vec<DataSourceView>> {
+    #[cfg(feature = "database")]
+    let sources = state
+        .db_manager
+        .datasources()
+        .into_iter()
+        .map(|ds| DataSourceView {
+            name: ds.name,
+            kind: ds.kind,
+            default: ds.default,
+        })
+        .collect();
+    #[cfg(not(feature = "database"))]
+    let sources = vec![];
+
+    ApiResult::ok(sources)
+}
+
+#[allow(unused_variables)]
+async fn jobs(state: web::Data<AppState>) -> JietoResult<Vec<JobView>> {
+    #[cfg(feature = "job")]
+    let jobs = state
+        .scheduler
+        .tasks()
+        .await
+        .into_iter()
+        .map(|task| JobView {
+            name: task.name,
+            cron: task.cron,
+            next_tick: task.next_tick.and_then(format_time),
+        })
+        .collect();
+    #[cfg(not(feature = "job"))]
+    let jobs = vec![];
+
+    ApiResult::ok(jobs)
+}
+
+#[allow(unused_variables)]
+async fn ws(state: web::Data<AppState>) -> JietoResult<WsView> {
+    #[cfg(feature = "ws")]
+    if let Some(server) = &state.ws_server {
+        let stats = server.stats().await;
+        return ApiResult::ok(WsView {
+            enabled: true,
+            connections: stats.connections,
+            visitors: stats.visitors,
+            rooms: stats
+                .rooms
+                .into_iter()
+                .map(|(name, connections)| RoomView { name, connections })
+                .collect(),
+        });
+    }
+
+    ApiResult::ok(WsView::default())
+}
+
+async fn metrics() -> JietoResult<BTreeMap<String, u64>> {
+    ApiResult::ok(crate::metrics::snapshot())
+}
+
+#[cfg(feature = "flags")]
+async fn flags(state: web::Data<AppState>) -> JietoResult<Vec<crate::flags::FlagView>> {
+    ApiResult::ok(state.flags().list())
+}
+
+#[cfg(feature = "flags")]
+#[derive(Deserialize)]
+struct FlagUpdate {
+    value: crate::flags::Rollout,
+}
+
+/// Overrides a flag, e.g. `PUT /flags/new_checkout` with `{"value": 25}`.
+#[cfg(feature = "flags")]
+async fn update_flag(
+    state: web::Data<AppState>,
+    name: web::Path<String>,
+    body: web::Json<FlagUpdate>,
+) -> JietoResult<Vec<crate::flags::FlagView>> {
+    state
+        .flags()
+        .set(&name, body.value)
+        .await
+        .map_err(|e| WebError::Http(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    flags(state).await
+}
+
+async fn loggers(management: web::Data<ManagementState>) -> JietoResult<LoggerSpec> {
+    let spec = management
+        .logger
+        .current_log_spec()
+        .map_err(|e| WebError::Http(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    ApiResult::ok(LoggerSpec {
+        spec: spec.to_string(),
+    })
+}
+
+/// Replaces the log specification at runtime, e.g. `{"spec": "info, sqlx=debug"}`.
+async fn update_loggers(
+    management: web::Data<ManagementState>,
+    body: web::Json<LoggerSpec>,
+) -> JietoResult<LoggerSpec> {
+    management
+        .logger
+        .parse_new_spec(&body.spec)
+        .map_err(|e| WebError::Http(StatusCode::BAD_REQUEST, e.to_string()))?;
+    logging::info!("[management] log specification changed to '{}'", body.spec);
+    loggers(management).await
+}
+
+async fn require_token(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let expected = req
+        .app_data::<web::Data<ManagementState>>()
+        .and_then(|m| m.token.clone());
+
+    // 配置校验保证启用时有 token，缺失时同样拒绝
+    let provided = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    let valid = expected.is_some_and(|expected| {
+        constant_time_eq(provided.as_bytes(), expected.expose_secret().as_bytes())
+    });
+    if !valid {
+        return Err(WebError::Http(StatusCode::UNAUTHORIZED, String::from("invalid token")).into());
+    }
+
+    next.call(req).await
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Mounts the management endpoints under `prefix`.
+pub(crate) fn configure_management(
+    cfg: &mut web::ServiceConfig,
+    prefix: &str,
+    management: web::Data<ManagementState>,
+) {
+    let scope = web::scope(prefix.trim_end_matches('/'))
+        .app_data(management)
+        .wrap(from_fn(require_token))
+        .route("/info", web::get().to(info))
+        .route("/env", web::get().to(env))
+        .route("/datasources", web::get().to(datasources))
+        .route("/jobs", web::get().to(jobs))
+        .route("/ws", web::get().to(ws))
+        .route("/metrics", web::get().to(metrics))
+        .route("/loggers", web::get().to(loggers))
+        .route("/loggers", web::put().to(update_loggers));
+    #[cfg(feature = "flags")]
+    let scope = scope
+        .route("/flags", web::get().to(flags))
+        .route("/flags/{name}", web::put().to(update_flag));
+    cfg.service(scope);
+}
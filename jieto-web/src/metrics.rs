@@ -0,0 +1,17 @@
+//! Process wide counters, exposed by the management endpoint `/metrics`.
+
+use std::collections::BTreeMap;
+use std::sync::{LazyLock, Mutex};
+
+static COUNTERS: LazyLock<Mutex<BTreeMap<String, u64>>> = LazyLock::new(Default::default);
+
+/// Adds one to the counter `name`, e.g. `http_server_timeouts_total{route="/reports/{id}"}`.
+pub(crate) fn increment(name: String) {
+    // unwrap: the lock is never held across a panic
+    *COUNTERS.lock().unwrap().entry(name).or_default() += 1;
+}
+
+pub(crate) fn snapshot() -> BTreeMap<String, u64> {
+    // unwrap: the lock is never held across a panic
+    COUNTERS.lock().unwrap().clone()
+}
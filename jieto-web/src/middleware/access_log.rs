@@ -0,0 +1,356 @@
+use crate::config::AccessLog;
+use crate::extract::client_ip;
+use crate::log4r::{ACCESS_TARGET, ACCESS_WRITER_TARGET};
+use crate::logging;
+use crate::middleware::{path_matches, request_path};
+use actix_web::Error;
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform, forward_ready};
+use actix_web::http::StatusCode;
+use actix_web::http::header::HeaderMap;
+use futures_util::future::LocalBoxFuture;
+use std::fmt::Write as _;
+use std::future::{Ready, ready};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+
+/// A parsed access log format directive.
+///
+/// Supported: `%%`, `%a` client ip, `%t` time, `%r` request line, `%s` status, `%b` response
+/// size, `%T` seconds, `%D` milliseconds, `%U` path, `%{Name}i` request header and
+/// `%{Name}o` response header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Text(String),
+    ClientIp,
+    Time,
+    RequestLine,
+    Status,
+    Size,
+    Seconds,
+    Millis,
+    Path,
+    RequestHeader(String),
+    ResponseHeader(String),
+}
+
+fn parse_format(format: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut text = String::new();
+    let mut chars = format.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            text.push(c);
+            continue;
+        }
+
+        let token = match chars.next() {
+            Some('%') => {
+                text.push('%');
+                continue;
+            }
+            Some('a') => Token::ClientIp,
+            Some('t') => Token::Time,
+            Some('r') => Token::RequestLine,
+            Some('s') => Token::Status,
+            Some('b') => Token::Size,
+            Some('T') => Token::Seconds,
+            Some('D') => Token::Millis,
+            Some('U') => Token::Path,
+            Some('{') => {
+                let rest = chars.as_str();
+                let Some(end) = rest.find('}') else {
+                    // 缺少 `}` 时原样输出
+                    text.push_str("%{");
+                    continue;
+                };
+                let name = rest[..end].to_string();
+                chars = rest[end + 1..].chars();
+                match chars.next() {
+                    Some('i') => Token::RequestHeader(name),
+                    Some('o') => Token::ResponseHeader(name),
+                    other => {
+                        // 不支持的指令原样输出
+                        text.push_str(&format!("%{{{name}}}"));
+                        text.extend(other);
+                        continue;
+                    }
+                }
+            }
+            other => {
+                text.push('%');
+                text.extend(other);
+                continue;
+            }
+        };
+
+        if !text.is_empty() {
+            tokens.push(Token::Text(std::mem::take(&mut text)));
+        }
+        tokens.push(token);
+    }
+
+    if !text.is_empty() {
+        tokens.push(Token::Text(text));
+    }
+    tokens
+}
+
+/// Writes one line per request as configured by `[web.access_log]`.
+#[derive(Debug, Clone)]
+pub(crate) struct AccessLogger {
+    config: Arc<AccessLog>,
+    tokens: Arc<Vec<Token>>,
+}
+
+impl AccessLogger {
+    pub(crate) fn new(config: AccessLog) -> Self {
+        let tokens = parse_format(&config.format);
+        Self {
+            config: Arc::new(config),
+            tokens: Arc::new(tokens),
+        }
+    }
+
+    fn excluded(&self, path: &str) -> bool {
+        self.config
+            .exclude
+            .iter()
+            .any(|pattern| path_matches(pattern, path))
+    }
+
+    /// Captures the request side before the request is handed to the inner service.
+    fn request_info(&self, req: &ServiceRequest) -> RequestInfo {
+        let uri = req.uri();
+        let headers = self
+            .tokens
+            .iter()
+            .filter_map(|token| match token {
+                Token::RequestHeader(name) => Some(header(req.headers(), name).to_string()),
+                _ => None,
+            })
+            .collect();
+
+        RequestInfo {
+            client_ip: client_ip(req.request()).map(|ip| ip.to_string()),
+            request_line: format!(
+                "{} {} {:?}",
+                req.method(),
+                uri.path_and_query().map_or(uri.path(), |pq| pq.as_str()),
+                req.version()
+            ),
+            path: req.path().to_string(),
+            headers,
+        }
+    }
+
+    fn render(
+        &self,
+        req: &RequestInfo,
+        status: StatusCode,
+        size: BodySize,
+        headers: &HeaderMap,
+        elapsed: Duration,
+    ) -> String {
+        let mut line = String::new();
+        let mut request_headers = req.headers.iter();
+        for token in self.tokens.iter() {
+            // unwrap: writing to a String cannot fail
+            match token {
+                Token::Text(text) => line.push_str(text),
+                Token::ClientIp => line.push_str(req.client_ip.as_deref().unwrap_or("-")),
+                Token::Time => {
+                    let now =
+                        OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
+                    line.push_str(&now.format(&Rfc3339).unwrap_or_default());
+                }
+                Token::RequestLine => line.push_str(&req.request_line),
+                Token::Status => write!(line, "{}", status.as_u16()).unwrap(),
+                Token::Size => match size {
+                    BodySize::Sized(size) => write!(line, "{size}").unwrap(),
+                    _ => line.push('-'),
+                },
+                Token::Seconds => write!(line, "{:.6}", elapsed.as_secs_f64()).unwrap(),
+                Token::Millis => write!(line, "{}", elapsed.as_millis()).unwrap(),
+                Token::Path => line.push_str(&req.path),
+                Token::RequestHeader(_) => {
+                    line.push_str(request_headers.next().map_or("-", String::as_str))
+                }
+                Token::ResponseHeader(name) => line.push_str(header(headers, name)),
+            }
+        }
+        line
+    }
+
+    fn log(&self, line: String, elapsed: Duration) {
+        let slow = self
+            .config
+            .slow_threshold_ms
+            .is_some_and(|threshold| elapsed.as_millis() >= threshold as u128);
+
+        // tracing 的 target 必须是常量
+        match (self.config.file, slow) {
+            (true, true) => logging::warn!(target: ACCESS_WRITER_TARGET, "[SLOW] {}", line),
+            (true, false) => logging::info!(target: ACCESS_WRITER_TARGET, "{}", line),
+            (false, true) => logging::warn!(target: ACCESS_TARGET, "[SLOW] {}", line),
+            (false, false) => logging::info!(target: ACCESS_TARGET, "{}", line),
+        }
+    }
+}
+
+struct RequestInfo {
+    client_ip: Option<String>,
+    request_line: String,
+    path: String,
+    /// Values of the `%{Name}i` directives in format order.
+    headers: Vec<String>,
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> &'a str {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("-")
+}
+
+impl<S, B> Transform<S, ServiceRequest> for AccessLogger
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = AccessLoggerMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AccessLoggerMiddleware {
+            service: Rc::new(service),
+            logger: self.clone(),
+        }))
+    }
+}
+
+pub(crate) struct AccessLoggerMiddleware<S> {
+    service: Rc<S>,
+    logger: AccessLogger,
+}
+
+impl<S, B> Service<ServiceRequest> for AccessLoggerMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        if self.logger.excluded(request_path(&req)) {
+            return Box::pin(async move { service.call(req).await });
+        }
+
+        let logger = self.logger.clone();
+        let started = Instant::now();
+        let request = logger.request_info(&req);
+
+        Box::pin(async move {
+            let result = service.call(req).await;
+            let elapsed = started.elapsed();
+            let line = match &result {
+                Ok(res) => logger.render(
+                    &request,
+                    res.status(),
+                    res.response().body().size(),
+                    res.headers(),
+                    elapsed,
+                ),
+                Err(err) => {
+                    let res = err.error_response();
+                    logger.render(
+                        &request,
+                        res.status(),
+                        res.body().size(),
+                        res.headers(),
+                        elapsed,
+                    )
+                }
+            };
+            logger.log(line, elapsed);
+            result
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> Token {
+        Token::Text(text.to_string())
+    }
+
+    #[test]
+    fn parses_every_directive() {
+        assert_eq!(
+            parse_format("%a %t %r %s %b %T %D %U %{User-Agent}i %{X-Request-Id}o"),
+            [
+                Token::ClientIp,
+                text(" "),
+                Token::Time,
+                text(" "),
+                Token::RequestLine,
+                text(" "),
+                Token::Status,
+                text(" "),
+                Token::Size,
+                text(" "),
+                Token::Seconds,
+                text(" "),
+                Token::Millis,
+                text(" "),
+                Token::Path,
+                text(" "),
+                Token::RequestHeader(String::from("User-Agent")),
+                text(" "),
+                Token::ResponseHeader(String::from("X-Request-Id")),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_literal_text() {
+        assert_eq!(parse_format(""), []);
+        assert_eq!(parse_format("plain text"), [text("plain text")]);
+        assert_eq!(
+            parse_format("[%s] 100%% done in %Dms"),
+            [
+                text("["),
+                Token::Status,
+                text("] 100% done in "),
+                Token::Millis,
+                text("ms"),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_unknown_directives_as_text() {
+        assert_eq!(parse_format("%x %s"), [text("%x "), Token::Status]);
+        assert_eq!(
+            parse_format("%{Host}x-%s"),
+            [text("%{Host}x-"), Token::Status]
+        );
+        assert_eq!(parse_format("%{Host}"), [text("%{Host}")]);
+        assert_eq!(parse_format("%{Host %s"), [text("%{Host "), Token::Status]);
+        assert_eq!(parse_format("100%"), [text("100%")]);
+    }
+}
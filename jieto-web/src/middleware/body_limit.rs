@@ -0,0 +1,243 @@
+use crate::config::Limits;
+use crate::error::WebError;
+use crate::middleware::{path_matches, request_path};
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{Payload, Service, ServiceRequest, ServiceResponse, Transform, forward_ready};
+use actix_web::error::PayloadError;
+use actix_web::http::StatusCode;
+use actix_web::http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use actix_web::{Error, HttpMessage, HttpRequest, ResponseError, web};
+use futures_util::StreamExt as _;
+use futures_util::future::LocalBoxFuture;
+use std::future::{Ready, ready};
+use std::rc::Rc;
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BodyKind {
+    Json,
+    Form,
+    Payload,
+}
+
+impl BodyKind {
+    /// Multipart bodies are left to the upload handling and get no kind.
+    fn of(req: &ServiceRequest) -> Option<Self> {
+        let content_type = req
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        let essence = content_type.split(';').next().unwrap_or_default().trim();
+
+        if essence.starts_with("multipart/") {
+            None
+        } else if essence == "application/json" || essence.ends_with("+json") {
+            Some(BodyKind::Json)
+        } else if essence == "application/x-www-form-urlencoded" {
+            Some(BodyKind::Form)
+        } else {
+            Some(BodyKind::Payload)
+        }
+    }
+}
+
+/// Enforces `[web.limits]`.
+///
+/// The extractor configs carry the largest limit of any route so that per-route overrides can
+/// raise it, this middleware then caps each request at its effective limit by checking
+/// `Content-Length` and counting streamed bytes. `PayloadConfig` takes no error handler, so the
+/// overflow errors of the `Bytes` and `String` extractors are replaced here by a 413 `ApiResult`.
+#[derive(Debug, Clone)]
+pub(crate) struct BodyLimit {
+    limits: Arc<Limits>,
+}
+
+impl BodyLimit {
+    pub(crate) fn new(limits: Arc<Limits>) -> Self {
+        Self { limits }
+    }
+
+    fn max(&self, kind: BodyKind) -> usize {
+        self.limits
+            .routes
+            .iter()
+            .filter_map(|route| route_limit(route, kind))
+            .fold(global_limit(&self.limits, kind), usize::max)
+    }
+
+    fn effective(&self, path: &str, kind: BodyKind) -> usize {
+        self.limits
+            .routes
+            .iter()
+            .find(|route| path_matches(&route.pattern, path))
+            .and_then(|route| route_limit(route, kind))
+            .unwrap_or_else(|| global_limit(&self.limits, kind))
+    }
+
+    /// Registers extractor configs returning `ApiResult` errors.
+    pub(crate) fn configure(&self, cfg: &mut web::ServiceConfig) {
+        cfg.app_data(
+            web::JsonConfig::default()
+                .limit(self.max(BodyKind::Json))
+                .error_handler(|err, _req: &HttpRequest| {
+                    WebError::Http(err.status_code(), err.to_string()).into()
+                }),
+        )
+        .app_data(
+            web::FormConfig::default()
+                .limit(self.max(BodyKind::Form))
+                .error_handler(|err, _req: &HttpRequest| {
+                    WebError::Http(err.status_code(), err.to_string()).into()
+                }),
+        )
+        .app_data(web::PayloadConfig::default().limit(self.max(BodyKind::Payload)));
+    }
+}
+
+fn global_limit(limits: &Limits, kind: BodyKind) -> usize {
+    match kind {
+        BodyKind::Json => limits.json,
+        BodyKind::Form => limits.form,
+        BodyKind::Payload => limits.payload,
+    }
+}
+
+fn route_limit(route: &crate::config::RouteLimits, kind: BodyKind) -> Option<usize> {
+    match kind {
+        BodyKind::Json => route.json,
+        BodyKind::Form => route.form,
+        BodyKind::Payload => route.payload,
+    }
+}
+
+fn too_large(limit: usize) -> WebError {
+    WebError::Http(
+        StatusCode::PAYLOAD_TOO_LARGE,
+        format!("payload is larger than allowed (limit: {limit} bytes)"),
+    )
+}
+
+impl<S, B> Transform<S, ServiceRequest> for BodyLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = BodyLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(BodyLimitMiddleware {
+            service: Rc::new(service),
+            limit: self.clone(),
+        }))
+    }
+}
+
+pub(crate) struct BodyLimitMiddleware<S> {
+    service: Rc<S>,
+    limit: BodyLimit,
+}
+
+impl<S, B> Service<ServiceRequest> for BodyLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let limit = BodyKind::of(&req).and_then(|kind| {
+            let effective = self.limit.effective(request_path(&req), kind);
+            // 仅当路由限制低于提取器上限时才需要额外计数
+            (effective < self.limit.max(kind)).then_some(effective)
+        });
+
+        if let Some(limit) = limit {
+            let declared = req
+                .headers()
+                .get(CONTENT_LENGTH)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse::<usize>().ok());
+            if declared.is_some_and(|len| len > limit) {
+                let res = too_large(limit).error_response();
+                return Box::pin(ready(Ok(req.into_response(res).map_into_right_body())));
+            }
+
+            let mut received = 0usize;
+            let payload = req.take_payload().map(move |chunk| {
+                let chunk = chunk?;
+                received += chunk.len();
+                if received > limit {
+                    Err(PayloadError::Overflow)
+                } else {
+                    Ok(chunk)
+                }
+            });
+            req.set_payload(Payload::Stream {
+                payload: Box::pin(payload),
+            });
+        }
+
+        let payload_limit = self.limit.max(BodyKind::Payload);
+        let reported = limit.map_or(payload_limit, |limit| limit.min(payload_limit));
+        let service = self.service.clone();
+        Box::pin(async move {
+            let res = service.call(req).await?;
+            let overflow = res
+                .response()
+                .error()
+                .and_then(|e| e.as_error::<PayloadError>())
+                .is_some_and(|e| matches!(e, PayloadError::Overflow));
+            if overflow {
+                let error = too_large(reported).error_response();
+                return Ok(res.into_response(error).map_into_right_body());
+            }
+            Ok(res.map_into_left_body())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::web::Bytes;
+    use actix_web::{App, HttpResponse, test};
+
+    #[actix_web::test]
+    async fn payload_overflow_returns_api_result() {
+        let limit = BodyLimit::new(Arc::new(Limits {
+            payload: 16,
+            ..Limits::default()
+        }));
+        let app = test::init_service(
+            App::new()
+                .configure(|cfg| limit.configure(cfg))
+                .wrap(limit.clone())
+                .route(
+                    "/",
+                    web::post().to(|body: Bytes| async move { HttpResponse::Ok().body(body) }),
+                ),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/")
+            .insert_header((CONTENT_TYPE, "application/octet-stream"))
+            .set_payload(vec![0u8; 32])
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let body = test::read_body(res).await;
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.starts_with(r#"{"code":413,"#), "{body}");
+    }
+}
@@ -0,0 +1,242 @@
+use crate::error::WebError;
+use crate::extract::client_ip;
+use crate::logging;
+use actix_web::Error;
+use actix_web::body::MessageBody;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform, forward_ready};
+use actix_web::http::StatusCode;
+use futures_util::FutureExt as _;
+use futures_util::future::LocalBoxFuture;
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::cell::{Cell, RefCell};
+use std::future::{Future, Ready, ready};
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Once;
+use std::task::{Context, Poll};
+
+thread_local! {
+    /// Set while a request future is polled, the panic hook then keeps the panic for the log.
+    /// Panics the future catches itself are logged once its poll returns.
+    static CATCHING: Cell<bool> = const { Cell::new(false) };
+    static CAUGHT: RefCell<Option<Caught>> = const { RefCell::new(None) };
+}
+
+struct Caught {
+    message: String,
+    location: Option<String>,
+    backtrace: Backtrace,
+}
+
+impl Caught {
+    /// Logs a panic that the handler caught itself instead of the previous hook.
+    fn report(self) {
+        logging::warn!(
+            "[panic] '{}' at {}, caught by the handler",
+            self.message,
+            self.location.as_deref().unwrap_or("<unknown>")
+        );
+    }
+}
+
+/// Replaces the panic hook once, panics outside request futures still reach the previous hook.
+fn install_hook() {
+    static HOOK: Once = Once::new();
+    HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if !CATCHING.get() {
+                return previous(info);
+            }
+            let caught = Caught {
+                message: info.payload_as_str().unwrap_or("Box<dyn Any>").to_string(),
+                location: info.location().map(|l| l.to_string()),
+                backtrace: Backtrace::force_capture(),
+            };
+            // 上一个 panic 没有传到中间件，已被处理函数捕获
+            if let Some(previous) = CAUGHT.replace(Some(caught)) {
+                previous.report();
+            }
+        }));
+    });
+}
+
+/// Polls a future, returning the payload of a panic instead of unwinding.
+struct CatchUnwind<F> {
+    inner: F,
+}
+
+impl<F: Future + Unpin> Future for CatchUnwind<F> {
+    type Output = Result<F::Output, Box<dyn Any + Send>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let catching = CATCHING.replace(true);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| self.inner.poll_unpin(cx)));
+        CATCHING.set(catching);
+        match result {
+            Ok(poll) => {
+                if let Some(caught) = CAUGHT.take() {
+                    caught.report();
+                }
+                poll.map(Ok)
+            }
+            Err(payload) => Poll::Ready(Err(payload)),
+        }
+    }
+}
+
+pub(crate) fn payload_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s
+    } else {
+        "Box<dyn Any>"
+    }
+}
+
+/// Turns a panic of the handler into a 500 `ApiResult`, logging the payload and backtrace.
+///
+/// Wrapped innermost so that the outer middlewares see the error like any handler error, the
+/// handler future, and with it any open transaction, is dropped before that.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CatchPanic;
+
+impl<S, B> Transform<S, ServiceRequest> for CatchPanic
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = CatchPanicMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        install_hook();
+        ready(Ok(CatchPanicMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub(crate) struct CatchPanicMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for CatchPanicMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let method = req.method().clone();
+        let uri = req.uri().clone();
+        let ip = client_ip(req.request());
+
+        Box::pin(async move {
+            let future = CatchUnwind {
+                inner: Box::pin(async move { service.call(req).await }),
+            };
+            let payload = match future.await {
+                Ok(result) => return result,
+                Err(payload) => payload,
+            };
+
+            let (location, backtrace) = match CAUGHT.take() {
+                Some(caught) => (caught.location, caught.backtrace.to_string()),
+                None => (None, String::new()),
+            };
+            logging::error!(
+                "[panic] {} {} from {}: '{}' at {}\n{}",
+                method,
+                uri.path_and_query().map_or(uri.path(), |pq| pq.as_str()),
+                ip.map_or_else(|| String::from("-"), |ip| ip.to_string()),
+                payload_message(payload.as_ref()),
+                location.as_deref().unwrap_or("<unknown>"),
+                backtrace
+            );
+            Err(WebError::Http(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                String::from("internal server error"),
+            )
+            .into())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{App, HttpResponse, test, web};
+
+    #[actix_web::test]
+    async fn panics_caught_by_the_handler_are_not_kept() {
+        let app = test::init_service(
+            App::new()
+                .wrap(CatchPanic)
+                .route(
+                    "/caught",
+                    web::get().to(|| async {
+                        let caught = panic::catch_unwind(|| panic!("caught"));
+                        assert!(caught.is_err());
+                        HttpResponse::Ok().finish()
+                    }),
+                )
+                .route(
+                    "/panic",
+                    web::get().to(|| async {
+                        panic!("escaped");
+                        #[allow(unreachable_code)]
+                        HttpResponse::Ok().finish()
+                    }),
+                ),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/caught").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        assert!(CAUGHT.with_borrow(Option::is_none));
+
+        let req = test::TestRequest::get().uri("/panic").to_request();
+        let err = app.call(req).await.err().unwrap();
+        assert_eq!(
+            err.as_response_error().status_code(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert!(CAUGHT.with_borrow(Option::is_none));
+    }
+
+    #[actix_web::test]
+    async fn panics_after_an_await_return_an_api_result() {
+        let app = test::init_service(App::new().wrap(CatchPanic).route(
+            "/",
+            web::get().to(|| async {
+                tokio::task::yield_now().await;
+                panic!("after await");
+                #[allow(unreachable_code)]
+                HttpResponse::Ok().finish()
+            }),
+        ))
+        .await;
+
+        let req = test::TestRequest::get().uri("/").to_request();
+        let res = app.call(req).await.err().unwrap().error_response();
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = actix_web::body::to_bytes(res.into_body()).await.unwrap();
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.starts_with(r#"{"code":500,"#), "{body}");
+        assert!(body.contains("internal server error"), "{body}");
+        assert!(!body.contains("after await"), "{body}");
+    }
+}
@@ -0,0 +1,273 @@
+use crate::config::Compression;
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform, forward_ready};
+use actix_web::http::header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE, HeaderValue};
+use actix_web::Error;
+use futures_util::future::LocalBoxFuture;
+use std::future::{Ready, ready};
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// Applies `[web.compression]` around actix's `Compress`.
+///
+/// [`AcceptEncodingFilter`] wraps `Compress` and narrows the negotiable codings to the configured
+/// algorithms, [`CompressionSkip`] sits inside it and marks small or excluded responses as
+/// `identity` so `Compress` leaves them alone.
+#[derive(Debug, Clone)]
+pub(crate) struct AcceptEncodingFilter {
+    config: Arc<Compression>,
+}
+
+impl AcceptEncodingFilter {
+    pub(crate) fn new(config: Arc<Compression>) -> Self {
+        Self { config }
+    }
+
+    fn filter(&self, accept: &str) -> Option<String> {
+        let allowed = |coding: &str| {
+            coding == "identity"
+                || self
+                    .config
+                    .algorithms
+                    .iter()
+                    .any(|a| a.eq_ignore_ascii_case(coding))
+        };
+
+        let mut codings = Vec::new();
+        for item in accept.split(',') {
+            let item = item.trim();
+            let (coding, params) = item.split_once(';').unwrap_or((item, ""));
+            match coding.trim() {
+                // 通配符展开为允许的算法，避免协商出未启用的编码
+                "*" => codings.extend(self.config.algorithms.iter().map(|a| {
+                    if params.is_empty() {
+                        a.clone()
+                    } else {
+                        format!("{a};{params}")
+                    }
+                })),
+                coding if allowed(coding) => codings.push(item.to_string()),
+                _ => {}
+            }
+        }
+        (!codings.is_empty()).then(|| codings.join(", "))
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for AcceptEncodingFilter
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    B: MessageBody,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = AcceptEncodingFilterMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AcceptEncodingFilterMiddleware {
+            service,
+            filter: self.clone(),
+        }))
+    }
+}
+
+pub(crate) struct AcceptEncodingFilterMiddleware<S> {
+    service: S,
+    filter: AcceptEncodingFilter,
+}
+
+impl<S, B> Service<ServiceRequest> for AcceptEncodingFilterMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    B: MessageBody,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = S::Future;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let accept = req
+            .headers()
+            .get(ACCEPT_ENCODING)
+            .and_then(|v| v.to_str().ok())
+            .map(|accept| self.filter.filter(accept));
+
+        match accept {
+            Some(Some(accept)) => {
+                if let Ok(value) = HeaderValue::from_str(&accept) {
+                    req.headers_mut().insert(ACCEPT_ENCODING, value);
+                }
+            }
+            // 没有可用算法时按未声明处理，返回原始内容而不是 406
+            Some(None) => {
+                req.headers_mut().remove(ACCEPT_ENCODING);
+            }
+            None => {}
+        }
+
+        self.service.call(req)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct CompressionSkip {
+    config: Arc<Compression>,
+}
+
+impl CompressionSkip {
+    pub(crate) fn new(config: Arc<Compression>) -> Self {
+        Self { config }
+    }
+
+    fn skip<B: MessageBody>(&self, res: &ServiceResponse<B>) -> bool {
+        if let BodySize::Sized(size) = res.response().body().size()
+            && size < self.config.min_size as u64
+        {
+            return true;
+        }
+
+        res.headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|content_type| {
+                let content_type = content_type.to_ascii_lowercase();
+                self.config
+                    .exclude_content_types
+                    .iter()
+                    .any(|excluded| content_type.starts_with(&excluded.to_ascii_lowercase()))
+            })
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for CompressionSkip
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = CompressionSkipMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(CompressionSkipMiddleware {
+            service: Rc::new(service),
+            skip: self.clone(),
+        }))
+    }
+}
+
+pub(crate) struct CompressionSkipMiddleware<S> {
+    service: Rc<S>,
+    skip: CompressionSkip,
+}
+
+impl<S, B> Service<ServiceRequest> for CompressionSkipMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let skip = self.skip.clone();
+
+        Box::pin(async move {
+            let mut res = service.call(req).await?;
+            if !res.headers().contains_key(CONTENT_ENCODING) && skip.skip(&res) {
+                res.headers_mut()
+                    .insert(CONTENT_ENCODING, HeaderValue::from_static("identity"));
+            }
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::middleware::Compress;
+    use actix_web::test::{TestRequest, call_service, init_service};
+    use actix_web::{App, HttpResponse, web};
+
+    fn config() -> Arc<Compression> {
+        Arc::new(Compression {
+            enabled: true,
+            algorithms: vec![String::from("gzip")],
+            ..Compression::default()
+        })
+    }
+
+    /// Content encoding of the response to `GET path` accepting gzip.
+    async fn encoding(path: &str) -> Option<String> {
+        let config = config();
+        let app = init_service(
+            App::new()
+                .wrap(CompressionSkip::new(config.clone()))
+                .wrap(Compress::default())
+                .wrap(AcceptEncodingFilter::new(config))
+                .route(
+                    "/text",
+                    web::get().to(|| async { HttpResponse::Ok().body("a".repeat(4096)) }),
+                )
+                .route(
+                    "/small",
+                    web::get().to(|| async { HttpResponse::Ok().body("a".repeat(100)) }),
+                )
+                .route(
+                    "/image",
+                    web::get().to(|| async {
+                        HttpResponse::Ok()
+                            .content_type("image/png")
+                            .body(vec![0u8; 4096])
+                    }),
+                ),
+        )
+        .await;
+        let req = TestRequest::get()
+            .uri(path)
+            .insert_header((ACCEPT_ENCODING, "gzip, br"))
+            .to_request();
+        let res = call_service(&app, req).await;
+        res.headers()
+            .get(CONTENT_ENCODING)
+            .map(|v| v.to_str().unwrap().to_string())
+    }
+
+    #[actix_web::test]
+    async fn large_responses_are_compressed() {
+        assert_eq!(encoding("/text").await.as_deref(), Some("gzip"));
+    }
+
+    #[actix_web::test]
+    async fn responses_under_the_minimum_size_are_not_compressed() {
+        assert_ne!(encoding("/small").await.as_deref(), Some("gzip"));
+    }
+
+    #[actix_web::test]
+    async fn excluded_content_types_are_not_compressed() {
+        assert_ne!(encoding("/image").await.as_deref(), Some("gzip"));
+    }
+
+    #[test]
+    fn accept_encoding_is_narrowed_to_the_configured_algorithms() {
+        let filter = AcceptEncodingFilter::new(config());
+        assert_eq!(
+            filter.filter("br, gzip;q=0.8").as_deref(),
+            Some("gzip;q=0.8")
+        );
+        assert_eq!(filter.filter("*;q=0.5").as_deref(), Some("gzip;q=0.5"));
+        assert_eq!(filter.filter("identity").as_deref(), Some("identity"));
+        assert_eq!(filter.filter("br, zstd"), None);
+    }
+}
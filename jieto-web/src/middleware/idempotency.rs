@@ -0,0 +1,697 @@
+use crate::config::{Idempotency, RateLimitBackend};
+use crate::error::WebError;
+use crate::extract::CurrentUser;
+use crate::logging;
+use crate::middleware::{path_matches, request_path};
+use actix_web::body::{BodySize, BoxBody, EitherBody, MessageBody};
+use actix_web::dev::{Payload, Service, ServiceRequest, ServiceResponse, Transform, forward_ready};
+use actix_web::error::PayloadError;
+use actix_web::http::StatusCode;
+use actix_web::http::header::{
+    CONNECTION, CONTENT_LENGTH, DATE, HeaderName, HeaderValue, SET_COOKIE, TRANSFER_ENCODING,
+};
+use actix_web::web::{Bytes, BytesMut};
+use actix_web::{Error, HttpMessage, HttpResponse, ResponseError};
+use futures_util::StreamExt as _;
+use futures_util::future::LocalBoxFuture;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::future::{Ready, ready};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const IDEMPOTENT_REPLAYED: HeaderName = HeaderName::from_static("idempotent-replayed");
+
+/// Keys longer than this are rejected.
+const MAX_KEY_LEN: usize = 255;
+
+/// Memory store entries are swept every this many requests.
+const SWEEP_EVERY: u64 = 1024;
+
+/// Stored state of a key: in progress until `response` is set.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Record {
+    fingerprint: String,
+    response: Option<StoredResponse>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredResponse {
+    status: u16,
+    headers: Vec<(String, Vec<u8>)>,
+    body: Vec<u8>,
+}
+
+impl StoredResponse {
+    fn to_response(&self) -> HttpResponse {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::OK);
+        let mut res = HttpResponse::build(status);
+        for (name, value) in &self.headers {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::try_from(name.as_str()),
+                HeaderValue::from_bytes(value),
+            ) {
+                res.append_header((name, value));
+            }
+        }
+        res.insert_header((IDEMPOTENT_REPLAYED, HeaderValue::from_static("true")));
+        res.body(self.body.clone())
+    }
+}
+
+#[derive(Debug, Default)]
+struct MemoryStore {
+    /// Records with the instant after which they can be dropped.
+    entries: Mutex<HashMap<String, (Record, Instant)>>,
+    requests: AtomicU64,
+}
+
+impl MemoryStore {
+    fn begin(&self, key: &str, fingerprint: &str, lock: Duration) -> Option<Record> {
+        let now = Instant::now();
+        // unwrap: the lock is never held across a panic
+        let mut entries = self.entries.lock().unwrap();
+
+        if self
+            .requests
+            .fetch_add(1, Ordering::Relaxed)
+            .is_multiple_of(SWEEP_EVERY)
+        {
+            entries.retain(|_, (_, expires)| *expires > now);
+        }
+
+        match entries.get(key) {
+            Some((record, expires)) if *expires > now => Some(record.clone()),
+            _ => {
+                let record = Record {
+                    fingerprint: fingerprint.to_string(),
+                    response: None,
+                };
+                entries.insert(key.to_string(), (record, now + lock));
+                None
+            }
+        }
+    }
+
+    fn complete(&self, key: &str, record: Record, ttl: Duration) {
+        let expires = Instant::now() + ttl;
+        // unwrap: the lock is never held across a panic
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), (record, expires));
+    }
+
+    fn release(&self, key: &str) {
+        // unwrap: the lock is never held across a panic
+        self.entries.lock().unwrap().remove(key);
+    }
+}
+
+#[cfg(feature = "redis")]
+mod redis_store {
+    use super::Record;
+    use deadpool_redis::redis::{AsyncCommands, Script};
+    use std::sync::LazyLock;
+    use std::time::Duration;
+
+    // 在一个脚本内读取或占用：返回已有记录，否则写入进行中标记
+    static BEGIN: LazyLock<Script> = LazyLock::new(|| {
+        Script::new(
+            r#"
+local existing = redis.call('GET', KEYS[1])
+if existing then
+  return existing
+end
+redis.call('SET', KEYS[1], ARGV[1], 'PX', ARGV[2])
+return false
+"#,
+        )
+    });
+
+    pub(super) async fn begin(
+        pool: &deadpool_redis::Pool,
+        key: &str,
+        fingerprint: &str,
+        lock: Duration,
+    ) -> anyhow::Result<Option<Record>> {
+        let mut conn = pool.get().await?;
+        let marker = serde_json::to_vec(&Record {
+            fingerprint: fingerprint.to_string(),
+            response: None,
+        })?;
+        let existing: Option<Vec<u8>> = BEGIN
+            .key(key)
+            .arg(marker)
+            .arg(lock.as_millis() as u64)
+            .invoke_async(&mut conn)
+            .await?;
+        Ok(existing
+            .map(|bytes| serde_json::from_slice(&bytes))
+            .transpose()?)
+    }
+
+    pub(super) async fn complete(
+        pool: &deadpool_redis::Pool,
+        key: &str,
+        record: &Record,
+        ttl: Duration,
+    ) -> anyhow::Result<()> {
+        let mut conn = pool.get().await?;
+        let _: () = conn
+            .pset_ex(key, serde_json::to_vec(record)?, ttl.as_millis() as u64)
+            .await?;
+        Ok(())
+    }
+
+    pub(super) async fn release(pool: &deadpool_redis::Pool, key: &str) -> anyhow::Result<()> {
+        let mut conn = pool.get().await?;
+        let _: () = conn.del(key).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+enum Store {
+    Memory(MemoryStore),
+    #[cfg(feature = "redis")]
+    Redis(deadpool_redis::Pool),
+}
+
+impl Store {
+    /// Marks `key` in progress, or returns its record when already taken.
+    async fn begin(
+        &self,
+        key: &str,
+        fingerprint: &str,
+        lock: Duration,
+    ) -> anyhow::Result<Option<Record>> {
+        match self {
+            Store::Memory(store) => Ok(store.begin(key, fingerprint, lock)),
+            #[cfg(feature = "redis")]
+            Store::Redis(pool) => redis_store::begin(pool, key, fingerprint, lock).await,
+        }
+    }
+
+    async fn complete(&self, key: &str, record: Record, ttl: Duration) -> anyhow::Result<()> {
+        match self {
+            Store::Memory(store) => {
+                store.complete(key, record, ttl);
+                Ok(())
+            }
+            #[cfg(feature = "redis")]
+            Store::Redis(pool) => redis_store::complete(pool, key, &record, ttl).await,
+        }
+    }
+
+    async fn release(&self, key: &str) -> anyhow::Result<()> {
+        match self {
+            Store::Memory(store) => {
+                store.release(key);
+                Ok(())
+            }
+            #[cfg(feature = "redis")]
+            Store::Redis(pool) => redis_store::release(pool, key).await,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Guard {
+    store: Store,
+    header: HeaderName,
+    methods: Vec<String>,
+    paths: Vec<String>,
+    ttl: Duration,
+    lock: Duration,
+    max_body_size: usize,
+    max_response_size: usize,
+}
+
+impl Guard {
+    fn applies(&self, req: &ServiceRequest) -> bool {
+        let method = req.method().as_str();
+        self.methods.iter().any(|m| m.eq_ignore_ascii_case(method))
+            && (self.paths.is_empty()
+                || self
+                    .paths
+                    .iter()
+                    .any(|pattern| path_matches(pattern, request_path(req))))
+    }
+
+    /// Stores the final response, or releases the key so that the request can be retried.
+    async fn finish(&self, key: &str, record: Option<Record>) {
+        let result = match record {
+            Some(record) => self.store.complete(key, record, self.ttl).await,
+            None => self.store.release(key).await,
+        };
+        if let Err(e) = result {
+            logging::warn!("[idempotency] failed to update {}: {}", key, e);
+        }
+    }
+}
+
+/// The in-progress marker of a key, released when dropped before [`finish`](Self::finish), e.g.
+/// when the client disconnects while the handler runs.
+struct Pending {
+    inner: Arc<Guard>,
+    key: Option<String>,
+}
+
+impl Pending {
+    async fn finish(mut self, record: Option<Record>) {
+        if let Some(key) = &self.key {
+            self.inner.finish(key, record).await;
+        }
+        self.key = None;
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            let inner = self.inner.clone();
+            actix_web::rt::spawn(async move { inner.finish(&key, None).await });
+        }
+    }
+}
+
+/// Replays responses of requests repeated with the same `Idempotency-Key`, configured by
+/// `[web.idempotency]`.
+///
+/// Keys are scoped to the current user and bound to a hash of the method, URI and body. A key is
+/// marked in progress before the handler runs: repeats get 409 until the response is stored, and
+/// 422 when the request differs. Server errors, streamed responses and responses larger than
+/// `max_response_size` are not stored so that the client can retry.
+#[derive(Debug, Clone)]
+pub(crate) struct IdempotencyGuard {
+    inner: Arc<Guard>,
+}
+
+impl IdempotencyGuard {
+    pub(crate) fn from_config(
+        config: &Idempotency,
+        #[cfg(feature = "redis")] db_manager: &crate::DbManager,
+    ) -> anyhow::Result<Self> {
+        let store = match config.backend {
+            RateLimitBackend::Memory => Store::Memory(MemoryStore::default()),
+            #[cfg(feature = "redis")]
+            RateLimitBackend::Redis => Store::Redis(match &config.redis {
+                Some(name) => db_manager.with_redis(name)?,
+                None => db_manager.with_redis_default()?,
+            }),
+            #[cfg(not(feature = "redis"))]
+            RateLimitBackend::Redis => anyhow::bail!(
+                "[idempotency] redis backend {:?} requires the `redis` feature",
+                config.redis
+            ),
+        };
+
+        Ok(Self {
+            inner: Arc::new(Guard {
+                store,
+                header: HeaderName::try_from(config.header.as_str())?,
+                methods: config.methods.clone(),
+                paths: config.paths.clone(),
+                ttl: Duration::from_secs(config.ttl_secs),
+                lock: Duration::from_secs(config.lock_secs),
+                max_body_size: config.max_body_size,
+                max_response_size: config.max_response_size,
+            }),
+        })
+    }
+
+    /// Disabled guard, never matches a request.
+    pub(crate) fn disabled() -> Self {
+        Self {
+            inner: Arc::new(Guard {
+                store: Store::Memory(MemoryStore::default()),
+                header: HeaderName::from_static("idempotency-key"),
+                methods: vec![],
+                paths: vec![],
+                ttl: Duration::ZERO,
+                lock: Duration::ZERO,
+                max_body_size: 0,
+                max_response_size: 0,
+            }),
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for IdempotencyGuard
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = IdempotencyMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(IdempotencyMiddleware {
+            service: Rc::new(service),
+            inner: self.inner.clone(),
+        }))
+    }
+}
+
+pub(crate) struct IdempotencyMiddleware<S> {
+    service: Rc<S>,
+    inner: Arc<Guard>,
+}
+
+impl<S, B> Service<ServiceRequest> for IdempotencyMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let inner = self.inner.clone();
+
+        Box::pin(async move {
+            let key = match req.headers().get(&inner.header) {
+                Some(key) if inner.applies(&req) => key.to_str().ok().map(str::to_string),
+                _ => return service.call(req).await.map(|res| res.map_into_left_body()),
+            };
+            let Some(key) = key.filter(|k| !k.is_empty() && k.len() <= MAX_KEY_LEN) else {
+                let res = reject(StatusCode::BAD_REQUEST, "invalid idempotency key");
+                return Ok(req.into_response(res).map_into_right_body());
+            };
+
+            let body = match read_body(&mut req, inner.max_body_size).await {
+                Ok(body) => body,
+                Err(e) => return Ok(req.into_response(e.error_response()).map_into_right_body()),
+            };
+            let fingerprint = fingerprint(&req, &body);
+            req.set_payload(Payload::from(body));
+
+            let scope = CurrentUser::resolve(req.request())
+                .map(|user| format!("user:{}", user.0))
+                .unwrap_or_else(|| String::from("anonymous"));
+            let key = format!("jieto:idem:{}:{}", scope, key);
+
+            let existing = match inner.store.begin(&key, &fingerprint, inner.lock).await {
+                Ok(existing) => existing,
+                Err(e) => {
+                    // 存储不可用时直接处理请求，与限流一致
+                    logging::warn!("[idempotency] check failed for {}: {}", key, e);
+                    return service.call(req).await.map(|res| res.map_into_left_body());
+                }
+            };
+            if let Some(record) = existing {
+                let res = if record.fingerprint != fingerprint {
+                    reject(
+                        StatusCode::UNPROCESSABLE_ENTITY,
+                        "idempotency key was used for a different request",
+                    )
+                } else if let Some(response) = &record.response {
+                    logging::debug!("[idempotency] replaying {}", key);
+                    response.to_response()
+                } else {
+                    reject(
+                        StatusCode::CONFLICT,
+                        "a request with this idempotency key is in progress",
+                    )
+                };
+                return Ok(req.into_response(res).map_into_right_body());
+            }
+
+            let pending = Pending {
+                inner: inner.clone(),
+                key: Some(key.clone()),
+            };
+            let res = match service.call(req).await {
+                Ok(res) => res,
+                Err(e) => {
+                    pending.finish(None).await;
+                    return Err(e);
+                }
+            };
+            if res.status().is_server_error() {
+                pending.finish(None).await;
+                return Ok(res.map_into_left_body());
+            }
+            let storable = match res.response().body().size() {
+                BodySize::None => true,
+                BodySize::Sized(size) => size <= inner.max_response_size as u64,
+                BodySize::Stream => false,
+            };
+            if !storable {
+                logging::debug!("[idempotency] response of {} is not stored", key);
+                pending.finish(None).await;
+                return Ok(res.map_into_left_body());
+            }
+
+            let (req, res) = res.into_parts();
+            let (head, body) = res.into_parts();
+            let body = match actix_web::body::to_bytes(body).await {
+                Ok(body) => body,
+                Err(e) => {
+                    pending.finish(None).await;
+                    return Err(actix_web::error::ErrorInternalServerError(e.into()));
+                }
+            };
+
+            let headers = head
+                .headers()
+                .iter()
+                .filter(|(name, _)| {
+                    ![
+                        CONTENT_LENGTH,
+                        TRANSFER_ENCODING,
+                        CONNECTION,
+                        DATE,
+                        SET_COOKIE,
+                    ]
+                    .contains(name)
+                })
+                .map(|(name, value)| (name.to_string(), value.as_bytes().to_vec()))
+                .collect();
+            let record = Record {
+                fingerprint,
+                response: Some(StoredResponse {
+                    status: head.status().as_u16(),
+                    headers,
+                    body: body.to_vec(),
+                }),
+            };
+            pending.finish(Some(record)).await;
+
+            let res = head.set_body(BoxBody::new(body));
+            Ok(ServiceResponse::new(req, res).map_into_right_body())
+        })
+    }
+}
+
+fn reject(status: StatusCode, msg: &str) -> HttpResponse {
+    WebError::Http(status, msg.to_string()).error_response()
+}
+
+/// Hash of the method, URI and body a key is bound to.
+fn fingerprint(req: &ServiceRequest, body: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(req.method().as_str());
+    hasher.update(b" ");
+    hasher.update(req.uri().to_string());
+    hasher.update(b"\n");
+    hasher.update(body);
+    format!("{:x}", hasher.finalize())
+}
+
+async fn read_body(req: &mut ServiceRequest, limit: usize) -> Result<Bytes, WebError> {
+    let mut payload = req.take_payload();
+    let mut body = BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(|e| match e {
+            // 外层的 body 限制已生效
+            PayloadError::Overflow => WebError::Http(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                String::from("payload is larger than allowed"),
+            ),
+            e => WebError::Web(e.into()),
+        })?;
+        if body.len() + chunk.len() > limit {
+            return Err(WebError::Http(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("idempotent request body is larger than allowed (limit: {limit} bytes)"),
+            ));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body.freeze())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{App, test, web};
+    use futures_util::stream;
+    use std::sync::atomic::AtomicUsize;
+
+    fn guard() -> IdempotencyGuard {
+        IdempotencyGuard {
+            inner: Arc::new(Guard {
+                store: Store::Memory(MemoryStore::default()),
+                header: HeaderName::from_static("idempotency-key"),
+                methods: vec![String::from("POST")],
+                paths: vec![],
+                ttl: Duration::from_secs(60),
+                lock: Duration::from_secs(60),
+                max_body_size: 1024,
+                max_response_size: 1024,
+            }),
+        }
+    }
+
+    fn stored_keys(guard: &IdempotencyGuard) -> usize {
+        match &guard.inner.store {
+            Store::Memory(store) => store.entries.lock().unwrap().len(),
+            #[cfg(feature = "redis")]
+            Store::Redis(_) => unreachable!(),
+        }
+    }
+
+    fn request() -> test::TestRequest {
+        test::TestRequest::post()
+            .uri("/")
+            .insert_header(("Idempotency-Key", "k1"))
+    }
+
+    #[actix_web::test]
+    async fn dropped_requests_release_the_key() {
+        let guard = guard();
+        let app = test::init_service(
+            App::new()
+                .wrap(guard.clone())
+                .route("/", web::post().to(std::future::pending::<HttpResponse>)),
+        )
+        .await;
+
+        let call =
+            tokio::time::timeout(Duration::from_millis(20), app.call(request().to_request()));
+        assert!(call.await.is_err());
+        tokio::task::yield_now().await;
+        assert_eq!(stored_keys(&guard), 0);
+    }
+
+    #[actix_web::test]
+    async fn streamed_responses_are_not_stored() {
+        let guard = guard();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let handler_calls = calls.clone();
+        let app = test::init_service(App::new().wrap(guard.clone()).route(
+            "/",
+            web::post().to(move || {
+                handler_calls.fetch_add(1, Ordering::Relaxed);
+                let chunks = stream::iter([Ok::<_, Error>(Bytes::from_static(b"chunk"))]);
+                async move { HttpResponse::Ok().streaming(chunks) }
+            }),
+        ))
+        .await;
+
+        for _ in 0..2 {
+            let res = test::call_service(&app, request().to_request()).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(test::read_body(res).await, Bytes::from_static(b"chunk"));
+        }
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+        assert_eq!(stored_keys(&guard), 0);
+    }
+
+    #[actix_web::test]
+    async fn repeated_requests_replay_the_stored_response() {
+        let guard = guard();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let handler_calls = calls.clone();
+        let app = test::init_service(App::new().wrap(guard.clone()).route(
+            "/",
+            web::post().to(move || {
+                let n = handler_calls.fetch_add(1, Ordering::Relaxed);
+                async move {
+                    HttpResponse::Created()
+                        .insert_header(("x-order", n.to_string()))
+                        .body("created")
+                }
+            }),
+        ))
+        .await;
+
+        let first = test::call_service(&app, request().set_payload("a").to_request()).await;
+        assert_eq!(first.status(), StatusCode::CREATED);
+        assert!(first.headers().get(IDEMPOTENT_REPLAYED).is_none());
+
+        let res = test::call_service(&app, request().set_payload("a").to_request()).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(res.headers().get(IDEMPOTENT_REPLAYED).unwrap(), "true");
+        assert_eq!(res.headers().get("x-order").unwrap(), "0");
+        assert_eq!(test::read_body(res).await, Bytes::from_static(b"created"));
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+    }
+
+    #[actix_web::test]
+    async fn key_in_progress_is_rejected() {
+        let notify = Arc::new(tokio::sync::Notify::new());
+        let handler_notify = notify.clone();
+        let app = test::init_service(App::new().wrap(guard()).route(
+            "/",
+            web::post().to(move || {
+                let notify = handler_notify.clone();
+                async move {
+                    notify.notified().await;
+                    HttpResponse::Ok().finish()
+                }
+            }),
+        ))
+        .await;
+
+        // 第一个请求停在处理函数中时发送第二个请求
+        let first = app.call(request().to_request());
+        let second = async {
+            let res = test::call_service(&app, request().to_request()).await;
+            notify.notify_one();
+            res
+        };
+        let (first, second) = tokio::join!(first, second);
+        assert_eq!(first.unwrap().status(), StatusCode::OK);
+        assert_eq!(second.status(), StatusCode::CONFLICT);
+    }
+
+    #[actix_web::test]
+    async fn key_reused_for_another_request_is_rejected() {
+        let app = test::init_service(
+            App::new()
+                .wrap(guard())
+                .route("/", web::post().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let res = test::call_service(&app, request().set_payload("a").to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = test::call_service(&app, request().set_payload("b").to_request()).await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[actix_web::test]
+    async fn payload_overflow_is_payload_too_large() {
+        let mut req = request().to_srv_request();
+        let chunks = stream::iter([Err::<Bytes, _>(PayloadError::Overflow)]);
+        req.set_payload(Payload::Stream {
+            payload: Box::pin(chunks),
+        });
+
+        let err = read_body(&mut req, 1024).await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
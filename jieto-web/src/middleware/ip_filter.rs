@@ -0,0 +1,284 @@
+use crate::config::IpFilter;
+use crate::error::WebError;
+use crate::extract::client_ip;
+use crate::logging;
+use crate::middleware::{path_matches, request_path};
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform, forward_ready};
+use actix_web::http::StatusCode;
+use actix_web::{Error, ResponseError};
+use futures_util::future::LocalBoxFuture;
+use ipnet::IpNet;
+use jieto_config::error::ConfigError;
+use std::future::{Ready, ready};
+use std::net::IpAddr;
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+/// Whether `ip` passes an allow and a deny list. Unknown addresses pass deny lists only.
+fn permits(allow: &[IpNet], deny: &[IpNet], ip: Option<IpAddr>) -> bool {
+    let contains =
+        |networks: &[IpNet]| ip.is_some_and(|ip| networks.iter().any(|n| n.contains(&ip)));
+    !contains(deny) && (allow.is_empty() || contains(allow))
+}
+
+/// Enforces `[web.ip_filter]` with the address resolved by [`ClientIp`](crate::ClientIp).
+///
+/// A request must pass the global lists and the lists of every route whose pattern matches its
+/// path, otherwise it is rejected with 403. The rules are replaced when the configuration file
+/// changes, see [`IpAccessControl::watch`].
+#[derive(Clone)]
+pub(crate) struct IpAccessControl {
+    rules: Arc<RwLock<Arc<IpFilter>>>,
+}
+
+impl IpAccessControl {
+    pub(crate) fn new(config: IpFilter) -> Self {
+        Self {
+            rules: Arc::new(RwLock::new(Arc::new(config))),
+        }
+    }
+
+    fn rules(&self) -> Arc<IpFilter> {
+        // unwrap: the lock is never held across a panic
+        self.rules.read().unwrap().clone()
+    }
+
+    fn permits(rules: &IpFilter, path: &str, ip: Option<IpAddr>) -> bool {
+        permits(&rules.allow, &rules.deny, ip)
+            && rules
+                .routes
+                .iter()
+                .filter(|route| path_matches(&route.pattern, path))
+                .all(|route| permits(&route.allow, &route.deny, ip))
+    }
+
+    /// Re-reads `[web.ip_filter]` every `reload_secs` once the configuration file or its profile
+    /// overlay was modified. Invalid files are logged and leave the current rules in place.
+    ///
+    /// Nothing is watched when the filter is disabled and `reload_secs` is not set.
+    pub(crate) fn watch(&self, config_path: String) {
+        let rules = self.rules();
+        let reload_secs = match rules.reload_secs {
+            Some(secs) => secs,
+            None if rules.enabled => 10,
+            None => 0,
+        };
+        if reload_secs == 0 {
+            return;
+        }
+        let control = self.clone();
+        tokio::spawn(async move {
+            let mut last_modified = modified(&config_path);
+            let mut interval = tokio::time::interval(Duration::from_secs(reload_secs));
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let current = modified(&config_path);
+                if current == last_modified {
+                    continue;
+                }
+                last_modified = current;
+                let rules = match jieto_config::section(&config_path, "web.ip_filter").await {
+                    Err(ConfigError::MissingSection(_)) => Ok(IpFilter::default()),
+                    rules => rules,
+                };
+                match rules {
+                    Ok(rules) => {
+                        logging::info!(
+                            "[ip_filter] reloaded from '{}' (enabled: {}, {} route rules)",
+                            config_path,
+                            rules.enabled,
+                            rules.routes.len()
+                        );
+                        // unwrap: the lock is never held across a panic
+                        *control.rules.write().unwrap() = Arc::new(rules);
+                    }
+                    Err(e) => logging::warn!(
+                        "[ip_filter] keeping the current rules, failed to reload '{}': {}",
+                        config_path,
+                        e
+                    ),
+                }
+            }
+        });
+    }
+}
+
+/// Modification times of the configuration file and of the active profile overlay.
+fn modified(config_path: &str) -> Vec<Option<SystemTime>> {
+    let mut paths = vec![config_path.to_string()];
+    if let Some(profile) = jieto_config::profile() {
+        paths.push(jieto_config::profile_path(config_path, &profile));
+    }
+    paths
+        .iter()
+        .map(|path| Path::new(path).metadata().and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+impl<S, B> Transform<S, ServiceRequest> for IpAccessControl
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = IpAccessControlMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(IpAccessControlMiddleware {
+            service: Rc::new(service),
+            control: self.clone(),
+        }))
+    }
+}
+
+pub(crate) struct IpAccessControlMiddleware<S> {
+    service: Rc<S>,
+    control: IpAccessControl,
+}
+
+impl<S, B> Service<ServiceRequest> for IpAccessControlMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let rules = self.control.rules();
+        if rules.enabled {
+            let ip = client_ip(req.request());
+            if !IpAccessControl::permits(&rules, request_path(&req), ip) {
+                logging::debug!(
+                    "[ip_filter] rejected {} from {}",
+                    req.path(),
+                    ip.map_or_else(|| String::from("-"), |ip| ip.to_string())
+                );
+                let res = WebError::Http(StatusCode::FORBIDDEN, String::from("access denied"))
+                    .error_response();
+                return Box::pin(ready(Ok(req.into_response(res).map_into_right_body())));
+            }
+        }
+
+        let service = self.service.clone();
+        Box::pin(async move { service.call(req).await.map(|res| res.map_into_left_body()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{TestRequest, call_service, init_service};
+    use actix_web::{App, HttpResponse, web};
+
+    fn networks(list: &[&str]) -> Vec<IpNet> {
+        list.iter().map(|n| n.parse().unwrap()).collect()
+    }
+
+    fn ip(ip: &str) -> Option<IpAddr> {
+        Some(ip.parse().unwrap())
+    }
+
+    fn filter(toml: &str) -> IpFilter {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn permits_lists() {
+        let allow = networks(&["10.0.0.0/8"]);
+        let deny = networks(&["10.0.0.1/32"]);
+        assert!(permits(&allow, &deny, ip("10.1.2.3")));
+        assert!(!permits(&allow, &deny, ip("10.0.0.1")));
+        assert!(!permits(&allow, &deny, ip("192.168.0.1")));
+
+        // 允许列表为空时放行所有地址
+        assert!(permits(&[], &deny, ip("192.168.0.1")));
+        assert!(!permits(&[], &deny, ip("10.0.0.1")));
+
+        // 无法解析地址时只能通过拒绝列表
+        assert!(permits(&[], &deny, None));
+        assert!(!permits(&allow, &[], None));
+    }
+
+    #[actix_web::test]
+    async fn route_rules_apply_on_top_of_the_global_lists() {
+        let control = IpAccessControl::new(filter(
+            r#"
+            enabled = true
+            allow = ["10.0.0.0/8"]
+
+            [[routes]]
+            pattern = "/admin/**"
+            allow = ["10.1.0.0/16"]
+
+            [[routes]]
+            pattern = "/admin/users"
+            deny = ["10.1.0.1"]
+            "#,
+        ));
+        let app = init_service(
+            App::new()
+                .wrap(control)
+                .default_service(web::to(HttpResponse::Ok)),
+        )
+        .await;
+
+        for (path, peer, status) in [
+            ("/", "10.2.0.1", 200),
+            ("/", "192.168.0.1", 403),
+            ("/admin/settings", "10.1.0.1", 200),
+            ("/admin/settings", "10.2.0.1", 403),
+            ("/admin/users", "10.1.0.2", 200),
+            ("/admin/users", "10.1.0.1", 403),
+        ] {
+            let req = TestRequest::get()
+                .uri(path)
+                .peer_addr(format!("{peer}:1234").parse().unwrap())
+                .to_request();
+            assert_eq!(
+                call_service(&app, req).await.status(),
+                status,
+                "{path} {peer}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn watch_replaces_rules_and_keeps_them_on_invalid_files() {
+        let path =
+            std::env::temp_dir().join(format!("jieto-ip-filter-{}.toml", std::process::id()));
+        let config_path = path.to_string_lossy().into_owned();
+        let write = |content: &str| std::fs::write(&path, content).unwrap();
+        write("[web.ip_filter]\nenabled = true\nreload_secs = 1\n");
+
+        let control = IpAccessControl::new(filter("enabled = true\nreload_secs = 1"));
+        control.watch(config_path);
+        // 等待监视任务记录初始修改时间
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let deny = |control: &IpAccessControl| control.rules().deny.clone();
+
+        // 修改后最多两个检查周期内生效
+        write("[web.ip_filter]\nenabled = true\nreload_secs = 1\ndeny = [\"10.0.0.1\"]\n");
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+        while deny(&control).is_empty() && tokio::time::Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        assert_eq!(deny(&control), networks(&["10.0.0.1/32"]));
+
+        write("[web.ip_filter]\nenabled = true\ndeny = [\"not an address\"]\n");
+        tokio::time::sleep(Duration::from_millis(2500)).await;
+        assert_eq!(deny(&control), networks(&["10.0.0.1/32"]));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,102 @@
+pub(crate) mod access_log;
+pub(crate) mod body_limit;
+pub(crate) mod catch_panic;
+pub(crate) mod compression;
+#[cfg(feature = "idempotency")]
+pub(crate) mod idempotency;
+pub(crate) mod ip_filter;
+pub(crate) mod rate_limit;
+pub(crate) mod security_headers;
+pub(crate) mod timeout;
+#[cfg(feature = "tracing")]
+pub(crate) mod trace;
+#[cfg(any(feature = "mysql", feature = "postgres", feature = "sqlite"))]
+pub(crate) mod transaction;
+
+use actix_web::dev::ServiceRequest;
+
+/// The path route patterns are matched against.
+///
+/// This is the percent-decoded path the router resolves handlers from, so that e.g.
+/// `/%6Danagement/env` cannot reach the `/management` scope past a `/management/**` rule.
+pub(crate) fn request_path(req: &ServiceRequest) -> &str {
+    req.match_info().as_str()
+}
+
+/// Matches a request path against a route pattern.
+///
+/// Segments are compared one by one, `*` matches any characters within a segment and a trailing
+/// `**` matches any remaining segments, e.g. `/api/*/items` or `/admin/**`.
+pub(crate) fn path_matches(pattern: &str, path: &str) -> bool {
+    let mut pattern_segments = pattern.trim_matches('/').split('/');
+    let mut path_segments = path.trim_matches('/').split('/');
+
+    loop {
+        match (pattern_segments.next(), path_segments.next()) {
+            (Some("**"), _) => return true,
+            (Some(p), Some(s)) if segment_matches(p, s) => {}
+            (None, None) => return true,
+            _ => return false,
+        }
+    }
+}
+
+fn segment_matches(pattern: &str, segment: &str) -> bool {
+    if !pattern.contains('*') {
+        return pattern == segment;
+    }
+
+    let mut parts = pattern.split('*');
+    // unwrap: split always yields at least one part
+    let first = parts.next().unwrap();
+    let Some(mut rest) = segment.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts: Vec<&str> = parts.collect();
+    let last = parts.pop().unwrap_or_default();
+    for part in parts {
+        match rest.find(part) {
+            Some(idx) => rest = &rest[idx + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn matches(pattern: &str, uri: &str) -> bool {
+        let req = TestRequest::with_uri(uri).to_srv_request();
+        path_matches(pattern, request_path(&req))
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(path_matches("/api/*/items", "/api/orders/items"));
+        assert!(path_matches("/api/v*-beta/**", "/api/v2-beta/a/b"));
+        assert!(path_matches("/admin/**", "/admin"));
+        assert!(!path_matches("/api/*/items", "/api/orders/items/1"));
+        assert!(!path_matches("/admin/**", "/administrator"));
+    }
+
+    #[test]
+    fn encoded_paths_are_decoded() {
+        assert!(matches("/management/**", "/%6Danagement/env"));
+        assert!(matches("/management/**", "/%6d%61nagement/loggers"));
+        assert!(matches("/api/*/items", "/api/%6Frders/items"));
+        // 编码的分隔符不会被路由拆分，也不拆分段
+        assert!(!matches("/a/b", "/a%2Fb"));
+    }
+
+    #[test]
+    fn doubled_and_trailing_slashes() {
+        assert!(matches("/management/**", "//management/env"));
+        assert!(matches("/management/**", "/management//env"));
+        assert!(matches("/management/**", "/management/"));
+        assert!(matches("/admin", "/admin/"));
+        assert!(matches("/api/*/items", "/api/orders/items/"));
+    }
+}
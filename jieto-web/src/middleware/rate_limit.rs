@@ -0,0 +1,668 @@
+use crate::config::{LimitPolicy, RateLimit, RateLimitAlgorithm, RateLimitBackend};
+use crate::error::WebError;
+use crate::extract::{CurrentUser, client_ip};
+use crate::logging;
+use crate::middleware::{path_matches, request_path};
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform, forward_ready};
+use actix_web::http::StatusCode;
+use actix_web::http::header::{HeaderName, HeaderValue, RETRY_AFTER};
+use actix_web::{Error, HttpRequest, ResponseError};
+use futures_util::future::LocalBoxFuture;
+use std::collections::HashMap;
+use std::future::{Ready, ready};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+const X_RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("x-ratelimit-limit");
+const X_RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("x-ratelimit-remaining");
+const X_RATELIMIT_RESET: HeaderName = HeaderName::from_static("x-ratelimit-reset");
+
+/// Memory store entries are swept every this many checks.
+const SWEEP_EVERY: u64 = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum KeyBy {
+    Ip,
+    User,
+    Header(String),
+}
+
+impl KeyBy {
+    fn parse(key: &str) -> anyhow::Result<Self> {
+        match key {
+            "ip" => Ok(KeyBy::Ip),
+            "user" => Ok(KeyBy::User),
+            _ => match key.strip_prefix("header:") {
+                Some(name) if !name.is_empty() => Ok(KeyBy::Header(name.to_lowercase())),
+                _ => Err(anyhow::anyhow!("[rate_limit] invalid key '{}'", key)),
+            },
+        }
+    }
+
+    /// Users and headers fall back to the client address when absent.
+    fn identify(&self, req: &HttpRequest) -> String {
+        let by_ip = || {
+            client_ip(req)
+                .map(|ip| format!("ip:{ip}"))
+                .unwrap_or_else(|| String::from("ip:unknown"))
+        };
+        match self {
+            KeyBy::Ip => by_ip(),
+            KeyBy::User => CurrentUser::resolve(req)
+                .map(|user| format!("user:{}", user.0))
+                .unwrap_or_else(by_ip),
+            KeyBy::Header(name) => req
+                .headers()
+                .get(name.as_str())
+                .and_then(|v| v.to_str().ok())
+                .map(|v| format!("header:{v}"))
+                .unwrap_or_else(by_ip),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Policy {
+    /// Part of the bucket keys, so that changing a policy starts new buckets.
+    name: String,
+    algorithm: RateLimitAlgorithm,
+    capacity: u64,
+    period: Duration,
+    key: KeyBy,
+}
+
+impl TryFrom<&LimitPolicy> for Policy {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &LimitPolicy) -> Result<Self, Self::Error> {
+        if value.capacity == 0 || value.period_secs == 0 {
+            anyhow::bail!("[rate_limit] capacity and period_secs must be greater than zero");
+        }
+        let algorithm = match value.algorithm {
+            RateLimitAlgorithm::TokenBucket => "token_bucket",
+            RateLimitAlgorithm::SlidingWindow => "sliding_window",
+        };
+        Ok(Policy {
+            name: format!(
+                "{}:{}/{}s:{}",
+                algorithm, value.capacity, value.period_secs, value.key
+            ),
+            algorithm: value.algorithm,
+            capacity: value.capacity,
+            period: Duration::from_secs(value.period_secs),
+            key: KeyBy::parse(&value.key)?,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Rule {
+    pattern: String,
+    methods: Vec<String>,
+    policy: Policy,
+}
+
+/// Outcome of a single check against a policy.
+#[derive(Debug)]
+struct Decision {
+    allowed: bool,
+    limit: u64,
+    remaining: u64,
+    /// Time until the client is back to full quota.
+    reset: Duration,
+    /// Time until the next request may be accepted.
+    retry_after: Duration,
+}
+
+#[derive(Debug)]
+enum BucketState {
+    Tokens {
+        tokens: f64,
+        updated: Instant,
+    },
+    Window {
+        start: Instant,
+        previous: u64,
+        current: u64,
+    },
+}
+
+#[derive(Debug, Default)]
+struct MemoryStore {
+    /// Bucket state with the instant after which it can be dropped.
+    entries: Mutex<HashMap<String, (BucketState, Instant)>>,
+    checks: AtomicU64,
+}
+
+impl MemoryStore {
+    fn check(&self, key: &str, policy: &Policy) -> Decision {
+        let now = Instant::now();
+        // unwrap: the lock is never held across a panic
+        let mut entries = self.entries.lock().unwrap();
+
+        if self
+            .checks
+            .fetch_add(1, Ordering::Relaxed)
+            .is_multiple_of(SWEEP_EVERY)
+        {
+            entries.retain(|_, (_, expires)| *expires > now);
+        }
+
+        let (state, expires) = entries.entry(key.to_string()).or_insert_with(|| {
+            let state = match policy.algorithm {
+                RateLimitAlgorithm::TokenBucket => BucketState::Tokens {
+                    tokens: policy.capacity as f64,
+                    updated: now,
+                },
+                RateLimitAlgorithm::SlidingWindow => BucketState::Window {
+                    start: now,
+                    previous: 0,
+                    current: 0,
+                },
+            };
+            (state, now)
+        });
+        // 两个周期无请求后状态等同于初始值，可以清理
+        *expires = now + policy.period * 2;
+
+        match state {
+            BucketState::Tokens { tokens, updated } => {
+                let rate = policy.capacity as f64 / policy.period.as_secs_f64();
+                *tokens = (*tokens + now.duration_since(*updated).as_secs_f64() * rate)
+                    .min(policy.capacity as f64);
+                *updated = now;
+                token_bucket_decision(tokens, policy.capacity, rate)
+            }
+            BucketState::Window {
+                start,
+                previous,
+                current,
+            } => {
+                let elapsed = now.duration_since(*start);
+                if elapsed >= policy.period * 2 {
+                    *start = now;
+                    *previous = 0;
+                    *current = 0;
+                } else if elapsed >= policy.period {
+                    *start += policy.period;
+                    *previous = *current;
+                    *current = 0;
+                }
+                let into_window = now.duration_since(*start);
+                sliding_window_decision(*previous, current, policy, into_window)
+            }
+        }
+    }
+}
+
+fn token_bucket_decision(tokens: &mut f64, capacity: u64, rate: f64) -> Decision {
+    let allowed = *tokens >= 1.0;
+    if allowed {
+        *tokens -= 1.0;
+    }
+    Decision {
+        allowed,
+        limit: capacity,
+        remaining: tokens.floor() as u64,
+        reset: Duration::from_secs_f64((capacity as f64 - *tokens) / rate),
+        retry_after: Duration::from_secs_f64(((1.0 - *tokens) / rate).max(0.0)),
+    }
+}
+
+/// Sliding window counter: the previous window's count is weighted by how much of it still
+/// overlaps the sliding window.
+fn sliding_window_decision(
+    previous: u64,
+    current: &mut u64,
+    policy: &Policy,
+    into_window: Duration,
+) -> Decision {
+    let overlap = 1.0 - into_window.as_secs_f64() / policy.period.as_secs_f64();
+    let estimate = previous as f64 * overlap + *current as f64;
+    let allowed = estimate + 1.0 <= policy.capacity as f64;
+    if allowed {
+        *current += 1;
+    }
+    let used = estimate + if allowed { 1.0 } else { 0.0 };
+    let until_next_window = policy.period.saturating_sub(into_window);
+    Decision {
+        allowed,
+        limit: policy.capacity,
+        remaining: (policy.capacity as f64 - used).max(0.0).floor() as u64,
+        reset: until_next_window,
+        retry_after: until_next_window,
+    }
+}
+
+#[cfg(feature = "redis")]
+mod redis_store {
+    use super::{Decision, Policy};
+    use crate::config::RateLimitAlgorithm;
+    use deadpool_redis::redis::Script;
+    use std::sync::LazyLock;
+    use std::time::Duration;
+
+    static TOKEN_BUCKET: LazyLock<Script> = LazyLock::new(|| {
+        Script::new(
+            r#"
+local t = redis.call('TIME')
+local now = tonumber(t[1]) * 1000 + math.floor(tonumber(t[2]) / 1000)
+local capacity = tonumber(ARGV[1])
+local period = tonumber(ARGV[2])
+local rate = capacity / period
+local state = redis.call('HMGET', KEYS[1], 'tokens', 'ts')
+local tokens = tonumber(state[1]) or capacity
+local ts = tonumber(state[2]) or now
+tokens = math.min(capacity, tokens + math.max(0, now - ts) * rate)
+local allowed = 0
+if tokens >= 1 then
+  tokens = tokens - 1
+  allowed = 1
+end
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'ts', now)
+redis.call('PEXPIRE', KEYS[1], period * 2)
+return {allowed, tostring(tokens)}
+"#,
+        )
+    });
+
+    static SLIDING_WINDOW: LazyLock<Script> = LazyLock::new(|| {
+        Script::new(
+            r#"
+local t = redis.call('TIME')
+local now = tonumber(t[1]) * 1000 + math.floor(tonumber(t[2]) / 1000)
+local capacity = tonumber(ARGV[1])
+local period = tonumber(ARGV[2])
+local start = now - (now % period)
+local current_key = KEYS[1] .. ':' .. start
+local previous = tonumber(redis.call('GET', KEYS[1] .. ':' .. (start - period)) or '0')
+local current = tonumber(redis.call('GET', current_key) or '0')
+local estimate = previous * (1 - (now - start) / period) + current
+local allowed = 0
+if estimate + 1 <= capacity then
+  redis.call('INCR', current_key)
+  redis.call('PEXPIRE', current_key, period * 2)
+  estimate = estimate + 1
+  allowed = 1
+end
+return {allowed, tostring(estimate), start + period - now}
+"#,
+        )
+    });
+
+    pub(super) async fn check(
+        pool: &deadpool_redis::Pool,
+        key: &str,
+        policy: &Policy,
+    ) -> anyhow::Result<Decision> {
+        let mut conn = pool.get().await?;
+        let period_ms = policy.period.as_millis() as u64;
+        let capacity = policy.capacity;
+
+        match policy.algorithm {
+            RateLimitAlgorithm::TokenBucket => {
+                let (allowed, tokens): (u8, String) = TOKEN_BUCKET
+                    .key(key)
+                    .arg(capacity)
+                    .arg(period_ms)
+                    .invoke_async(&mut conn)
+                    .await?;
+                let tokens: f64 = tokens.parse()?;
+                let rate = capacity as f64 / policy.period.as_secs_f64();
+                Ok(Decision {
+                    allowed: allowed == 1,
+                    limit: capacity,
+                    remaining: tokens.floor() as u64,
+                    reset: Duration::from_secs_f64((capacity as f64 - tokens) / rate),
+                    retry_after: Duration::from_secs_f64(((1.0 - tokens) / rate).max(0.0)),
+                })
+            }
+            RateLimitAlgorithm::SlidingWindow => {
+                let (allowed, estimate, until_next): (u8, String, u64) = SLIDING_WINDOW
+                    .key(key)
+                    .arg(capacity)
+                    .arg(period_ms)
+                    .invoke_async(&mut conn)
+                    .await?;
+                let estimate: f64 = estimate.parse()?;
+                let until_next = Duration::from_millis(until_next);
+                Ok(Decision {
+                    allowed: allowed == 1,
+                    limit: capacity,
+                    remaining: (capacity as f64 - estimate).max(0.0).floor() as u64,
+                    reset: until_next,
+                    retry_after: until_next,
+                })
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Store {
+    Memory(MemoryStore),
+    #[cfg(feature = "redis")]
+    Redis(deadpool_redis::Pool),
+}
+
+#[derive(Debug)]
+struct Limiter {
+    store: Store,
+    global: Option<Policy>,
+    rules: Vec<Rule>,
+}
+
+impl Limiter {
+    /// The first matching rule wins, requests matching no rule use the global policy.
+    ///
+    /// Buckets are scoped by the rule pattern and the policy, so that they survive reordering the
+    /// rules and are not shared with a different rule after a configuration change.
+    fn policy_for(&self, req: &ServiceRequest) -> Option<(String, &Policy)> {
+        let path = request_path(req);
+        let method = req.method().as_str();
+        self.rules
+            .iter()
+            .find(|rule| {
+                path_matches(&rule.pattern, path)
+                    && (rule.methods.is_empty()
+                        || rule.methods.iter().any(|m| m.eq_ignore_ascii_case(method)))
+            })
+            .map(|rule| {
+                (
+                    format!("{}:{}", rule.pattern, rule.policy.name),
+                    &rule.policy,
+                )
+            })
+            .or_else(|| {
+                self.global
+                    .as_ref()
+                    .map(|policy| (format!("global:{}", policy.name), policy))
+            })
+    }
+
+    async fn check(&self, key: &str, policy: &Policy) -> anyhow::Result<Decision> {
+        match &self.store {
+            Store::Memory(store) => Ok(store.check(key, policy)),
+            #[cfg(feature = "redis")]
+            Store::Redis(pool) => redis_store::check(pool, key, policy).await,
+        }
+    }
+}
+
+/// Rate limiting middleware configured by `[web.rate_limit]`.
+#[derive(Debug, Clone)]
+pub(crate) struct RateLimiter {
+    inner: Arc<Limiter>,
+}
+
+impl RateLimiter {
+    pub(crate) fn from_config(
+        config: &RateLimit,
+        #[cfg(feature = "redis")] db_manager: &crate::DbManager,
+    ) -> anyhow::Result<Self> {
+        let store = match config.backend {
+            RateLimitBackend::Memory => Store::Memory(MemoryStore::default()),
+            #[cfg(feature = "redis")]
+            RateLimitBackend::Redis => Store::Redis(match &config.redis {
+                Some(name) => db_manager.with_redis(name)?,
+                None => db_manager.with_redis_default()?,
+            }),
+            #[cfg(not(feature = "redis"))]
+            RateLimitBackend::Redis => anyhow::bail!(
+                "[rate_limit] redis backend {:?} requires the `redis` feature",
+                config.redis
+            ),
+        };
+
+        let rules = config
+            .rules
+            .iter()
+            .map(|rule| {
+                Ok(Rule {
+                    pattern: rule.pattern.clone(),
+                    methods: rule.methods.clone(),
+                    policy: Policy::try_from(&rule.policy)?,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Ok(Self {
+            inner: Arc::new(Limiter {
+                store,
+                global: config.global.as_ref().map(Policy::try_from).transpose()?,
+                rules,
+            }),
+        })
+    }
+
+    /// Disabled limiter, never matches a request.
+    pub(crate) fn disabled() -> Self {
+        Self {
+            inner: Arc::new(Limiter {
+                store: Store::Memory(MemoryStore::default()),
+                global: None,
+                rules: vec![],
+            }),
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimiter
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = RateLimiterMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimiterMiddleware {
+            service: Rc::new(service),
+            inner: self.inner.clone(),
+        }))
+    }
+}
+
+pub(crate) struct RateLimiterMiddleware<S> {
+    service: Rc<S>,
+    inner: Arc<Limiter>,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimiterMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let inner = self.inner.clone();
+
+        Box::pin(async move {
+            let Some((scope, policy)) = inner.policy_for(&req) else {
+                return service.call(req).await.map(|res| res.map_into_left_body());
+            };
+
+            let key = format!("jieto:rl:{}:{}", scope, policy.key.identify(req.request()));
+            let decision = match inner.check(&key, policy).await {
+                Ok(decision) => decision,
+                Err(e) => {
+                    // 存储不可用时放行，避免限流组件拖垮业务
+                    logging::warn!("[rate_limit] check failed for {}: {}", key, e);
+                    return service.call(req).await.map(|res| res.map_into_left_body());
+                }
+            };
+
+            if !decision.allowed {
+                logging::debug!("[rate_limit] rejected {}", key);
+                let mut res = WebError::Http(
+                    StatusCode::TOO_MANY_REQUESTS,
+                    String::from("too many requests"),
+                )
+                .error_response();
+                let retry_after = decision.retry_after.as_secs_f64().ceil().max(1.0) as u64;
+                res.headers_mut()
+                    .insert(RETRY_AFTER, HeaderValue::from(retry_after));
+                insert_headers(res.headers_mut(), &decision);
+                return Ok(req.into_response(res).map_into_right_body());
+            }
+
+            let mut res = service.call(req).await?;
+            insert_headers(res.headers_mut(), &decision);
+            Ok(res.map_into_left_body())
+        })
+    }
+}
+
+fn insert_headers(headers: &mut actix_web::http::header::HeaderMap, decision: &Decision) {
+    headers.insert(X_RATELIMIT_LIMIT, HeaderValue::from(decision.limit));
+    headers.insert(X_RATELIMIT_REMAINING, HeaderValue::from(decision.remaining));
+    headers.insert(
+        X_RATELIMIT_RESET,
+        HeaderValue::from(decision.reset.as_secs_f64().ceil() as u64),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn limiter(config: &str) -> RateLimiter {
+        let config: RateLimit = toml::from_str(config).unwrap();
+        RateLimiter::from_config(
+            &config,
+            #[cfg(feature = "redis")]
+            &crate::DbManager::default(),
+        )
+        .unwrap()
+    }
+
+    fn scope(limiter: &RateLimiter, path: &str) -> String {
+        let req = TestRequest::with_uri(path).to_srv_request();
+        limiter.inner.policy_for(&req).unwrap().0
+    }
+
+    fn policy(config: &str) -> Policy {
+        let config: LimitPolicy = toml::from_str(config).unwrap();
+        Policy::try_from(&config).unwrap()
+    }
+
+    fn allowed(store: &MemoryStore, policy: &Policy, times: usize) -> usize {
+        (0..times)
+            .filter(|_| store.check("ip:127.0.0.1", policy).allowed)
+            .count()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn token_bucket_allows_a_burst_and_refills() {
+        let store = MemoryStore::default();
+        let policy = policy("capacity = 5\nperiod_secs = 10\n");
+
+        assert_eq!(allowed(&store, &policy, 10), 5);
+        let decision = store.check("ip:127.0.0.1", &policy);
+        assert!(!decision.allowed);
+        assert_eq!(decision.remaining, 0);
+        assert_eq!(decision.retry_after, Duration::from_secs(2));
+        assert_eq!(decision.reset, Duration::from_secs(10));
+
+        // 每 2 秒补充一个令牌
+        tokio::time::advance(Duration::from_secs(2)).await;
+        assert_eq!(allowed(&store, &policy, 3), 1);
+        tokio::time::advance(Duration::from_secs(5)).await;
+        assert_eq!(allowed(&store, &policy, 5), 2);
+
+        // 补充不超过容量
+        tokio::time::advance(Duration::from_secs(100)).await;
+        assert_eq!(allowed(&store, &policy, 10), 5);
+        assert_eq!(allowed(&store, &policy, 1), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn token_buckets_are_kept_per_key() {
+        let store = MemoryStore::default();
+        let policy = policy("capacity = 2\nperiod_secs = 60\n");
+        assert_eq!(allowed(&store, &policy, 3), 2);
+        assert!(store.check("ip:10.0.0.1", &policy).allowed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn sliding_window_rejects_at_the_limit() {
+        let store = MemoryStore::default();
+        let policy = policy("algorithm = \"sliding_window\"\ncapacity = 4\nperiod_secs = 10\n");
+
+        assert_eq!(allowed(&store, &policy, 4), 4);
+        let decision = store.check("ip:127.0.0.1", &policy);
+        assert!(!decision.allowed);
+        assert_eq!(decision.remaining, 0);
+        assert_eq!(decision.retry_after, Duration::from_secs(10));
+
+        tokio::time::advance(Duration::from_secs(3)).await;
+        let decision = store.check("ip:127.0.0.1", &policy);
+        assert!(!decision.allowed);
+        assert_eq!(decision.retry_after, Duration::from_secs(7));
+
+        // 下一个窗口过半时，上一个窗口的 4 次按一半计入
+        tokio::time::advance(Duration::from_secs(12)).await;
+        assert_eq!(allowed(&store, &policy, 4), 2);
+        let decision = store.check("ip:127.0.0.1", &policy);
+        assert!(!decision.allowed);
+        assert_eq!(decision.retry_after, Duration::from_secs(5));
+
+        // 两个周期无请求后重新计数
+        tokio::time::advance(Duration::from_secs(20)).await;
+        assert_eq!(allowed(&store, &policy, 5), 4);
+    }
+
+    #[actix_web::test]
+    async fn rejected_requests_carry_retry_after() {
+        use actix_web::{App, HttpResponse, test, web};
+
+        tokio::time::pause();
+        let limiter = limiter(
+            "[[rules]]\npattern = \"/api/**\"\nalgorithm = \"sliding_window\"\ncapacity = 2\nperiod_secs = 30\n",
+        );
+        let app = test::init_service(
+            App::new()
+                .wrap(limiter)
+                .route("/api/items", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let call =
+            || test::call_service(&app, test::TestRequest::with_uri("/api/items").to_request());
+
+        assert_eq!(call().await.status(), StatusCode::OK);
+        tokio::time::advance(Duration::from_millis(11_500)).await;
+        assert_eq!(call().await.status(), StatusCode::OK);
+        let res = call().await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        // 剩余 18.5 秒向上取整
+        assert_eq!(res.headers().get(RETRY_AFTER).unwrap(), "19");
+        assert_eq!(res.headers().get(X_RATELIMIT_REMAINING).unwrap(), "0");
+    }
+
+    #[test]
+    fn buckets_are_named_by_pattern_and_policy() {
+        let login = "[[rules]]\npattern = \"/login\"\ncapacity = 5\n";
+        let api = "[[rules]]\npattern = \"/api/**\"\ncapacity = 100\n";
+        let first = limiter(&format!("{login}{api}"));
+        let reordered = limiter(&format!("{api}{login}"));
+        assert_eq!(scope(&first, "/api/users"), scope(&reordered, "/api/users"));
+
+        let lowered = limiter(&format!("{login}{}", api.replace("100", "50")));
+        assert_ne!(scope(&first, "/api/users"), scope(&lowered, "/api/users"));
+        assert_ne!(scope(&first, "/api/users"), scope(&first, "/login"));
+    }
+}
@@ -0,0 +1,354 @@
+use crate::config::{RouteSecurity, Security};
+use crate::extract::CspNonce;
+use crate::middleware::{path_matches, request_path};
+use actix_web::body::MessageBody;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform, forward_ready};
+use actix_web::http::StatusCode;
+use actix_web::http::header::{
+    CONTENT_SECURITY_POLICY, HeaderMap, HeaderName, HeaderValue, REFERRER_POLICY,
+    STRICT_TRANSPORT_SECURITY, X_CONTENT_TYPE_OPTIONS, X_FRAME_OPTIONS,
+};
+use actix_web::{Error, HttpMessage, HttpResponse, ResponseError};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use futures_util::future::LocalBoxFuture;
+use rand::Rng as _;
+use std::fmt;
+use std::future::{Ready, ready};
+use std::rc::Rc;
+use std::sync::Arc;
+
+const PERMISSIONS_POLICY: HeaderName = HeaderName::from_static("permissions-policy");
+const NONCE: &str = "{nonce}";
+
+struct Header {
+    name: HeaderName,
+    value: HeaderValue,
+    /// The value contains `{nonce}`.
+    nonce: bool,
+}
+
+/// The headers of the global configuration or of a route override.
+struct HeaderSet {
+    pattern: Option<String>,
+    headers: Vec<Header>,
+    nonce: bool,
+}
+
+impl HeaderSet {
+    fn new(pattern: Option<String>, values: [(HeaderName, &str); 6]) -> anyhow::Result<Self> {
+        let mut headers = vec![];
+        for (name, value) in values {
+            if value.is_empty() {
+                continue;
+            }
+            let value = HeaderValue::from_str(value)
+                .map_err(|e| anyhow::anyhow!("[security] invalid {} '{}': {}", name, value, e))?;
+            let nonce =
+                name == CONTENT_SECURITY_POLICY && value.to_str().is_ok_and(|v| v.contains(NONCE));
+            headers.push(Header { name, value, nonce });
+        }
+        let nonce = headers.iter().any(|h| h.nonce);
+        Ok(Self {
+            pattern,
+            headers,
+            nonce,
+        })
+    }
+
+    /// Adds the headers `headers` does not contain yet.
+    fn apply(&self, headers: &mut HeaderMap, nonce: Option<&str>) {
+        for header in &self.headers {
+            if headers.contains_key(&header.name) {
+                continue;
+            }
+            let value = match (nonce, header.nonce) {
+                (Some(nonce), true) => {
+                    // unwrap: a base64 nonce keeps a valid header value valid
+                    let value = header.value.to_str().unwrap().replace(NONCE, nonce);
+                    HeaderValue::from_str(&value).unwrap()
+                }
+                _ => header.value.clone(),
+            };
+            headers.insert(header.name.clone(), value);
+        }
+    }
+}
+
+/// An error returned as `Err` by an inner service (panics, timeouts, failed commits), rendered
+/// with the security headers once actix-web turns it into a response.
+struct SecuredError {
+    inner: Error,
+    sets: Arc<Vec<HeaderSet>>,
+    index: usize,
+    nonce: Option<String>,
+}
+
+impl fmt::Debug for SecuredError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.inner, f)
+    }
+}
+
+impl fmt::Display for SecuredError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.inner, f)
+    }
+}
+
+impl ResponseError for SecuredError {
+    fn status_code(&self) -> StatusCode {
+        self.inner.as_response_error().status_code()
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut res = self.inner.error_response();
+        self.sets[self.index].apply(res.headers_mut(), self.nonce.as_deref());
+        res
+    }
+}
+
+/// Adds the headers of `[web.security]` to responses that did not set them.
+///
+/// Routes are matched in order against `[[web.security.routes]]`, the first match replaces the
+/// global values it sets. Errors that inner middlewares return as `Err` get the headers too.
+#[derive(Clone)]
+pub(crate) struct SecurityHeaders {
+    sets: Arc<Vec<HeaderSet>>,
+}
+
+impl SecurityHeaders {
+    pub(crate) fn from_config(config: &Security) -> anyhow::Result<Self> {
+        let mut sets = vec![];
+        if config.enabled {
+            for route in &config.routes {
+                sets.push(HeaderSet::new(
+                    Some(route.pattern.clone()),
+                    values(config, Some(route)),
+                )?);
+            }
+            sets.push(HeaderSet::new(None, values(config, None))?);
+        }
+        Ok(Self {
+            sets: Arc::new(sets),
+        })
+    }
+
+    fn matching(&self, path: &str) -> Option<usize> {
+        self.sets.iter().position(|set| {
+            set.pattern
+                .as_deref()
+                .is_none_or(|pattern| path_matches(pattern, path))
+        })
+    }
+}
+
+fn values<'a>(
+    config: &'a Security,
+    route: Option<&'a RouteSecurity>,
+) -> [(HeaderName, &'a str); 6] {
+    let pick = |global: &'a String, route: Option<&'a Option<String>>| {
+        route.and_then(Option::as_deref).unwrap_or(global)
+    };
+    [
+        (
+            STRICT_TRANSPORT_SECURITY,
+            pick(&config.hsts, route.map(|r| &r.hsts)),
+        ),
+        (
+            CONTENT_SECURITY_POLICY,
+            pick(
+                &config.content_security_policy,
+                route.map(|r| &r.content_security_policy),
+            ),
+        ),
+        (
+            X_CONTENT_TYPE_OPTIONS,
+            pick(
+                &config.content_type_options,
+                route.map(|r| &r.content_type_options),
+            ),
+        ),
+        (
+            X_FRAME_OPTIONS,
+            pick(&config.frame_options, route.map(|r| &r.frame_options)),
+        ),
+        (
+            REFERRER_POLICY,
+            pick(&config.referrer_policy, route.map(|r| &r.referrer_policy)),
+        ),
+        (
+            PERMISSIONS_POLICY,
+            pick(
+                &config.permissions_policy,
+                route.map(|r| &r.permissions_policy),
+            ),
+        ),
+    ]
+}
+
+fn generate_nonce() -> String {
+    STANDARD.encode(rand::rng().random::<[u8; 16]>())
+}
+
+impl<S, B> Transform<S, ServiceRequest> for SecurityHeaders
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = SecurityHeadersMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(SecurityHeadersMiddleware {
+            service: Rc::new(service),
+            headers: self.clone(),
+        }))
+    }
+}
+
+pub(crate) struct SecurityHeadersMiddleware<S> {
+    service: Rc<S>,
+    headers: SecurityHeaders,
+}
+
+impl<S, B> Service<ServiceRequest> for SecurityHeadersMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let Some(index) = self.headers.matching(request_path(&req)) else {
+            return Box::pin(async move { service.call(req).await });
+        };
+
+        let sets = self.headers.sets.clone();
+        let nonce = sets[index].nonce.then(generate_nonce);
+        if let Some(nonce) = &nonce {
+            req.extensions_mut().insert(CspNonce(nonce.clone()));
+        }
+
+        Box::pin(async move {
+            match service.call(req).await {
+                Ok(mut res) => {
+                    sets[index].apply(res.headers_mut(), nonce.as_deref());
+                    Ok(res)
+                }
+                Err(inner) => Err(SecuredError {
+                    inner,
+                    sets,
+                    index,
+                    nonce,
+                }
+                .into()),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::WebError;
+    use actix_web::{App, test, web};
+    use std::future::ready;
+
+    fn headers(config: &str) -> SecurityHeaders {
+        let config: Security = toml::from_str(config).unwrap();
+        SecurityHeaders::from_config(&config).unwrap()
+    }
+
+    fn header<'a>(res: &'a HttpResponse<impl MessageBody>, name: &HeaderName) -> Option<&'a str> {
+        res.headers().get(name).map(|v| v.to_str().unwrap())
+    }
+
+    #[actix_web::test]
+    async fn route_overrides_replace_the_values_they_set() {
+        let headers = headers(
+            "enabled = true\nframe_options = \"DENY\"\n\
+             [[routes]]\npattern = \"/embed/**\"\nframe_options = \"SAMEORIGIN\"\n",
+        );
+        let app = test::init_service(
+            App::new()
+                .wrap(headers)
+                .route("/embed/{id}", web::get().to(HttpResponse::Ok))
+                .route("/page", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/embed/1").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(header(res.response(), &X_FRAME_OPTIONS), Some("SAMEORIGIN"));
+        // 未覆盖的字段沿用全局配置
+        assert_eq!(
+            header(res.response(), &REFERRER_POLICY),
+            Some("strict-origin-when-cross-origin")
+        );
+
+        let req = test::TestRequest::get().uri("/page").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(header(res.response(), &X_FRAME_OPTIONS), Some("DENY"));
+    }
+
+    #[actix_web::test]
+    async fn csp_nonce_matches_the_extracted_nonce() {
+        let headers =
+            headers("enabled = true\ncontent_security_policy = \"script-src 'nonce-{nonce}'\"\n");
+        let app = test::init_service(
+            App::new()
+                .wrap(headers)
+                .route("/", web::get().to(|nonce: CspNonce| async move { nonce.0 })),
+        )
+        .await;
+
+        let mut nonces = vec![];
+        for _ in 0..2 {
+            let req = test::TestRequest::get().uri("/").to_request();
+            let res = test::call_service(&app, req).await;
+            let csp = header(res.response(), &CONTENT_SECURITY_POLICY)
+                .unwrap()
+                .to_string();
+            let nonce = String::from_utf8(test::read_body(res).await.to_vec()).unwrap();
+            assert_eq!(csp, format!("script-src 'nonce-{}'", nonce));
+            nonces.push(nonce);
+        }
+        assert_ne!(nonces[0], nonces[1]);
+    }
+
+    #[actix_web::test]
+    async fn errors_of_inner_middlewares_get_the_headers() {
+        let headers = headers("enabled = true\nframe_options = \"DENY\"\n");
+        let app = test::init_service(
+            App::new()
+                .wrap_fn(|_, _| {
+                    ready(Err::<ServiceResponse, _>(
+                        WebError::Http(
+                            StatusCode::SERVICE_UNAVAILABLE,
+                            String::from("unavailable"),
+                        )
+                        .into(),
+                    ))
+                })
+                .wrap(headers)
+                .route("/", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/").to_request();
+        let err = app.call(req).await.err().unwrap();
+        let res = err.error_response();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(header(&res, &X_FRAME_OPTIONS), Some("DENY"));
+        assert_eq!(header(&res, &X_CONTENT_TYPE_OPTIONS), Some("nosniff"));
+    }
+}
@@ -0,0 +1,181 @@
+use crate::config::Timeout;
+use crate::error::WebError;
+use crate::logging;
+use crate::metrics;
+use crate::middleware::{path_matches, request_path};
+use actix_web::Error;
+use actix_web::body::MessageBody;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform, forward_ready};
+use actix_web::http::StatusCode;
+use futures_util::future::LocalBoxFuture;
+use std::future::{Ready, ready};
+use std::rc::Rc;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Enforces `[web.timeout]`.
+///
+/// The handler future is dropped once the timeout of the route elapsed, which releases its pooled
+/// connections and rolls back open transactions. Response bodies (downloads, SSE) are not limited.
+#[derive(Debug, Clone)]
+pub(crate) struct RequestTimeout {
+    config: Arc<Timeout>,
+    status: StatusCode,
+}
+
+impl RequestTimeout {
+    pub(crate) fn from_config(config: Timeout) -> anyhow::Result<Self> {
+        let status = match config.status {
+            503 => StatusCode::SERVICE_UNAVAILABLE,
+            504 => StatusCode::GATEWAY_TIMEOUT,
+            other => anyhow::bail!("[timeout] status must be 503 or 504, got {}", other),
+        };
+        Ok(Self {
+            config: Arc::new(config),
+            status,
+        })
+    }
+
+    fn timeout(&self, path: &str) -> Option<Duration> {
+        if !self.config.enabled {
+            return None;
+        }
+        let secs = self
+            .config
+            .routes
+            .iter()
+            .find(|route| path_matches(&route.pattern, path))
+            .map_or(self.config.secs, |route| route.secs);
+        (secs > 0).then(|| Duration::from_secs(secs))
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RequestTimeout
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RequestTimeoutMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestTimeoutMiddleware {
+            service: Rc::new(service),
+            timeout: self.clone(),
+        }))
+    }
+}
+
+pub(crate) struct RequestTimeoutMiddleware<S> {
+    service: Rc<S>,
+    timeout: RequestTimeout,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestTimeoutMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let Some(duration) = self.timeout.timeout(request_path(&req)) else {
+            return Box::pin(async move { service.call(req).await });
+        };
+
+        let status = self.timeout.status;
+        let code = self.timeout.config.code;
+        let method = req.method().clone();
+        let path = req.path().to_string();
+        // 未匹配到路由时不使用原始路径，避免计数项无限增长
+        let route = req.match_pattern();
+
+        Box::pin(async move {
+            match tokio::time::timeout(duration, service.call(req)).await {
+                Ok(result) => result,
+                Err(_) => {
+                    logging::warn!("[timeout] {} {} exceeded {:?}", method, path, duration);
+                    metrics::increment(format!(
+                        "http_server_timeouts_total{{route=\"{}\"}}",
+                        route.as_deref().unwrap_or("<unmatched>")
+                    ));
+                    Err(WebError::Timeout(status, code, duration).into())
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{TestRequest, call_service, init_service};
+    use actix_web::{App, HttpResponse, body, web};
+
+    async fn slow() -> HttpResponse {
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        HttpResponse::Ok().finish()
+    }
+
+    #[actix_web::test]
+    async fn slow_handlers_time_out_with_the_configured_status() {
+        tokio::time::pause();
+        let config: Timeout = toml::from_str(
+            "enabled = true\nsecs = 1\nstatus = 503\n\
+             [[routes]]\npattern = \"/timeout-test/fast/**\"\nsecs = 0\n",
+        )
+        .unwrap();
+        let timeout = RequestTimeout::from_config(config).unwrap();
+        let app = init_service(
+            App::new()
+                .wrap(timeout)
+                .route("/timeout-test/reports/{id}", web::get().to(slow))
+                .route("/timeout-test/fast/{id}", web::get().to(slow)),
+        )
+        .await;
+
+        let req = TestRequest::get()
+            .uri("/timeout-test/reports/1")
+            .to_request();
+        let err = app.call(req).await.err().unwrap();
+        let res = err.as_response_error().error_response();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = body::to_bytes(res.into_body()).await.unwrap();
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.starts_with(r#"{"code":5040,"#), "{body}");
+
+        let counter = r#"http_server_timeouts_total{route="/timeout-test/reports/{id}"}"#;
+        assert_eq!(metrics::snapshot().get(counter), Some(&1));
+
+        // secs = 0 不限制
+        let req = TestRequest::get().uri("/timeout-test/fast/1").to_request();
+        assert_eq!(call_service(&app, req).await.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn only_503_and_504_are_accepted() {
+        for status in [503, 504] {
+            let config = Timeout {
+                status,
+                ..Timeout::default()
+            };
+            assert_eq!(
+                RequestTimeout::from_config(config).unwrap().status.as_u16(),
+                status
+            );
+        }
+        let config = Timeout {
+            status: 500,
+            ..Timeout::default()
+        };
+        assert!(RequestTimeout::from_config(config).is_err());
+    }
+}
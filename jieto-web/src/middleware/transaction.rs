@@ -0,0 +1,202 @@
+use crate::error::WebError;
+use crate::tx::PendingTransactions;
+use actix_web::Error;
+use actix_web::HttpMessage;
+use actix_web::body::MessageBody;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform, forward_ready};
+use futures_util::future::LocalBoxFuture;
+use std::future::{Ready, ready};
+use std::rc::Rc;
+
+/// Finishes the transactions of extracted `Tx` values: commits when the handler produced a
+/// successful response, rolls back when it returned an error.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TransactionFinalizer;
+
+impl<S, B> Transform<S, ServiceRequest> for TransactionFinalizer
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = TransactionFinalizerMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(TransactionFinalizerMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub(crate) struct TransactionFinalizerMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for TransactionFinalizerMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+
+        Box::pin(async move {
+            // 出错时未完成的事务随请求一起释放，由 sqlx 回滚
+            let res = service.call(req).await?;
+            let pending = res
+                .request()
+                .extensions()
+                .get::<PendingTransactions>()
+                .cloned();
+            let Some(pending) = pending else {
+                return Ok(res);
+            };
+
+            let commit = res.response().error().is_none() && res.status().is_success();
+            match pending.finish(commit).await {
+                Ok(()) => Ok(res),
+                Err(e) if commit => Err(WebError::Execution(e).into()),
+                Err(_) => Ok(res),
+            }
+        })
+    }
+}
+
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::tx::{Sqlite, Tx};
+    use crate::{AppState, DbManager};
+    use actix_web::http::StatusCode;
+    use actix_web::{App, HttpResponse, test, web};
+    use jieto_db::database::{DataSource, DefaultKey};
+    use sqlx::SqlitePool;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    async fn insert(tx: &mut Tx<Sqlite>, name: &str) {
+        sqlx::query("INSERT INTO items (name) VALUES (?)")
+            .bind(name)
+            .execute(&mut **tx)
+            .await
+            .unwrap();
+    }
+
+    async fn committed(mut tx: Tx<Sqlite>) -> HttpResponse {
+        insert(&mut tx, "committed").await;
+        HttpResponse::Ok().finish()
+    }
+
+    async fn failed(mut tx: Tx<Sqlite>) -> Result<HttpResponse, WebError> {
+        insert(&mut tx, "failed").await;
+        Err(WebError::Business(400, String::from("rejected")))
+    }
+
+    async fn server_error(mut tx: Tx<Sqlite>) -> HttpResponse {
+        insert(&mut tx, "server_error").await;
+        HttpResponse::InternalServerError().finish()
+    }
+
+    async fn without_tx() -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    /// A pool on a fresh database file, shared by every connection unlike `sqlite::memory:`.
+    async fn pool(name: &str) -> SqlitePool {
+        let path =
+            std::env::temp_dir().join(format!("jieto-tx-{}-{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let pool =
+            SqlitePool::connect_lazy(&format!("sqlite://{}?mode=rwc", path.display())).unwrap();
+        sqlx::query("CREATE TABLE items (name TEXT NOT NULL)")
+            .execute(&pool)
+            .await
+            .unwrap();
+        pool
+    }
+
+    async fn names(pool: &SqlitePool) -> Vec<String> {
+        sqlx::query_scalar("SELECT name FROM items")
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    fn state(pool: &SqlitePool) -> web::Data<AppState> {
+        let manager = DbManager::new(
+            HashMap::from([(
+                String::from("main"),
+                DataSource::Sqlite { pool: pool.clone() },
+            )]),
+            HashMap::from([(DefaultKey::Sqlite, String::from("main"))]),
+        );
+        let mut state = AppState::default();
+        state.with_db(Arc::new(manager));
+        web::Data::new(state)
+    }
+
+    #[actix_web::test]
+    async fn commits_successful_responses_and_rolls_back_errors() {
+        let pool = pool("finish").await;
+        let app = test::init_service(
+            App::new()
+                .app_data(state(&pool))
+                .wrap(TransactionFinalizer)
+                .route("/committed", web::post().to(committed))
+                .route("/failed", web::post().to(failed))
+                .route("/server-error", web::post().to(server_error)),
+        )
+        .await;
+
+        let call = |uri: &'static str| {
+            test::call_service(&app, test::TestRequest::post().uri(uri).to_request())
+        };
+        assert_eq!(call("/committed").await.status(), StatusCode::OK);
+        // 业务错误以 200 返回 ApiResult，同样回滚
+        assert_eq!(call("/failed").await.status(), StatusCode::OK);
+        assert_eq!(
+            call("/server-error").await.status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+
+        assert_eq!(names(&pool).await, ["committed"]);
+        pool.close().await;
+        let path = std::env::temp_dir().join(format!("jieto-tx-finish-{}.db", std::process::id()));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn requests_without_tx_open_no_connection() {
+        let path = std::env::temp_dir().join(format!("jieto-tx-idle-{}.db", std::process::id()));
+        let pool =
+            SqlitePool::connect_lazy(&format!("sqlite://{}?mode=rwc", path.display())).unwrap();
+        let app = test::init_service(
+            App::new()
+                .app_data(state(&pool))
+                .wrap(TransactionFinalizer)
+                .route("/", web::get().to(without_tx)),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(
+            res.request()
+                .extensions()
+                .get::<PendingTransactions>()
+                .is_none()
+        );
+        assert_eq!(pool.size(), 0);
+        assert!(!path.exists());
+    }
+}
@@ -1,9 +1,9 @@
 use crate::error::WebError;
 use crate::{BusinessError, JietoResult};
 use actix_web::{HttpResponse, Responder};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Default, Clone, Debug)]
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
 pub struct ApiResult<T>
 where
     T: Serialize,
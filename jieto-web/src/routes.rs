@@ -0,0 +1,168 @@
+//! Routes registered through [`Application::routes`](crate::Application::routes).
+
+use actix_web::http::Method;
+use actix_web::web::{self, ServiceConfig};
+use actix_web::{FromRequest, Handler, Responder};
+use std::fmt;
+
+/// A route recorded by [`Routes`], listed by the `routes` command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteInfo {
+    pub method: String,
+    pub path: String,
+    pub handler: String,
+}
+
+impl fmt::Display for RouteInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:<7} {:<48} {}", self.method, self.path, self.handler)
+    }
+}
+
+/// Registers routes on a `ServiceConfig` and records their method, path and handler.
+///
+/// ```ignore
+/// Application::new(|_| {})
+///     .routes(|r| {
+///         r.get("/health", health);
+///         r.scope("/api", |r| {
+///             r.get("/users/{id}", find_user).post("/users", create_user);
+///         });
+///     })
+/// ```
+pub struct Routes<'a> {
+    cfg: &'a mut ServiceConfig,
+    prefix: String,
+    table: &'a mut Vec<RouteInfo>,
+}
+
+impl<'a> Routes<'a> {
+    pub(crate) fn new(cfg: &'a mut ServiceConfig, table: &'a mut Vec<RouteInfo>) -> Self {
+        Self {
+            cfg,
+            prefix: String::new(),
+            table,
+        }
+    }
+
+    /// Registers `handler` for `method` requests to `path`.
+    pub fn route<F, Args>(&mut self, method: Method, path: &str, handler: F) -> &mut Self
+    where
+        F: Handler<Args>,
+        Args: FromRequest + 'static,
+        F::Output: Responder + 'static,
+    {
+        self.table.push(RouteInfo {
+            method: method.to_string(),
+            path: format!("{}{}", self.prefix, path),
+            handler: std::any::type_name::<F>().to_string(),
+        });
+        self.cfg.route(path, web::method(method).to(handler));
+        self
+    }
+
+    pub fn get<F, Args>(&mut self, path: &str, handler: F) -> &mut Self
+    where
+        F: Handler<Args>,
+        Args: FromRequest + 'static,
+        F::Output: Responder + 'static,
+    {
+        self.route(Method::GET, path, handler)
+    }
+
+    pub fn post<F, Args>(&mut self, path: &str, handler: F) -> &mut Self
+    where
+        F: Handler<Args>,
+        Args: FromRequest + 'static,
+        F::Output: Responder + 'static,
+    {
+        self.route(Method::POST, path, handler)
+    }
+
+    pub fn put<F, Args>(&mut self, path: &str, handler: F) -> &mut Self
+    where
+        F: Handler<Args>,
+        Args: FromRequest + 'static,
+        F::Output: Responder + 'static,
+    {
+        self.route(Method::PUT, path, handler)
+    }
+
+    pub fn patch<F, Args>(&mut self, path: &str, handler: F) -> &mut Self
+    where
+        F: Handler<Args>,
+        Args: FromRequest + 'static,
+        F::Output: Responder + 'static,
+    {
+        self.route(Method::PATCH, path, handler)
+    }
+
+    pub fn delete<F, Args>(&mut self, path: &str, handler: F) -> &mut Self
+    where
+        F: Handler<Args>,
+        Args: FromRequest + 'static,
+        F::Output: Responder + 'static,
+    {
+        self.route(Method::DELETE, path, handler)
+    }
+
+    /// Registers the routes of `f` under the `path` prefix.
+    pub fn scope<S>(&mut self, path: &str, f: S) -> &mut Self
+    where
+        S: FnOnce(&mut Routes<'_>),
+    {
+        let prefix = format!("{}{}", self.prefix, path);
+        let table = &mut *self.table;
+        self.cfg.service(web::scope(path).configure(|cfg| {
+            f(&mut Routes { cfg, prefix, table });
+        }));
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{App, HttpResponse, test};
+
+    async fn ok() -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    #[actix_web::test]
+    async fn records_and_serves_scoped_routes() {
+        let mut table = vec![];
+        let app = App::new().configure(|cfg| {
+            let mut routes = Routes::new(cfg, &mut table);
+            routes.get("/health", ok);
+            routes.scope("/api", |r| {
+                r.post("/users", ok);
+                r.scope("/admin", |r| {
+                    r.delete("/users/{id}", ok);
+                });
+            });
+        });
+
+        let paths: Vec<(&str, &str)> = table
+            .iter()
+            .map(|route| (route.method.as_str(), route.path.as_str()))
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                ("GET", "/health"),
+                ("POST", "/api/users"),
+                ("DELETE", "/api/admin/users/{id}"),
+            ]
+        );
+        assert!(table[0].handler.ends_with("::ok"));
+
+        let app = test::init_service(app).await;
+        let req = test::TestRequest::delete()
+            .uri("/api/admin/users/1")
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+        let req = test::TestRequest::get().uri("/api/users").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 404);
+    }
+}
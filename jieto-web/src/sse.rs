@@ -0,0 +1,516 @@
+//! Server-Sent Events.
+//!
+//! Clients subscribe to a channel through an endpoint returning [`Sse::stream`]. Events are
+//! published through the [`SseBroadcaster`] in `AppState`, or [`GLOBAL_SSE`](crate::GLOBAL_SSE)
+//! outside handlers, e.g. from scheduled tasks.
+//!
+//! ```ignore
+//! #[get("/events/{room}")]
+//! async fn events(sse: Sse, room: web::Path<String>, user: CurrentUser) -> impl Responder {
+//!     sse.stream(room.as_str()).client(user.0)
+//! }
+//!
+//! #[post("/rooms/{room}/messages")]
+//! async fn say(state: web::Data<AppState>, room: web::Path<String>, body: String) -> HttpResponse {
+//!     state.sse.publish(&room, SseEvent::new(body).event("message"));
+//!     HttpResponse::Accepted().finish()
+//! }
+//! ```
+//!
+//! Published events are numbered and the last `buffer_size` of a channel are kept, so a client
+//! reconnecting with `Last-Event-ID` receives what it missed. A channel without clients is dropped
+//! once it has been idle for `replay_secs`, which ends its replay. Ids keep increasing across all
+//! channels, so that a channel created again never reuses the id a client last saw. Events sent to a single client
+//! with [`SseBroadcaster::send_to`] are not buffered.
+
+use crate::config;
+use crate::error::WebError;
+use crate::logging;
+use actix_web::body::BoxBody;
+use actix_web::dev::Payload;
+use actix_web::http::StatusCode;
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::web::Bytes;
+use actix_web::{FromRequest, HttpRequest, HttpResponse, Responder, web};
+use futures_util::stream;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fmt::Write as _;
+use std::future::{Ready, ready};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::time::{Instant, MissedTickBehavior, interval_at};
+
+const LAST_EVENT_ID: &str = "Last-Event-ID";
+
+/// An event sent to SSE clients.
+#[derive(Debug, Clone, Default)]
+pub struct SseEvent {
+    event: Option<String>,
+    data: String,
+    retry: Option<Duration>,
+}
+
+impl SseEvent {
+    pub fn new(data: impl Into<String>) -> Self {
+        Self {
+            data: data.into(),
+            ..Default::default()
+        }
+    }
+
+    /// An event whose data is `value` serialized as JSON.
+    pub fn json<T: Serialize>(value: &T) -> Result<Self, WebError> {
+        serde_json::to_string(value)
+            .map(Self::new)
+            .map_err(|e| WebError::Http(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+    }
+
+    /// Event type, dispatched to `addEventListener(name)` instead of `onmessage`.
+    pub fn event(mut self, name: impl Into<String>) -> Self {
+        self.event = Some(name.into());
+        self
+    }
+
+    /// Reconnection delay the client should use from now on.
+    pub fn retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    fn encode(&self, id: Option<u64>) -> Bytes {
+        let mut frame = String::with_capacity(self.data.len() + 32);
+        if let Some(id) = id {
+            let _ = writeln!(frame, "id: {id}");
+        }
+        if let Some(event) = &self.event {
+            // 换行会结束字段，事件名不能跨行
+            let event = event.replace(['\r', '\n'], "");
+            let _ = writeln!(frame, "event: {event}");
+        }
+        if let Some(retry) = self.retry {
+            let _ = writeln!(frame, "retry: {}", retry.as_millis());
+        }
+        // 多行数据逐行输出 data 字段
+        for line in self.data.split('\n') {
+            let _ = writeln!(frame, "data: {}", line.trim_end_matches('\r'));
+        }
+        frame.push('\n');
+        Bytes::from(frame)
+    }
+}
+
+type ClientKey = u64;
+
+struct Client {
+    id: Option<String>,
+    channel: String,
+    tx: mpsc::Sender<Bytes>,
+}
+
+struct Channel {
+    buffer: VecDeque<(u64, Bytes)>,
+    clients: Vec<ClientKey>,
+    /// Last event or client departure.
+    last_active: Instant,
+}
+
+impl Channel {
+    fn new() -> Self {
+        Self {
+            buffer: VecDeque::new(),
+            clients: vec![],
+            last_active: Instant::now(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Registry {
+    next_key: ClientKey,
+    /// Id of the last published event, on any channel.
+    last_id: u64,
+    channels: HashMap<String, Channel>,
+    clients: HashMap<ClientKey, Client>,
+    last_sweep: Option<Instant>,
+}
+
+impl Registry {
+    fn remove(&mut self, key: ClientKey) {
+        let Some(client) = self.clients.remove(&key) else {
+            return;
+        };
+        if let Some(channel) = self.channels.get_mut(&client.channel) {
+            channel.clients.retain(|k| *k != key);
+            channel.last_active = Instant::now();
+            // 没有订阅者且无可恢复的事件时释放频道
+            if channel.clients.is_empty() && channel.buffer.is_empty() {
+                self.channels.remove(&client.channel);
+            }
+        }
+    }
+
+    /// Drops the channels without clients that were idle for `idle`, sweeping at most once per
+    /// `idle` so that a channel lives at most twice that long.
+    fn evict_idle(&mut self, idle: Duration) {
+        let now = Instant::now();
+        if self.last_sweep.is_some_and(|last| now - last < idle) {
+            return;
+        }
+        self.last_sweep = Some(now);
+        self.channels
+            .retain(|_, channel| !channel.clients.is_empty() || now - channel.last_active < idle);
+    }
+
+    /// Queues `frame` for `key`, returns false when the client is gone or too slow.
+    fn deliver(&self, key: ClientKey, frame: &Bytes) -> bool {
+        let Some(client) = self.clients.get(&key) else {
+            return false;
+        };
+        match client.tx.try_send(frame.clone()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                // 断开过慢的客户端，重连后通过 Last-Event-ID 补发
+                logging::warn!(
+                    "[sse] client {} on '{}' is too slow, disconnecting",
+                    client.id.as_deref().unwrap_or("-"),
+                    client.channel
+                );
+                false
+            }
+            Err(TrySendError::Closed(_)) => false,
+        }
+    }
+}
+
+/// Publishes events to SSE channels and clients, cheap to clone.
+#[derive(Clone)]
+pub struct SseBroadcaster {
+    config: Arc<config::Sse>,
+    registry: Arc<Mutex<Registry>>,
+}
+
+impl Default for SseBroadcaster {
+    fn default() -> Self {
+        Self::new(config::Sse::default())
+    }
+}
+
+impl fmt::Debug for SseBroadcaster {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SseBroadcaster")
+            .field("clients", &self.client_count())
+            .finish()
+    }
+}
+
+impl SseBroadcaster {
+    pub(crate) fn new(config: config::Sse) -> Self {
+        Self {
+            config: Arc::new(config),
+            registry: Arc::new(Mutex::new(Registry::default())),
+        }
+    }
+
+    fn replay(&self) -> Duration {
+        Duration::from_secs(self.config.replay_secs)
+    }
+
+    fn registry(&self) -> std::sync::MutexGuard<'_, Registry> {
+        // 锁内不会 panic，出现中毒时继续使用
+        self.registry.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Sends `event` to every client of `channel` and buffers it for resuming clients.
+    ///
+    /// Returns the id assigned to the event.
+    pub fn publish(&self, channel: &str, event: SseEvent) -> u64 {
+        let mut registry = self.registry();
+        registry.evict_idle(self.replay());
+        let buffer_size = self.config.buffer_size;
+        registry.last_id += 1;
+        let id = registry.last_id;
+        let entry = registry
+            .channels
+            .entry(channel.to_string())
+            .or_insert_with(Channel::new);
+        entry.last_active = Instant::now();
+        let frame = event.encode(Some(id));
+        if buffer_size > 0 {
+            if entry.buffer.len() == buffer_size {
+                entry.buffer.pop_front();
+            }
+            entry.buffer.push_back((id, frame.clone()));
+        }
+
+        let clients = entry.clients.clone();
+        for key in clients {
+            if !registry.deliver(key, &frame) {
+                registry.remove(key);
+            }
+        }
+        id
+    }
+
+    /// Sends `event` to the clients subscribed with [`SseStream::client`] as `client`, on any
+    /// channel. Returns how many streams received it.
+    pub fn send_to(&self, client: &str, event: SseEvent) -> usize {
+        let mut registry = self.registry();
+        let frame = event.encode(None);
+        let keys: Vec<ClientKey> = registry
+            .clients
+            .iter()
+            .filter(|(_, c)| c.id.as_deref() == Some(client))
+            .map(|(key, _)| *key)
+            .collect();
+
+        let mut delivered = 0;
+        for key in keys {
+            if registry.deliver(key, &frame) {
+                delivered += 1;
+            } else {
+                registry.remove(key);
+            }
+        }
+        delivered
+    }
+
+    /// Number of connected clients, on every channel.
+    pub fn client_count(&self) -> usize {
+        self.registry().clients.len()
+    }
+
+    /// Number of clients connected to `channel`.
+    pub fn channel_clients(&self, channel: &str) -> usize {
+        self.registry()
+            .channels
+            .get(channel)
+            .map(|c| c.clients.len())
+            .unwrap_or(0)
+    }
+
+    fn subscribe(
+        &self,
+        channel: &str,
+        client: Option<String>,
+        last_event_id: Option<u64>,
+    ) -> (Subscription, mpsc::Receiver<Bytes>) {
+        let mut registry = self.registry();
+        registry.evict_idle(self.replay());
+        let entry = registry
+            .channels
+            .entry(channel.to_string())
+            .or_insert_with(Channel::new);
+
+        // 补发 Last-Event-ID 之后的缓存事件
+        let missed: Vec<Bytes> = match last_event_id {
+            Some(last) => entry
+                .buffer
+                .iter()
+                .filter(|(id, _)| *id > last)
+                .map(|(_, frame)| frame.clone())
+                .collect(),
+            None => vec![],
+        };
+        let (tx, rx) = mpsc::channel(self.config.client_queue.max(1) + missed.len());
+        for frame in missed {
+            // unwrap: the queue has room for every missed event
+            tx.try_send(frame).unwrap();
+        }
+
+        let key = registry.next_key;
+        registry.next_key += 1;
+        // unwrap: inserted above
+        registry
+            .channels
+            .get_mut(channel)
+            .unwrap()
+            .clients
+            .push(key);
+        registry.clients.insert(
+            key,
+            Client {
+                id: client,
+                channel: channel.to_string(),
+                tx,
+            },
+        );
+        (
+            Subscription {
+                broadcaster: self.clone(),
+                key,
+            },
+            rx,
+        )
+    }
+}
+
+/// Unregisters the client when its response stream is dropped.
+struct Subscription {
+    broadcaster: SseBroadcaster,
+    key: ClientKey,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.broadcaster.registry().remove(self.key);
+    }
+}
+
+/// Extracts what an SSE endpoint needs: the broadcaster and the client's `Last-Event-ID`.
+pub struct Sse {
+    broadcaster: SseBroadcaster,
+    last_event_id: Option<u64>,
+}
+
+impl Sse {
+    /// A response streaming the events of `channel`.
+    pub fn stream(self, channel: impl Into<String>) -> SseStream {
+        SseStream {
+            broadcaster: self.broadcaster,
+            channel: channel.into(),
+            client: None,
+            last_event_id: self.last_event_id,
+        }
+    }
+}
+
+impl FromRequest for Sse {
+    type Error = WebError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let Some(state) = req.app_data::<web::Data<crate::AppState>>() else {
+            return ready(Err(WebError::Http(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                String::from("sse broadcaster not configured"),
+            )));
+        };
+        let last_event_id = req
+            .headers()
+            .get(LAST_EVENT_ID)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse().ok());
+        ready(Ok(Sse {
+            broadcaster: state.sse.clone(),
+            last_event_id,
+        }))
+    }
+}
+
+/// `text/event-stream` response of one channel, see [`Sse::stream`].
+pub struct SseStream {
+    broadcaster: SseBroadcaster,
+    channel: String,
+    client: Option<String>,
+    last_event_id: Option<u64>,
+}
+
+impl SseStream {
+    /// Identifies the client for [`SseBroadcaster::send_to`], e.g. the user id.
+    pub fn client(mut self, id: impl Into<String>) -> Self {
+        self.client = Some(id.into());
+        self
+    }
+}
+
+impl Responder for SseStream {
+    type Body = BoxBody;
+
+    fn respond_to(self, _req: &HttpRequest) -> HttpResponse<Self::Body> {
+        let keep_alive = Duration::from_secs(self.broadcaster.config.keep_alive_secs.max(1));
+        let (subscription, rx) =
+            self.broadcaster
+                .subscribe(&self.channel, self.client, self.last_event_id);
+        let mut ticker = interval_at(Instant::now() + keep_alive, keep_alive);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        let events = stream::unfold(
+            (subscription, rx, ticker),
+            |(subscription, mut rx, mut ticker)| async move {
+                let chunk = tokio::select! {
+                    frame = rx.recv() => frame?,
+                    _ = ticker.tick() => Bytes::from_static(b": keep-alive\n\n"),
+                };
+                Some((Ok::<_, actix_web::Error>(chunk), (subscription, rx, ticker)))
+            },
+        );
+
+        HttpResponse::Ok()
+            .content_type("text/event-stream")
+            .insert_header((header::CACHE_CONTROL, "no-cache"))
+            // 压缩会缓冲事件，反向代理同理
+            .insert_header((
+                header::CONTENT_ENCODING,
+                HeaderValue::from_static("identity"),
+            ))
+            .insert_header(("X-Accel-Buffering", "no"))
+            .streaming(events)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_names_stay_on_one_line() {
+        let frame = SseEvent::new("a\nb").event("x\r\ndata: y").encode(Some(1));
+        assert_eq!(frame, "id: 1\nevent: xdata: y\ndata: a\ndata: b\n\n");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_channels_are_dropped() {
+        let broadcaster = SseBroadcaster::new(config::Sse::default());
+        let channels = || broadcaster.registry().channels.len();
+        broadcaster.publish("room:1", SseEvent::new("a"));
+        broadcaster.publish("room:2", SseEvent::new("b"));
+        assert_eq!(channels(), 2);
+
+        tokio::time::advance(Duration::from_secs(200)).await;
+        broadcaster.publish("room:2", SseEvent::new("c"));
+        assert_eq!(channels(), 2);
+
+        tokio::time::advance(Duration::from_secs(200)).await;
+        broadcaster.publish("room:3", SseEvent::new("d"));
+        assert_eq!(channels(), 2);
+        assert!(!broadcaster.registry().channels.contains_key("room:1"));
+    }
+
+    #[tokio::test]
+    async fn clients_resume_after_last_event_id() {
+        let broadcaster = SseBroadcaster::new(config::Sse::default());
+        for n in 1..=5 {
+            broadcaster.publish("room", SseEvent::new(n.to_string()));
+        }
+
+        let (_subscription, mut rx) = broadcaster.subscribe("room", None, Some(2));
+        let mut ids = vec![];
+        while let Ok(frame) = rx.try_recv() {
+            let frame = String::from_utf8(frame.to_vec()).unwrap();
+            ids.push(frame.lines().next().unwrap().to_string());
+        }
+        assert_eq!(ids, ["id: 3", "id: 4", "id: 5"]);
+
+        broadcaster.publish("room", SseEvent::new("6"));
+        assert_eq!(rx.recv().await.unwrap(), "id: 6\ndata: 6\n\n");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn ids_are_not_reused_after_eviction() {
+        let broadcaster = SseBroadcaster::new(config::Sse::default());
+        assert_eq!(broadcaster.publish("room", SseEvent::new("a")), 1);
+        assert_eq!(broadcaster.publish("room", SseEvent::new("b")), 2);
+
+        tokio::time::advance(Duration::from_secs(400)).await;
+        assert_eq!(broadcaster.publish("other", SseEvent::new("c")), 3);
+        assert!(!broadcaster.registry().channels.contains_key("room"));
+        // 客户端带着旧 id 重连时不会漏掉新频道的事件
+        assert_eq!(broadcaster.publish("room", SseEvent::new("d")), 4);
+        let (_subscription, mut rx) = broadcaster.subscribe("room", None, Some(2));
+        assert_eq!(rx.try_recv().unwrap(), "id: 4\ndata: d\n\n");
+    }
+}
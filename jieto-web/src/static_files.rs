@@ -0,0 +1,248 @@
+use crate::config::StaticFiles;
+use crate::logging;
+use actix_files::NamedFile;
+use actix_web::http::header::{self, ContentEncoding, HeaderValue};
+use actix_web::http::Method;
+use actix_web::{HttpRequest, HttpResponse, web};
+use std::path::{Path, PathBuf};
+
+/// Precompressed variants in order of preference.
+const PRECOMPRESSED: [(&str, &str, ContentEncoding); 2] = [
+    ("br", "br", ContentEncoding::Brotli),
+    ("gzip", "gz", ContentEncoding::Gzip),
+];
+
+async fn serve(req: HttpRequest, conf: web::Data<StaticFiles>) -> HttpResponse {
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+        return HttpResponse::NotFound().finish();
+    }
+
+    let Some(relative) = sanitize(req.match_info().unprocessed()) else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    let root = Path::new(&conf.directory);
+    let path = match resolve(root.join(&relative), &conf.index).await {
+        Some(path) => path,
+        // 前端路由：无扩展名的未知路径回退到 index
+        None if conf.spa && relative.extension().is_none() => root.join(&conf.index),
+        None => return HttpResponse::NotFound().finish(),
+    };
+
+    let (file, compressed) = match open(&req, &path, conf.precompressed).await {
+        Ok(opened) => opened,
+        Err(e) => {
+            logging::debug!("[static] failed to open {}: {}", path.display(), e);
+            return HttpResponse::NotFound().finish();
+        }
+    };
+
+    let mut res = file.into_response(&req);
+    let is_index = path.file_name().and_then(|n| n.to_str()) == Some(conf.index.as_str());
+    let cache_control = if is_index {
+        conf.index_cache_control.as_ref().or(conf.cache_control.as_ref())
+    } else {
+        conf.cache_control.as_ref()
+    };
+    if let Some(value) = cache_control.and_then(|v| HeaderValue::from_str(v).ok()) {
+        res.headers_mut().insert(header::CACHE_CONTROL, value);
+    }
+    if conf.precompressed || compressed {
+        res.headers_mut()
+            .insert(header::VARY, HeaderValue::from_static("accept-encoding"));
+    }
+    res
+}
+
+/// Returns the file to serve for `path`, descending into `index` for directories.
+async fn resolve(path: PathBuf, index: &str) -> Option<PathBuf> {
+    let meta = tokio::fs::metadata(&path).await.ok()?;
+    if meta.is_file() {
+        return Some(path);
+    }
+    let index = path.join(index);
+    match tokio::fs::metadata(&index).await {
+        Ok(meta) if meta.is_file() => Some(index),
+        _ => None,
+    }
+}
+
+/// Opens `path`, preferring a precompressed sibling the client accepts.
+async fn open(
+    req: &HttpRequest,
+    path: &Path,
+    precompressed: bool,
+) -> std::io::Result<(NamedFile, bool)> {
+    if precompressed {
+        let accept = req
+            .headers()
+            .get(header::ACCEPT_ENCODING)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+
+        for (coding, ext, encoding) in PRECOMPRESSED {
+            if !accepts_encoding(accept, coding) {
+                continue;
+            }
+            let mut candidate = path.as_os_str().to_owned();
+            candidate.push(".");
+            candidate.push(ext);
+            if let Ok(file) = NamedFile::open_async(&candidate).await {
+                let original_ext = path.extension().and_then(|e| e.to_str()).unwrap_or_default();
+                let file = file
+                    .set_content_type(actix_files::file_extension_to_mime(original_ext))
+                    .disable_content_disposition()
+                    .set_content_encoding(encoding);
+                return Ok((file, true));
+            }
+        }
+    }
+    Ok((NamedFile::open_async(path).await?, false))
+}
+
+fn accepts_encoding(accept: &str, coding: &str) -> bool {
+    accept.split(',').any(|item| {
+        let mut parts = item.split(';').map(str::trim);
+        parts.next() == Some(coding)
+            && !parts.any(|p| {
+                p.strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q == 0.0)
+            })
+    })
+}
+
+/// Turns the unmatched part of the request path into a relative path, rejecting traversal and
+/// hidden files.
+fn sanitize(tail: &str) -> Option<PathBuf> {
+    let decoded = urlencoding::decode(tail).ok()?;
+    let mut path = PathBuf::new();
+    for segment in decoded.split('/') {
+        if segment.is_empty() || segment == "." {
+            continue;
+        }
+        if segment.starts_with('.') || segment.contains(['\\', '\0']) {
+            return None;
+        }
+        path.push(segment);
+    }
+    Some(path)
+}
+
+/// Mounts every `[[web.static]]` entry. Must run after the application's own services so API
+/// routes and the ws path take precedence.
+pub fn configure_static(cfg: &mut web::ServiceConfig, statics: &[StaticFiles]) {
+    let mut statics: Vec<&StaticFiles> = statics.iter().collect();
+    // 更具体的挂载路径优先匹配
+    statics.sort_by_key(|s| std::cmp::Reverse(s.path.trim_end_matches('/').len()));
+
+    for conf in statics {
+        logging::info!("[static] mounting {} at {}", conf.directory, conf.path);
+        cfg.service(
+            web::scope(conf.path.trim_end_matches('/'))
+                .app_data(web::Data::new(conf.clone()))
+                .default_service(web::to(serve)),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::App;
+    use actix_web::test::{TestRequest, call_service, init_service, read_body};
+    use std::sync::OnceLock;
+
+    #[test]
+    fn traversal_and_hidden_files_are_rejected() {
+        assert_eq!(sanitize("a/b.js"), Some(PathBuf::from("a/b.js")));
+        assert_eq!(sanitize("//a/./b.js"), Some(PathBuf::from("a/b.js")));
+        assert_eq!(sanitize(""), Some(PathBuf::new()));
+        for tail in [
+            "../secret",
+            "a/../../secret",
+            "%2e%2e/secret",
+            "a/.env",
+            "a\\b",
+            "a%00",
+        ] {
+            assert_eq!(sanitize(tail), None, "{tail}");
+        }
+    }
+
+    #[test]
+    fn accepted_encodings() {
+        assert!(accepts_encoding("gzip, br", "br"));
+        assert!(accepts_encoding("br;q=0.5", "br"));
+        assert!(!accepts_encoding("br;q=0, gzip", "br"));
+        assert!(!accepts_encoding("*", "br"));
+    }
+
+    /// A SPA with precompressed assets, written once for all tests.
+    fn site() -> &'static Path {
+        static SITE: OnceLock<PathBuf> = OnceLock::new();
+        SITE.get_or_init(|| {
+            let dir = std::env::temp_dir().join(format!("jieto-static-{}", std::process::id()));
+            std::fs::create_dir_all(dir.join("docs")).unwrap();
+            for (name, content) in [
+                ("index.html", "index"),
+                ("app.js", "js"),
+                ("app.js.br", "br-js"),
+                ("app.js.gz", "gz-js"),
+                ("docs/index.html", "docs"),
+            ] {
+                std::fs::write(dir.join(name), content).unwrap();
+            }
+            dir
+        })
+    }
+
+    /// Status, `Content-Encoding` and body of `GET uri`.
+    async fn get(uri: &str, accept_encoding: Option<&str>) -> (u16, Option<String>, String) {
+        let mut conf: StaticFiles =
+            toml::from_str("path = \"/\"\ndirectory = \"\"\nspa = true\nprecompressed = true")
+                .unwrap();
+        conf.directory = site().to_string_lossy().into_owned();
+
+        let app = init_service(App::new().configure(|cfg| configure_static(cfg, &[conf]))).await;
+        let mut req = TestRequest::get().uri(uri);
+        if let Some(accept) = accept_encoding {
+            req = req.insert_header((header::ACCEPT_ENCODING, accept));
+        }
+        let res = call_service(&app, req.to_request()).await;
+        let status = res.status().as_u16();
+        let encoding = res
+            .headers()
+            .get(header::CONTENT_ENCODING)
+            .map(|v| v.to_str().unwrap().to_string());
+        let body = String::from_utf8(read_body(res).await.to_vec()).unwrap();
+        (status, encoding, body)
+    }
+
+    #[actix_web::test]
+    async fn unknown_paths_fall_back_to_the_index_unless_they_are_assets() {
+        assert_eq!(get("/app.js", None).await, (200, None, String::from("js")));
+        assert_eq!(get("/docs/", None).await.2, "docs");
+        assert_eq!(get("/users/42", None).await.2, "index");
+        assert_eq!(get("/missing.js", None).await.0, 404);
+        assert_eq!(get("/%2e%2e/secret", None).await.0, 404);
+    }
+
+    #[actix_web::test]
+    async fn precompressed_files_are_served_when_accepted() {
+        let (_, encoding, body) = get("/app.js", Some("gzip")).await;
+        assert_eq!(
+            (encoding.as_deref(), body.as_str()),
+            (Some("gzip"), "gz-js")
+        );
+        let (_, encoding, body) = get("/app.js", Some("gzip, br")).await;
+        assert_eq!((encoding.as_deref(), body.as_str()), (Some("br"), "br-js"));
+        let (_, encoding, body) = get("/app.js", Some("br;q=0, gzip")).await;
+        assert_eq!(
+            (encoding.as_deref(), body.as_str()),
+            (Some("gzip"), "gz-js")
+        );
+        let (_, encoding, body) = get("/app.js", Some("deflate")).await;
+        assert_eq!((encoding, body.as_str()), (None, "js"));
+    }
+}
@@ -0,0 +1,408 @@
+//! OpenTelemetry tracing, enabled by the `tracing` feature and `[tracing] enabled = true`.
+//!
+//! Spans are recorded through the `tracing` crate: HTTP requests (`middleware::trace`), scheduled
+//! task runs and websocket sessions. sqlx query events inside a span become its child spans.
+//!
+//! With the feature the framework logs through `tracing` as well: its events are attached to the
+//! current span and forwarded to the logger by [`LogForwarder`], or emitted as `log` records while
+//! no subscriber is installed. Records of other crates written with `log` inside a span are
+//! attached to it as events.
+
+use crate::config::{TraceExporter, Tracing};
+use opentelemetry::KeyValue;
+use opentelemetry::trace::{
+    Span as _, SpanKind, Status, TraceContextExt, Tracer as _, TracerProvider as _,
+};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::error::OTelSdkResult;
+use opentelemetry_sdk::trace::{Sampler, SdkTracer, SdkTracerProvider, SpanData, SpanExporter};
+use std::cell::Cell;
+use std::fmt::{self, Write as _};
+use std::time::{Duration, SystemTime};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::Layer;
+use tracing_subscriber::filter::filter_fn;
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Target of the span summaries written by the `log` exporter.
+pub(crate) const TRACE_TARGET: &str = "jieto::trace";
+
+const SQLX_TARGET: &str = "sqlx::query";
+
+/// Targets that are never exported as spans: sqlx queries are exported by [`SqlSpans`], and the
+/// OTLP client's own instrumentation would otherwise trace every export.
+const UNEXPORTED_TARGETS: [&str; 6] = [
+    SQLX_TARGET,
+    "opentelemetry",
+    "reqwest",
+    "hyper",
+    "h2",
+    "tower",
+];
+
+thread_local! {
+    // 正在把 tracing 事件转发给 log，该事件已由 OpenTelemetry 层记录
+    static FORWARDING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Keeps the tracer provider alive until the application stops.
+pub(crate) struct TracingGuard {
+    provider: SdkTracerProvider,
+}
+
+impl TracingGuard {
+    /// Exports the spans still buffered.
+    pub(crate) fn shutdown(self) {
+        if let Err(e) = self.provider.shutdown() {
+            tracing::warn!("[tracing] failed to export pending spans: {}", e);
+        }
+    }
+}
+
+/// Installs the global `tracing` subscriber exporting spans as configured by `[tracing]`.
+pub(crate) fn init(config: &Tracing, app_name: &str) -> anyhow::Result<Option<TracingGuard>> {
+    if !config.enabled {
+        return Ok(None);
+    }
+
+    let service_name = config
+        .service_name
+        .clone()
+        .unwrap_or_else(|| app_name.to_string());
+    let builder = SdkTracerProvider::builder()
+        .with_resource(Resource::builder().with_service_name(service_name).build())
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            config.sample_ratio,
+        ))));
+    let provider = match config.exporter {
+        TraceExporter::Otlp => {
+            let exporter = opentelemetry_otlp::SpanExporter::builder()
+                .with_http()
+                .with_endpoint(config.endpoint.clone())
+                .build()?;
+            builder.with_batch_exporter(exporter).build()
+        }
+        TraceExporter::Log => builder.with_batch_exporter(LogExporter).build(),
+    };
+
+    let tracer = provider.tracer("jieto");
+    let subscriber = tracing_subscriber::registry()
+        .with(
+            tracing_opentelemetry::layer()
+                .with_tracer(tracer.clone())
+                .with_filter(filter_fn(|metadata| {
+                    !UNEXPORTED_TARGETS
+                        .iter()
+                        .any(|target| metadata.target().starts_with(target))
+                })),
+        )
+        .with(SqlSpans { tracer })
+        .with(LogForwarder);
+    tracing::subscriber::set_global_default(subscriber)?;
+
+    match config.exporter {
+        TraceExporter::Otlp => tracing::info!("[tracing] exporting spans to {}", config.endpoint),
+        TraceExporter::Log => tracing::info!("[tracing] writing spans to the log"),
+    }
+    Ok(Some(TracingGuard { provider }))
+}
+
+/// Trace id of the current span, if any.
+pub(crate) fn current_trace_id() -> Option<String> {
+    let span = tracing::Span::current();
+    if span.is_none() {
+        return None;
+    }
+    let cx = span.context();
+    let span_context = cx.span().span_context().clone();
+    span_context
+        .is_valid()
+        .then(|| span_context.trace_id().to_string())
+}
+
+/// Attaches a log record to the current span as a `log` event.
+pub(crate) fn record_log(record: &log::Record) {
+    if FORWARDING.get() {
+        return;
+    }
+    let span = tracing::Span::current();
+    if span.is_none() {
+        return;
+    }
+    span.add_event(
+        "log",
+        vec![
+            KeyValue::new("level", record.level().as_str()),
+            KeyValue::new("target", record.target().to_string()),
+            KeyValue::new("message", record.args().to_string()),
+        ],
+    );
+}
+
+/// Turns sqlx `sqlx::query` events into client spans of the span the query ran in.
+struct SqlSpans {
+    tracer: SdkTracer,
+}
+
+#[derive(Default)]
+struct QueryFields {
+    summary: String,
+    statement: String,
+    rows_affected: u64,
+    rows_returned: u64,
+    elapsed_secs: f64,
+}
+
+impl Visit for QueryFields {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        if field.name() == "elapsed_secs" {
+            self.elapsed_secs = value;
+        }
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        match field.name() {
+            "rows_affected" => self.rows_affected = value,
+            "rows_returned" => self.rows_returned = value,
+            _ => {}
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match field.name() {
+            "summary" => self.summary = value.to_string(),
+            "db.statement" => self.statement = value.trim().to_string(),
+            _ => {}
+        }
+    }
+
+    fn record_debug(&mut self, _field: &Field, _value: &dyn fmt::Debug) {}
+}
+
+impl<S> Layer<S> for SqlSpans
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        if event.metadata().target() != SQLX_TARGET {
+            return;
+        }
+        // 只记录请求、任务等 span 内的查询
+        let current = tracing::Span::current();
+        if current.is_none() {
+            return;
+        }
+        let mut query = QueryFields::default();
+        event.record(&mut query);
+
+        // sqlx 在查询结束后才记录事件，开始时间由耗时倒推
+        let end = SystemTime::now();
+        let start = end
+            .checked_sub(Duration::from_secs_f64(query.elapsed_secs))
+            .unwrap_or(end);
+        // 短语句只有 summary，完整语句为空
+        let statement = if query.statement.is_empty() {
+            query.summary.clone()
+        } else {
+            query.statement
+        };
+
+        let name = query.summary.trim_end_matches(" …").to_string();
+
+        let parent = current.context();
+        let mut span = self
+            .tracer
+            .span_builder(name)
+            .with_kind(SpanKind::Client)
+            .with_start_time(start)
+            .with_attributes([
+                KeyValue::new("db.query.text", statement),
+                KeyValue::new("db.response.returned_rows", query.rows_returned as i64),
+                KeyValue::new("db.response.affected_rows", query.rows_affected as i64),
+            ])
+            .start_with_context(&self.tracer, &parent);
+        span.end_with_timestamp(end);
+    }
+}
+
+/// Writes `tracing` events to the `log` logger, as they were before the subscriber was installed.
+struct LogForwarder;
+
+#[derive(Default)]
+struct EventMessage {
+    message: String,
+    fields: String,
+}
+
+impl Visit for EventMessage {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            let _ = write!(self.fields, " {}={}", field.name(), value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{:?}", value);
+        } else {
+            let _ = write!(self.fields, " {}={:?}", field.name(), value);
+        }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for LogForwarder {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let level = match *metadata.level() {
+            tracing::Level::ERROR => log::Level::Error,
+            tracing::Level::WARN => log::Level::Warn,
+            tracing::Level::INFO => log::Level::Info,
+            tracing::Level::DEBUG => log::Level::Debug,
+            tracing::Level::TRACE => log::Level::Trace,
+        };
+        let log_metadata = log::Metadata::builder()
+            .level(level)
+            .target(metadata.target())
+            .build();
+        let logger = log::logger();
+        if !logger.enabled(&log_metadata) {
+            return;
+        }
+
+        let mut message = EventMessage::default();
+        event.record(&mut message);
+        FORWARDING.set(true);
+        logger.log(
+            &log::Record::builder()
+                .metadata(log_metadata)
+                .args(format_args!("{}{}", message.message, message.fields))
+                .module_path(metadata.module_path())
+                .file(metadata.file())
+                .line(metadata.line())
+                .build(),
+        );
+        FORWARDING.set(false);
+    }
+}
+
+/// Writes finished spans to the application log under [`TRACE_TARGET`].
+#[derive(Debug)]
+struct LogExporter;
+
+impl SpanExporter for LogExporter {
+    async fn export(&self, batch: Vec<SpanData>) -> OTelSdkResult {
+        for span in batch {
+            tracing::info!(target: TRACE_TARGET, "{}", SpanSummary(&span));
+        }
+        Ok(())
+    }
+}
+
+struct SpanSummary<'a>(&'a SpanData);
+
+impl fmt::Display for SpanSummary<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let span = self.0;
+        let duration = span
+            .end_time
+            .duration_since(span.start_time)
+            .unwrap_or_default();
+        write!(
+            f,
+            "{} trace_id={} span_id={} parent_id={} duration={:.3}ms",
+            span.name,
+            span.span_context.trace_id(),
+            span.span_context.span_id(),
+            span.parent_span_id,
+            duration.as_secs_f64() * 1000.0
+        )?;
+        if let Status::Error { description } = &span.status {
+            write!(f, " error={:?}", description)?;
+        }
+        for attribute in &span.attributes {
+            write!(f, " {}={}", attribute.key, attribute.value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Keeps the exported spans in memory.
+    #[derive(Debug, Clone, Default)]
+    pub(crate) struct MemoryExporter(Arc<Mutex<Vec<SpanData>>>);
+
+    impl MemoryExporter {
+        pub(crate) fn spans(&self) -> Vec<SpanData> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    impl SpanExporter for MemoryExporter {
+        async fn export(&self, batch: Vec<SpanData>) -> OTelSdkResult {
+            self.0.lock().unwrap().extend(batch);
+            Ok(())
+        }
+    }
+
+    /// A subscriber exporting every span as soon as it ends.
+    pub(crate) fn subscriber() -> (impl Subscriber + Send + Sync, MemoryExporter) {
+        let exporter = MemoryExporter::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("jieto")));
+        (subscriber, exporter)
+    }
+
+    fn record(message: fmt::Arguments<'_>, f: impl FnOnce(&log::Record)) {
+        f(&log::Record::builder()
+            .level(log::Level::Warn)
+            .target("app")
+            .args(message)
+            .build());
+    }
+
+    #[test]
+    fn log_records_are_attached_to_the_current_span() {
+        let (subscriber, exporter) = subscriber();
+        tracing::subscriber::with_default(subscriber, || {
+            // span 外的日志不记录
+            record(format_args!("outside"), record_log);
+            let span = tracing::info_span!("task");
+            let _entered = span.enter();
+            assert!(current_trace_id().is_some());
+            record(format_args!("inside"), record_log);
+            // 由 LogForwarder 转发的事件已记录过
+            FORWARDING.set(true);
+            record(format_args!("forwarded"), record_log);
+            FORWARDING.set(false);
+        });
+
+        let spans = exporter.spans();
+        assert_eq!(spans.len(), 1);
+        let events: Vec<_> = spans[0].events.iter().collect();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].name, "log");
+        let attribute = |key: &str| {
+            events[0]
+                .attributes
+                .iter()
+                .find(|kv| kv.key.as_str() == key)
+                .map(|kv| kv.value.to_string())
+        };
+        assert_eq!(attribute("level").as_deref(), Some("WARN"));
+        assert_eq!(attribute("target").as_deref(), Some("app"));
+        assert_eq!(attribute("message").as_deref(), Some("inside"));
+    }
+}
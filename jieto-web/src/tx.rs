@@ -0,0 +1,246 @@
+//! Request scoped sqlx transactions.
+//!
+//! [`Tx`] and [`NamedTx`] begin a transaction when extracted. The transaction is committed once
+//! the handler returned a successful response and rolled back when it returned an error (including
+//! `WebError::Business`) or panicked.
+//!
+//! ```ignore
+//! #[post("/orders")]
+//! async fn create(mut tx: Tx<MySql>, order: web::Json<Order>) -> JietoResult<()> {
+//!     sqlx::query("INSERT INTO orders (sku) VALUES (?)")
+//!         .bind(&order.sku)
+//!         .execute(&mut *tx)
+//!         .await?;
+//!     ApiResult::ok(())
+//! }
+//! ```
+//!
+//! Outside handlers, [`transactional`] wraps a function body in a transaction bound to `tx`.
+
+use crate::error::WebError;
+use crate::extract::DataSourceName;
+use crate::logging;
+use actix_web::dev::Payload;
+use actix_web::{FromRequest, HttpMessage, HttpRequest, web};
+use futures_util::future::LocalBoxFuture;
+use jieto_db::database::DataSourcePool;
+use jieto_db::error::DbError;
+use sqlx::{Database, Pool, Transaction};
+use std::cell::RefCell;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
+
+pub use jieto_macros::transactional;
+#[cfg(feature = "mysql")]
+pub use sqlx::MySql;
+#[cfg(feature = "postgres")]
+pub use sqlx::Postgres;
+#[cfg(feature = "sqlite")]
+pub use sqlx::Sqlite;
+
+type Finalize = Box<dyn FnOnce(bool) -> LocalBoxFuture<'static, Result<(), sqlx::Error>>>;
+
+/// Transactions handed back by extracted [`Tx`] values, finished by
+/// `middleware::transaction::TransactionFinalizer` once the response is known.
+#[derive(Clone, Default)]
+pub(crate) struct PendingTransactions(Rc<RefCell<Vec<Finalize>>>);
+
+impl PendingTransactions {
+    fn of(req: &HttpRequest) -> Self {
+        req.extensions_mut()
+            .get_or_insert_with(PendingTransactions::default)
+            .clone()
+    }
+
+    /// Commits or rolls back every pending transaction, returning the first error.
+    pub(crate) async fn finish(self, commit: bool) -> Result<(), sqlx::Error> {
+        let pending = std::mem::take(&mut *self.0.borrow_mut());
+        let mut result = Ok(());
+        for finalize in pending {
+            if let Err(e) = finalize(commit).await {
+                logging::error!("[tx] failed to finish transaction: {}", e);
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+        result
+    }
+}
+
+/// Begins a transaction on the default data source of `DB`, or the one named `datasource`.
+///
+/// Used by [`transactional`], handlers usually extract [`Tx`] instead.
+pub async fn begin<DB>(datasource: Option<&str>) -> Result<Transaction<'static, DB>, WebError>
+where
+    DB: Database,
+    Pool<DB>: DataSourcePool,
+{
+    let manager = crate::GLOBAL_DBMANAGER
+        .get()
+        .ok_or_else(|| DbError::PoolNotConfigured(datasource.unwrap_or("default").into()))?;
+    let pool = match datasource {
+        Some(name) => Pool::<DB>::from_named(manager, name)?,
+        None => Pool::<DB>::from_default(manager)?,
+    };
+    Ok(pool.begin().await?)
+}
+
+/// Commits `tx`, or rolls it back when `commit` is false.
+///
+/// Rollback failures are only logged, the database discards the transaction with the connection.
+pub async fn finish<DB: Database>(
+    tx: Transaction<'static, DB>,
+    commit: bool,
+) -> Result<(), WebError> {
+    if commit {
+        tx.commit().await?;
+    } else if let Err(e) = tx.rollback().await {
+        logging::error!("[tx] rollback failed: {}", e);
+    }
+    Ok(())
+}
+
+async fn begin_for_request<DB>(
+    req: &HttpRequest,
+    datasource: Option<&str>,
+) -> Result<Tx<DB>, WebError>
+where
+    DB: Database,
+    Pool<DB>: DataSourcePool,
+{
+    let manager = req
+        .app_data::<web::Data<crate::AppState>>()
+        .map(|state| state.db_manager.clone())
+        .ok_or_else(|| DbError::PoolNotConfigured(datasource.unwrap_or("default").into()))?;
+    let pool = match datasource {
+        Some(name) => Pool::<DB>::from_named(&manager, name)?,
+        None => Pool::<DB>::from_default(&manager)?,
+    };
+    let tx = pool.begin().await?;
+    Ok(Tx {
+        tx: Some(tx),
+        pending: PendingTransactions::of(req),
+    })
+}
+
+/// A transaction on the default data source of `DB`, e.g. `Tx<MySql>`.
+///
+/// Dereferences to the connection, so queries run with `.execute(&mut *tx)`.
+pub struct Tx<DB: Database> {
+    tx: Option<Transaction<'static, DB>>,
+    pending: PendingTransactions,
+}
+
+impl<DB: Database> Tx<DB> {
+    /// Commits now instead of when the response is known.
+    pub async fn commit(mut self) -> Result<(), WebError> {
+        // unwrap: only taken here, in `rollback` and on drop
+        Ok(self.tx.take().unwrap().commit().await?)
+    }
+
+    /// Rolls back now instead of when the response is known.
+    pub async fn rollback(mut self) -> Result<(), WebError> {
+        // unwrap: only taken here, in `commit` and on drop
+        Ok(self.tx.take().unwrap().rollback().await?)
+    }
+}
+
+impl<DB: Database> Deref for Tx<DB> {
+    type Target = DB::Connection;
+
+    fn deref(&self) -> &Self::Target {
+        // unwrap: present until the Tx is consumed
+        self.tx.as_ref().unwrap()
+    }
+}
+
+impl<DB: Database> DerefMut for Tx<DB> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        // unwrap: present until the Tx is consumed
+        self.tx.as_mut().unwrap()
+    }
+}
+
+impl<DB: Database> Drop for Tx<DB> {
+    fn drop(&mut self) {
+        let Some(tx) = self.tx.take() else {
+            return;
+        };
+        // 处理函数 panic 时直接丢弃，由 sqlx 回滚
+        if std::thread::panicking() {
+            return;
+        }
+        self.pending.0.borrow_mut().push(Box::new(move |commit| {
+            Box::pin(async move {
+                if commit {
+                    tx.commit().await
+                } else {
+                    tx.rollback().await
+                }
+            })
+        }));
+    }
+}
+
+impl<DB> FromRequest for Tx<DB>
+where
+    DB: Database,
+    Pool<DB>: DataSourcePool,
+{
+    type Error = WebError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move { begin_for_request(&req, None).await })
+    }
+}
+
+/// A transaction on the data source named by `N`, e.g. `NamedTx<Reporting, Postgres>`.
+pub struct NamedTx<N, DB: Database> {
+    tx: Tx<DB>,
+    _name: PhantomData<N>,
+}
+
+impl<N, DB: Database> NamedTx<N, DB> {
+    pub fn into_inner(self) -> Tx<DB> {
+        self.tx
+    }
+}
+
+impl<N, DB: Database> Deref for NamedTx<N, DB> {
+    type Target = DB::Connection;
+
+    fn deref(&self) -> &Self::Target {
+        &self.tx
+    }
+}
+
+impl<N, DB: Database> DerefMut for NamedTx<N, DB> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.tx
+    }
+}
+
+impl<N, DB> FromRequest for NamedTx<N, DB>
+where
+    N: DataSourceName,
+    DB: Database,
+    Pool<DB>: DataSourcePool,
+{
+    type Error = WebError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        Box::pin(async move {
+            let tx = begin_for_request(&req, Some(N::NAME)).await?;
+            Ok(NamedTx {
+                tx,
+                _name: PhantomData,
+            })
+        })
+    }
+}
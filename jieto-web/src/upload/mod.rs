@@ -0,0 +1,635 @@
+//! Multipart uploads and downloads of stored files.
+//!
+//! [`Uploads`] reads a `multipart/form-data` body, enforcing `[web.upload]`, and streams every
+//! file to the [`FileStorage`] before the handler runs:
+//!
+//! ```ignore
+//! #[post("/avatar")]
+//! async fn avatar(uploads: Uploads) -> JietoResult<Vec<StoredFile>> {
+//!     ApiResult::ok(uploads.files)
+//! }
+//! ```
+//!
+//! Stored files are served with `Range` support by [`download`], which is also mounted under
+//! `download_path` when configured. Only raster images and PDF are displayed inline, other types
+//! are sent as attachments so that uploaded HTML or SVG never runs on the application's origin.
+
+mod storage;
+
+pub use storage::{FileStorage, FileWriter, LocalStorage, NewFile};
+
+use crate::config::Upload;
+use crate::error::WebError;
+use crate::logging;
+use actix_multipart::{Field, Multipart};
+use actix_web::dev::Payload;
+use actix_web::http::StatusCode;
+use actix_web::http::header::{
+    self, ContentDisposition, DispositionParam, DispositionType, HeaderValue,
+};
+use actix_web::{FromRequest, HttpRequest, HttpResponse, mime, web};
+use futures_util::StreamExt as _;
+use futures_util::future::LocalBoxFuture;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io;
+use std::ops::Range;
+use std::sync::Arc;
+
+/// Bytes buffered before the content type is sniffed.
+const SNIFF_LEN: usize = 512;
+
+/// Types a browser cannot execute scripts from, served inline by [`download`].
+const INLINE_TYPES: [&str; 7] = [
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "image/avif",
+    "image/bmp",
+    "application/pdf",
+];
+
+/// Upload settings and storage, registered as app data.
+#[derive(Clone)]
+pub(crate) struct Uploader {
+    config: Arc<Upload>,
+    storage: Arc<dyn FileStorage>,
+}
+
+impl Uploader {
+    pub(crate) fn new(config: Upload, storage: Option<Arc<dyn FileStorage>>) -> Self {
+        let storage = storage.unwrap_or_else(|| Arc::new(LocalStorage::new(&config.directory)));
+        Self {
+            config: Arc::new(config),
+            storage,
+        }
+    }
+
+    /// Mounts `GET <download_path>/{key}` when a download path is configured.
+    pub(crate) fn configure(&self, cfg: &mut web::ServiceConfig) {
+        if let Some(path) = &self.config.download_path {
+            let pattern = format!("{}/{{key:.*}}", path.trim_end_matches('/'));
+            cfg.service(web::resource(pattern).route(web::get().to(serve)));
+        }
+    }
+
+    fn url(&self, key: &str) -> Option<String> {
+        self.config
+            .download_path
+            .as_ref()
+            .map(|path| format!("{}/{}", path.trim_end_matches('/'), key))
+    }
+
+    fn allowed(&self, content_type: &str) -> bool {
+        let types = &self.config.allowed_types;
+        types.is_empty()
+            || types
+                .iter()
+                .any(|allowed| match allowed.strip_suffix("/*") {
+                    Some(prefix) => content_type
+                        .split_once('/')
+                        .is_some_and(|(kind, _)| kind.eq_ignore_ascii_case(prefix)),
+                    None => allowed.eq_ignore_ascii_case(content_type),
+                })
+    }
+}
+
+/// Metadata of a stored file, returned to clients in `ApiResult`.
+#[derive(Debug, Clone, Serialize)]
+pub struct StoredFile {
+    /// Form field the file was sent in.
+    pub field: String,
+    /// Name sent by the client.
+    pub filename: String,
+    /// Storage key, used to download or delete the file.
+    pub key: String,
+    pub size: u64,
+    /// Type sniffed from the content, not the one declared by the client.
+    pub content_type: String,
+    /// Download URL when `download_path` is configured.
+    pub url: Option<String>,
+}
+
+/// The files and text fields of a `multipart/form-data` request.
+///
+/// Files are already stored when the handler runs. When a limit is exceeded or a type is not
+/// allowed the request fails with 413 / 415 and the files stored so far are deleted.
+#[derive(Debug, Default)]
+pub struct Uploads {
+    pub files: Vec<StoredFile>,
+    pub fields: HashMap<String, String>,
+}
+
+impl Uploads {
+    /// The first file sent in `field`.
+    pub fn file(&self, field: &str) -> Option<&StoredFile> {
+        self.files.iter().find(|file| file.field == field)
+    }
+
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields.get(name).map(String::as_str)
+    }
+}
+
+impl FromRequest for Uploads {
+    type Error = WebError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let uploader = req.app_data::<Uploader>().cloned();
+        let multipart = Multipart::new(req.headers(), payload.take());
+        Box::pin(async move {
+            let uploader = uploader.ok_or_else(|| {
+                WebError::Http(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    String::from("upload not configured"),
+                )
+            })?;
+            let mut uploads = Uploads::default();
+            match read_multipart(&uploader, multipart, &mut uploads).await {
+                Ok(()) => Ok(uploads),
+                Err(e) => {
+                    // 请求失败时删除本次已保存的文件
+                    for file in &uploads.files {
+                        if let Err(e) = uploader.storage.delete(&file.key).await {
+                            logging::warn!("[upload] failed to delete {}: {}", file.key, e);
+                        }
+                    }
+                    Err(e)
+                }
+            }
+        })
+    }
+}
+
+fn too_large(msg: String) -> WebError {
+    WebError::Http(StatusCode::PAYLOAD_TOO_LARGE, msg)
+}
+
+fn storage_error(e: io::Error) -> WebError {
+    logging::error!("[upload] storage error: {}", e);
+    WebError::Http(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        String::from("failed to store file"),
+    )
+}
+
+async fn read_multipart(
+    uploader: &Uploader,
+    mut multipart: Multipart,
+    uploads: &mut Uploads,
+) -> Result<(), WebError> {
+    let config = &uploader.config;
+    let mut total = 0;
+
+    while let Some(field) = multipart.next().await {
+        let mut field =
+            field.map_err(|e| WebError::Http(StatusCode::BAD_REQUEST, e.to_string()))?;
+        let name = field.name().unwrap_or_default().to_string();
+        let filename = field
+            .content_disposition()
+            .and_then(|cd| cd.get_filename())
+            .map(str::to_string);
+
+        let Some(filename) = filename else {
+            let mut value = Vec::new();
+            while let Some(chunk) = next_chunk(&mut field).await? {
+                total += chunk.len();
+                if total > config.max_total_size {
+                    return Err(too_large(format!(
+                        "upload exceeds {} bytes",
+                        config.max_total_size
+                    )));
+                }
+                value.extend_from_slice(&chunk);
+            }
+            let value = String::from_utf8(value).map_err(|_| {
+                WebError::Http(
+                    StatusCode::BAD_REQUEST,
+                    format!("field '{name}' is not UTF-8"),
+                )
+            })?;
+            uploads.fields.insert(name, value);
+            continue;
+        };
+
+        if uploads.files.len() >= config.max_files {
+            return Err(too_large(format!(
+                "at most {} files can be uploaded",
+                config.max_files
+            )));
+        }
+        let file = store_file(uploader, &mut field, name, filename, &mut total).await?;
+        uploads.files.push(file);
+    }
+    Ok(())
+}
+
+async fn next_chunk(field: &mut Field) -> Result<Option<web::Bytes>, WebError> {
+    field
+        .next()
+        .await
+        .transpose()
+        .map_err(|e| WebError::Http(StatusCode::BAD_REQUEST, e.to_string()))
+}
+
+async fn store_file(
+    uploader: &Uploader,
+    field: &mut Field,
+    name: String,
+    filename: String,
+    total: &mut usize,
+) -> Result<StoredFile, WebError> {
+    let config = &uploader.config;
+    let mut size = 0;
+    let mut count = |len: usize| {
+        size += len;
+        *total += len;
+        if size > config.max_file_size {
+            Err(too_large(format!(
+                "file '{}' exceeds {} bytes",
+                filename, config.max_file_size
+            )))
+        } else if *total > config.max_total_size {
+            Err(too_large(format!(
+                "upload exceeds {} bytes",
+                config.max_total_size
+            )))
+        } else {
+            Ok(())
+        }
+    };
+
+    // 先缓冲文件头用于识别类型，不允许的类型不会写入存储
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    let mut pending = None;
+    let mut complete = false;
+    while head.len() < SNIFF_LEN {
+        let Some(chunk) = next_chunk(field).await? else {
+            complete = true;
+            break;
+        };
+        count(chunk.len())?;
+        let take = chunk.len().min(SNIFF_LEN - head.len());
+        head.extend_from_slice(&chunk[..take]);
+        if take < chunk.len() {
+            pending = Some(chunk.slice(take..));
+        }
+    }
+
+    let (content_type, extension) = sniff(&head, &filename);
+    if !uploader.allowed(&content_type) {
+        return Err(WebError::Http(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            format!("file type '{content_type}' is not allowed"),
+        ));
+    }
+
+    let mut writer = uploader
+        .storage
+        .create(NewFile {
+            filename: &filename,
+            content_type: &content_type,
+            extension: extension.as_deref(),
+        })
+        .await
+        .map_err(storage_error)?;
+
+    let written = async {
+        writer.write(&head).await.map_err(storage_error)?;
+        if let Some(chunk) = pending {
+            writer.write(&chunk).await.map_err(storage_error)?;
+        }
+        // 字段结束后不能再读取
+        while !complete && let Some(chunk) = next_chunk(field).await? {
+            count(chunk.len())?;
+            writer.write(&chunk).await.map_err(storage_error)?;
+        }
+        Ok::<_, WebError>(())
+    }
+    .await;
+    if let Err(e) = written {
+        writer.abort().await;
+        return Err(e);
+    }
+    let key = writer.finish().await.map_err(storage_error)?;
+
+    Ok(StoredFile {
+        field: name,
+        url: uploader.url(&key),
+        filename,
+        key,
+        size: size as u64,
+        content_type,
+    })
+}
+
+/// Content type and extension of a file from its first bytes.
+fn sniff(head: &[u8], filename: &str) -> (String, Option<String>) {
+    if let Some(kind) = infer::get(head) {
+        return (
+            kind.mime_type().to_string(),
+            Some(kind.extension().to_string()),
+        );
+    }
+
+    // 无法识别的文本文件（csv、json 等）沿用原扩展名，part 留给未写完的文件
+    let extension = filename
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_ascii_lowercase())
+        .filter(|ext| ext.len() <= 10 && ext.chars().all(|c| c.is_ascii_alphanumeric()))
+        .filter(|ext| ext != "part");
+    let text = match std::str::from_utf8(head) {
+        Ok(_) => true,
+        // 截断在多字节字符中间
+        Err(e) => e.error_len().is_none(),
+    };
+    if text {
+        (String::from("text/plain"), extension)
+    } else {
+        (String::from("application/octet-stream"), None)
+    }
+}
+
+async fn serve(req: HttpRequest, key: web::Path<String>) -> Result<HttpResponse, WebError> {
+    download(&req, &key).await
+}
+
+/// Responds with a stored file, honouring a single `Range: bytes=...` request header.
+pub async fn download(req: &HttpRequest, key: &str) -> Result<HttpResponse, WebError> {
+    let uploader = req.app_data::<Uploader>().ok_or_else(|| {
+        WebError::Http(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            String::from("upload not configured"),
+        )
+    })?;
+    let not_found = || WebError::Http(StatusCode::NOT_FOUND, String::from("file not found"));
+    let size = match uploader.storage.size(key).await {
+        Ok(size) => size,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(not_found()),
+        Err(e) => return Err(storage_error(e)),
+    };
+
+    let range = req
+        .headers()
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| parse_range(value, size));
+    let (status, range) = match range {
+        Some(Ok(Some(range))) => (StatusCode::PARTIAL_CONTENT, range),
+        Some(Err(())) => {
+            return Ok(HttpResponse::RangeNotSatisfiable()
+                .insert_header((header::CONTENT_RANGE, format!("bytes */{size}")))
+                .finish());
+        }
+        // 未带 Range 或无法处理（如多段范围）时返回完整文件
+        Some(Ok(None)) | None => (StatusCode::OK, 0..size),
+    };
+
+    let body = match uploader.storage.read(key, range.clone()).await {
+        Ok(body) => body,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(not_found()),
+        Err(e) => return Err(storage_error(e)),
+    };
+    let content_type = key
+        .rsplit_once('.')
+        .map(|(_, ext)| actix_files::file_extension_to_mime(ext))
+        .unwrap_or(mime::APPLICATION_OCTET_STREAM);
+
+    let inline = INLINE_TYPES.contains(&content_type.essence_str());
+
+    let mut res = HttpResponse::build(status);
+    res.content_type(content_type)
+        .insert_header((header::ACCEPT_RANGES, HeaderValue::from_static("bytes")))
+        .insert_header((
+            header::X_CONTENT_TYPE_OPTIONS,
+            HeaderValue::from_static("nosniff"),
+        ))
+        .no_chunking(range.end - range.start);
+    if !inline {
+        let filename = key.rsplit('/').next().unwrap_or(key).to_string();
+        res.insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(filename)],
+        });
+    }
+    if status == StatusCode::PARTIAL_CONTENT {
+        res.insert_header((
+            header::CONTENT_RANGE,
+            format!("bytes {}-{}/{}", range.start, range.end - 1, size),
+        ));
+    }
+    Ok(res.streaming(body))
+}
+
+/// Parses a `Range` header against a file of `size` bytes.
+///
+/// `Ok(None)` when the range should be ignored, `Err` when it cannot be satisfied.
+fn parse_range(value: &str, size: u64) -> Result<Option<Range<u64>>, ()> {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return Ok(None);
+    };
+    if spec.contains(',') {
+        return Ok(None);
+    }
+    let Some((start, end)) = spec.trim().split_once('-') else {
+        return Ok(None);
+    };
+    let range = match (start.trim(), end.trim()) {
+        ("", "") => return Ok(None),
+        // 最后 n 个字节
+        ("", suffix) => {
+            let suffix: u64 = suffix.parse().map_err(|_| ())?;
+            if suffix == 0 {
+                return Err(());
+            }
+            size.saturating_sub(suffix)..size
+        }
+        (start, "") => start.parse().map_err(|_| ())?..size,
+        (start, end) => {
+            let start: u64 = start.parse().map_err(|_| ())?;
+            let end: u64 = end.parse().map_err(|_| ())?;
+            if end < start {
+                return Ok(None);
+            }
+            start..end.saturating_add(1).min(size)
+        }
+    };
+    if range.start >= size || range.is_empty() {
+        return Err(());
+    }
+    Ok(Some(range))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::{TestRequest, call_service, init_service};
+    use actix_web::{App, http::header::CONTENT_TYPE};
+    use std::path::{Path, PathBuf};
+
+    const BOUNDARY: &str = "jieto-boundary";
+
+    fn png(len: usize) -> Vec<u8> {
+        let mut data = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".to_vec();
+        data.resize(len, 0);
+        data
+    }
+
+    /// A `multipart/form-data` body with the `(field, filename, content)` files.
+    fn multipart(files: &[(&str, &str, &[u8])]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (field, filename, content) in files {
+            body.extend_from_slice(
+                format!(
+                    "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"{field}\"; \
+                     filename=\"{filename}\"\r\nContent-Type: application/octet-stream\r\n\r\n"
+                )
+                .as_bytes(),
+            );
+            body.extend_from_slice(content);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{BOUNDARY}--\r\n").as_bytes());
+        body
+    }
+
+    /// Files stored under `root`, in any directory.
+    fn stored_files(root: &Path) -> Vec<PathBuf> {
+        let mut files = vec![];
+        let mut directories = vec![root.to_path_buf()];
+        while let Some(directory) = directories.pop() {
+            let Ok(entries) = std::fs::read_dir(directory) else {
+                continue;
+            };
+            for entry in entries {
+                let path = entry.unwrap().path();
+                if path.is_dir() {
+                    directories.push(path);
+                } else {
+                    files.push(path);
+                }
+            }
+        }
+        files
+    }
+
+    /// Posts `body` to a handler extracting [`Uploads`], returning the status and stored files.
+    async fn upload(name: &str, config: Upload, body: Vec<u8>) -> (StatusCode, Vec<PathBuf>) {
+        let root =
+            std::env::temp_dir().join(format!("jieto-upload-{}-{}", name, std::process::id()));
+        let config = Upload {
+            directory: root.to_string_lossy().into_owned(),
+            ..config
+        };
+        let app = init_service(App::new().app_data(Uploader::new(config, None)).route(
+            "/",
+            web::post().to(|uploads: Uploads| async move {
+                HttpResponse::Ok().body(uploads.files.len().to_string())
+            }),
+        ))
+        .await;
+        let req = TestRequest::post()
+            .uri("/")
+            .insert_header((
+                CONTENT_TYPE,
+                format!("multipart/form-data; boundary={BOUNDARY}"),
+            ))
+            .set_payload(body)
+            .to_request();
+        let status = call_service(&app, req).await.status();
+        let files = stored_files(&root);
+        let _ = std::fs::remove_dir_all(&root);
+        (status, files)
+    }
+
+    #[actix_web::test]
+    async fn allowed_files_are_stored() {
+        let body = multipart(&[("a", "a.png", &png(100)), ("b", "b.png", &png(100))]);
+        let (status, files) = upload("stored", Upload::default(), body).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(files.len(), 2);
+        assert!(
+            files
+                .iter()
+                .all(|f| f.extension().is_some_and(|e| e == "png"))
+        );
+    }
+
+    #[actix_web::test]
+    async fn files_over_the_file_limit_are_rejected() {
+        let config = Upload {
+            max_file_size: 600,
+            ..Upload::default()
+        };
+        let body = multipart(&[("a", "a.png", &png(1000))]);
+        let (status, files) = upload("file-limit", config, body).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(files, Vec::<PathBuf>::new());
+    }
+
+    #[actix_web::test]
+    async fn uploads_over_the_total_limit_are_rejected_and_cleaned_up() {
+        let config = Upload {
+            max_total_size: 1500,
+            ..Upload::default()
+        };
+        let body = multipart(&[("a", "a.png", &png(1000)), ("b", "b.png", &png(1000))]);
+        let (status, files) = upload("total-limit", config, body).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        // 第一个文件已保存，请求失败后被删除
+        assert_eq!(files, Vec::<PathBuf>::new());
+    }
+
+    #[actix_web::test]
+    async fn disallowed_types_are_rejected_and_cleaned_up() {
+        // 按内容识别类型，扩展名不起作用
+        let body = multipart(&[
+            ("a", "a.png", &png(100)),
+            ("b", "b.png", b"<script>alert(1)</script>"),
+        ]);
+        let (status, files) = upload("type", Upload::default(), body).await;
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(files, Vec::<PathBuf>::new());
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Ok(Some(0..100)));
+        assert_eq!(parse_range("bytes=900-", 1000), Ok(Some(900..1000)));
+        assert_eq!(parse_range("bytes=900-5000", 1000), Ok(Some(900..1000)));
+        assert_eq!(parse_range("bytes=100-50", 1000), Ok(None));
+        assert_eq!(parse_range("bytes=0-1,5-6", 1000), Ok(None));
+        assert_eq!(parse_range("items=0-1", 1000), Ok(None));
+    }
+
+    #[test]
+    fn ranges_ending_past_the_file() {
+        assert_eq!(parse_range("bytes=0-1000", 1000), Ok(Some(0..1000)));
+        assert_eq!(parse_range("bytes=999-1000000", 1000), Ok(Some(999..1000)));
+        assert_eq!(
+            parse_range("bytes=0-18446744073709551615", 1000),
+            Ok(Some(0..1000))
+        );
+        assert_eq!(
+            parse_range("bytes=18446744073709551615-18446744073709551615", 1000),
+            Err(())
+        );
+    }
+
+    #[test]
+    fn suffix_ranges() {
+        assert_eq!(parse_range("bytes=-100", 1000), Ok(Some(900..1000)));
+        assert_eq!(parse_range("bytes=-5000", 1000), Ok(Some(0..1000)));
+        assert_eq!(parse_range("bytes=-0", 1000), Err(()));
+    }
+
+    #[test]
+    fn unsatisfiable_ranges() {
+        assert_eq!(parse_range("bytes=1000-", 1000), Err(()));
+        assert_eq!(parse_range("bytes=2000-3000", 1000), Err(()));
+        assert_eq!(parse_range("bytes=0-", 0), Err(()));
+        assert_eq!(parse_range("bytes=x-1", 1000), Err(()));
+    }
+}
@@ -0,0 +1,265 @@
+use crate::logging;
+use actix_web::web::Bytes;
+use async_trait::async_trait;
+use futures_util::stream::{self, BoxStream};
+use std::io;
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+const READ_CHUNK: usize = 64 * 1024;
+
+/// A file about to be stored.
+#[derive(Debug, Clone, Copy)]
+pub struct NewFile<'a> {
+    /// Name sent by the client.
+    pub filename: &'a str,
+    /// Type sniffed from the content.
+    pub content_type: &'a str,
+    /// Extension, without the dot, matching the content type when known.
+    pub extension: Option<&'a str>,
+}
+
+/// Receives the content of a file being uploaded.
+#[async_trait]
+pub trait FileWriter: Send {
+    async fn write(&mut self, chunk: &[u8]) -> io::Result<()>;
+
+    /// Completes the file, returning the key it is stored under.
+    async fn finish(self: Box<Self>) -> io::Result<String>;
+
+    /// Discards the partially written file.
+    async fn abort(self: Box<Self>);
+}
+
+/// Where uploaded files are kept, the local filesystem unless registered with
+/// [`Application::storage`](crate::Application::storage).
+#[async_trait]
+pub trait FileStorage: Send + Sync + 'static {
+    async fn create(&self, file: NewFile<'_>) -> io::Result<Box<dyn FileWriter>>;
+
+    /// Size in bytes of a stored file, `NotFound` for unknown keys.
+    async fn size(&self, key: &str) -> io::Result<u64>;
+
+    /// Streams the `range` bytes of a stored file.
+    async fn read(
+        &self,
+        key: &str,
+        range: Range<u64>,
+    ) -> io::Result<BoxStream<'static, io::Result<Bytes>>>;
+
+    async fn delete(&self, key: &str) -> io::Result<()>;
+}
+
+/// Stores files under `<directory>/<yyyy>/<mm>/<dd>/<uuid>.<ext>`.
+#[derive(Debug, Clone)]
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Path of `key`, rejecting keys that would escape the storage directory and the `.part`
+    /// files of uploads still being written.
+    fn path(&self, key: &str) -> io::Result<PathBuf> {
+        let relative = Path::new(key);
+        let valid = !key.is_empty()
+            && !key.ends_with(".part")
+            && relative
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)));
+        if !valid {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "invalid file key"));
+        }
+        Ok(self.root.join(relative))
+    }
+}
+
+struct LocalWriter {
+    file: fs::File,
+    partial: PathBuf,
+    path: PathBuf,
+    key: String,
+}
+
+#[async_trait]
+impl FileWriter for LocalWriter {
+    async fn write(&mut self, chunk: &[u8]) -> io::Result<()> {
+        self.file.write_all(chunk).await
+    }
+
+    async fn finish(mut self: Box<Self>) -> io::Result<String> {
+        self.file.flush().await?;
+        // 写完后再改名，未完成的上传不会以正式文件名出现
+        fs::rename(&self.partial, &self.path).await?;
+        Ok(self.key)
+    }
+
+    async fn abort(self: Box<Self>) {
+        drop(self.file);
+        if let Err(e) = fs::remove_file(&self.partial).await {
+            logging::warn!(
+                "[upload] failed to remove {}: {}",
+                self.partial.display(),
+                e
+            );
+        }
+    }
+}
+
+#[async_trait]
+impl FileStorage for LocalStorage {
+    async fn create(&self, file: NewFile<'_>) -> io::Result<Box<dyn FileWriter>> {
+        let today = time::OffsetDateTime::now_local()
+            .unwrap_or_else(|_| time::OffsetDateTime::now_utc())
+            .date();
+        let directory = format!(
+            "{:04}/{:02}/{:02}",
+            today.year(),
+            u8::from(today.month()),
+            today.day()
+        );
+        let name = match file.extension {
+            Some(extension) => format!("{}.{}", uuid::Uuid::new_v4().simple(), extension),
+            None => uuid::Uuid::new_v4().simple().to_string(),
+        };
+        let key = format!("{directory}/{name}");
+
+        fs::create_dir_all(self.root.join(&directory)).await?;
+        let path = self.root.join(&key);
+        let partial = path.with_file_name(format!("{name}.part"));
+        let handle = fs::File::create(&partial).await?;
+        Ok(Box::new(LocalWriter {
+            file: handle,
+            partial,
+            path,
+            key,
+        }))
+    }
+
+    async fn size(&self, key: &str) -> io::Result<u64> {
+        let metadata = fs::metadata(self.path(key)?).await?;
+        if !metadata.is_file() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "not a file"));
+        }
+        Ok(metadata.len())
+    }
+
+    async fn read(
+        &self,
+        key: &str,
+        range: Range<u64>,
+    ) -> io::Result<BoxStream<'static, io::Result<Bytes>>> {
+        let mut file = fs::File::open(self.path(key)?).await?;
+        file.seek(io::SeekFrom::Start(range.start)).await?;
+        let remaining = range.end.saturating_sub(range.start);
+
+        let chunks = stream::try_unfold((file, remaining), |(mut file, remaining)| async move {
+            if remaining == 0 {
+                return Ok(None);
+            }
+            let mut buf = vec![0; READ_CHUNK.min(remaining as usize)];
+            let read = file.read(&mut buf).await?;
+            if read == 0 {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+            }
+            buf.truncate(read);
+            Ok(Some((Bytes::from(buf), (file, remaining - read as u64))))
+        });
+        Ok(Box::pin(chunks))
+    }
+
+    async fn delete(&self, key: &str) -> io::Result<()> {
+        fs::remove_file(self.path(key)?).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn storage(name: &str) -> (LocalStorage, PathBuf) {
+        let root =
+            std::env::temp_dir().join(format!("jieto-storage-{}-{}", name, std::process::id()));
+        (LocalStorage::new(&root), root)
+    }
+
+    fn png() -> NewFile<'static> {
+        NewFile {
+            filename: "avatar.png",
+            content_type: "image/png",
+            extension: Some("png"),
+        }
+    }
+
+    #[test]
+    fn keys_cannot_escape_the_root() {
+        let storage = LocalStorage::new("/srv/uploads");
+        assert_eq!(
+            storage.path("2024/01/02/a.png").unwrap(),
+            Path::new("/srv/uploads/2024/01/02/a.png")
+        );
+        for key in [
+            "",
+            "../secret",
+            "2024/../../secret",
+            "/etc/passwd",
+            "./a.png",
+            "2024/01/02/a.png.part",
+        ] {
+            let err = storage.path(key).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::NotFound, "{key}");
+        }
+    }
+
+    #[tokio::test]
+    async fn files_are_stored_by_date() {
+        let (storage, root) = storage("layout");
+        let mut writer = storage.create(png()).await.unwrap();
+        writer.write(b"content").await.unwrap();
+        let key = writer.finish().await.unwrap();
+
+        let segments: Vec<&str> = key.split('/').collect();
+        assert_eq!(segments.len(), 4, "{key}");
+        assert_eq!(segments[0].len(), 4);
+        assert_eq!(segments[1].len(), 2);
+        assert_eq!(segments[2].len(), 2);
+        assert!(
+            segments[..3]
+                .iter()
+                .all(|s| s.chars().all(|c| c.is_ascii_digit()))
+        );
+        let (stem, extension) = segments[3].split_once('.').unwrap();
+        assert!(uuid::Uuid::parse_str(stem).is_ok(), "{key}");
+        assert_eq!(extension, "png");
+
+        assert_eq!(storage.size(&key).await.unwrap(), 7);
+        // 完成后不再留下 .part 文件
+        let directory = root.join(segments[..3].join("/"));
+        assert_eq!(std::fs::read_dir(&directory).unwrap().count(), 1);
+        storage.delete(&key).await.unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn aborted_files_are_removed() {
+        let (storage, root) = storage("abort");
+        let mut writer = storage.create(png()).await.unwrap();
+        writer.write(b"partial").await.unwrap();
+        writer.abort().await;
+
+        let mut directories = vec![root.clone()];
+        while let Some(directory) = directories.pop() {
+            for entry in std::fs::read_dir(directory).unwrap() {
+                let path = entry.unwrap().path();
+                assert!(path.is_dir(), "{} was left behind", path.display());
+                directories.push(path);
+            }
+        }
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
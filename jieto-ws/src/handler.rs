@@ -4,6 +4,7 @@ use actix_ws::AggregatedMessage;
 use futures_util::StreamExt as _;
 use tokio::{sync::mpsc, time::interval};
 
+use crate::logging;
 use crate::{WsServerHandle, ConnId};
 
 /// How often heartbeat pings are sent
@@ -15,11 +16,29 @@ const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
 /// Echo text & binary messages received from the client, respond to ping messages, and monitor
 /// connection health to detect network issues and free up resources.
 pub async fn chat_ws(
+    ws_server: WsServerHandle,
+    session: actix_ws::Session,
+    msg_stream: actix_ws::MessageStream,
+) {
+    let run = run_session(ws_server, session, msg_stream);
+    #[cfg(feature = "tracing")]
+    let run = tracing::Instrument::instrument(
+        run,
+        tracing::info_span!(
+            "ws.session",
+            otel.name = "ws session",
+            ws.conn_id = tracing::field::Empty,
+        ),
+    );
+    run.await
+}
+
+async fn run_session(
     ws_server: WsServerHandle,
     mut session: actix_ws::Session,
     msg_stream: actix_ws::MessageStream,
 ) {
-    log::info!("connected");
+    logging::info!("connected");
 
     let mut name: Option<String> = None;
     let mut last_heartbeat = Instant::now();
@@ -29,6 +48,8 @@ pub async fn chat_ws(
 
     // unwrap: chat server is not dropped before the HTTP server
     let conn_id = ws_server.connect(conn_tx).await;
+    #[cfg(feature = "tracing")]
+    tracing::Span::current().record("ws.conn_id", conn_id);
 
     let mut msg_stream = msg_stream
         .max_frame_size(128 * 1024)
@@ -38,7 +59,7 @@ pub async fn chat_ws(
     let close_reason = loop {
         tokio::select! {
             Some(Ok(msg)) = msg_stream.next() => {
-                log::debug!("msg: {msg:?}");
+                logging::debug!("msg: {msg:?}");
 
                 match msg {
                     AggregatedMessage::Ping(bytes) => {
@@ -51,12 +72,22 @@ pub async fn chat_ws(
                     }
 
                     AggregatedMessage::Text(text) => {
-                        process_text_msg(&ws_server, &mut session, &text, conn_id, &mut name)
-                            .await;
+                        let process =
+                            process_text_msg(&ws_server, &mut session, &text, conn_id, &mut name);
+                        #[cfg(feature = "tracing")]
+                        let process = tracing::Instrument::instrument(
+                            process,
+                            tracing::info_span!(
+                                "ws.message",
+                                otel.name = "ws message",
+                                ws.conn_id = conn_id,
+                            ),
+                        );
+                        process.await;
                     }
 
                     AggregatedMessage::Binary(_bin) => {
-                        log::warn!("unexpected binary message");
+                        logging::warn!("unexpected binary message");
                     }
 
                     AggregatedMessage::Close(reason) => break reason,
@@ -103,7 +134,7 @@ async fn process_text_msg(
         // unwrap: we have guaranteed non-zero string length already
         match cmd_args.next().unwrap() {
             "/list" => {
-                log::info!("conn {conn}: listing rooms");
+                logging::info!("conn {conn}: listing rooms");
 
                 let rooms = chat_server.list_rooms().await;
 
@@ -114,7 +145,7 @@ async fn process_text_msg(
 
             "/join" => match cmd_args.next() {
                 Some(room) => {
-                    log::info!("conn {conn}: joining room {room}");
+                    logging::info!("conn {conn}: joining room {room}");
 
                     chat_server.join_room(conn, room).await;
 
@@ -128,7 +159,7 @@ async fn process_text_msg(
 
             "/name" => match cmd_args.next() {
                 Some(new_name) => {
-                    log::info!("conn {conn}: setting name to: {new_name}");
+                    logging::info!("conn {conn}: setting name to: {new_name}");
                     name.replace(new_name.to_owned());
                 }
                 None => {
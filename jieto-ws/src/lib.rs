@@ -2,6 +2,14 @@ pub mod handler;
 mod model;
 mod server;
 
+/// Session logs go through `tracing` with the `tracing` feature so they land in the session span.
+mod logging {
+    #[cfg(not(feature = "tracing"))]
+    pub(crate) use log::{debug, info, warn};
+    #[cfg(feature = "tracing")]
+    pub(crate) use tracing::{debug, info, warn};
+}
+
 pub use crate::server::{WsServer, WsServerHandle};
 pub use actix_ws::handle as actix_ws_handle;
-pub use model::ConnId;
+pub use model::{ConnId, WsStats};
@@ -6,3 +6,16 @@ pub type RoomId = String;
 
 /// Message sent to a room/client.
 pub type Msg = String;
+
+/// Snapshot of rooms and connections, see [`WsServerHandle::stats`](crate::WsServerHandle::stats).
+#[derive(Debug, Clone, Default)]
+pub struct WsStats {
+    /// Currently connected sessions.
+    pub connections: usize,
+
+    /// Total number of historical connections established.
+    pub visitors: usize,
+
+    /// Room names with their participant counts.
+    pub rooms: Vec<(RoomId, usize)>,
+}
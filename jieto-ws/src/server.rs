@@ -10,7 +10,8 @@ use std::{
 use rand::Rng as _;
 use tokio::sync::{mpsc, oneshot};
 
-use crate::model::{ConnId, Msg, RoomId};
+use crate::logging;
+use crate::model::{ConnId, Msg, RoomId, WsStats};
 
 /// A command received by the [`ChatServer`].
 #[derive(Debug)]
@@ -28,6 +29,10 @@ enum Command {
         res_tx: oneshot::Sender<Vec<RoomId>>,
     },
 
+    Stats {
+        res_tx: oneshot::Sender<WsStats>,
+    },
+
     Join {
         conn: ConnId,
         room: RoomId,
@@ -116,7 +121,7 @@ impl WsServer {
 
     /// Register new session and assign unique ID to this session
     async fn connect(&mut self, tx: mpsc::UnboundedSender<Msg>) -> ConnId {
-        log::info!("Someone joined");
+        logging::info!("Someone joined");
 
         // notify all users in same room
         self.send_system_message("main", 0, "Someone joined").await;
@@ -164,6 +169,22 @@ impl WsServer {
         self.rooms.keys().cloned().collect()
     }
 
+    /// Returns connection and per-room participant counts.
+    fn stats(&self) -> WsStats {
+        let mut rooms: Vec<(RoomId, usize)> = self
+            .rooms
+            .iter()
+            .map(|(name, sessions)| (name.clone(), sessions.len()))
+            .collect();
+        rooms.sort();
+
+        WsStats {
+            connections: self.sessions.len(),
+            visitors: self.visitor_count.load(Ordering::SeqCst),
+            rooms,
+        }
+    }
+
     /// Join room, send disconnect message to old room send join message to new room.
     async fn join_room(&mut self, conn_id: ConnId, room: RoomId) {
         let mut rooms = Vec::new();
@@ -202,6 +223,10 @@ impl WsServer {
                     let _ = res_tx.send(self.list_rooms());
                 }
 
+                Command::Stats { res_tx } => {
+                    let _ = res_tx.send(self.stats());
+                }
+
                 Command::Join { conn, room, res_tx } => {
                     self.join_room(conn, room).await;
                     let _ = res_tx.send(());
@@ -251,6 +276,17 @@ impl WsServerHandle {
         res_rx.await.unwrap()
     }
 
+    /// Snapshot of rooms and connection counts.
+    pub async fn stats(&self) -> WsStats {
+        let (res_tx, res_rx) = oneshot::channel();
+
+        // unwrap: chat server should not have been dropped
+        self.cmd_tx.send(Command::Stats { res_tx }).unwrap();
+
+        // unwrap: chat server does not drop our response channel
+        res_rx.await.unwrap()
+    }
+
     /// Join `room`, creating it if it does not exist.
     pub async fn join_room(&self, conn: ConnId, room: impl Into<RoomId>) {
         let (res_tx, res_rx) = oneshot::channel();